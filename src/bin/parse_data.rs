@@ -14,6 +14,7 @@ struct EvalEntry {
     pvs: Vec<Pv>,
 }
 
+#[allow(dead_code)]
 #[derive(Deserialize)]
 struct Pv {
     cp:   Option<i32>,
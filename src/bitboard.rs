@@ -19,6 +19,7 @@ impl Bitboard {
     pub const BLACK_QUEENS: Bitboard = Bitboard(0x0800_0000_0000_0000);
     pub const WHITE_KING: Bitboard = Bitboard(0x0000_0000_0000_0010);
     pub const BLACK_KING: Bitboard = Bitboard(0x1000_0000_0000_0000);
+    pub const BACK_RANKS: Bitboard = Bitboard(0xFF00_0000_0000_00FF);
 
     pub const WHITE_OCCUPANCY: Bitboard = Bitboard(
         Bitboard::WHITE_PAWNS.0
@@ -40,6 +41,9 @@ impl Bitboard {
 
     pub const ALL_PIECES: Bitboard = Bitboard(Bitboard::BLACK_OCCUPANCY.0 | Bitboard::WHITE_OCCUPANCY.0);
 
+    pub const LIGHT_SQUARES: Bitboard = Bitboard(0x55AA_55AA_55AA_55AA);
+    pub const DARK_SQUARES: Bitboard = Bitboard(0xAA55_AA55_AA55_AA55);
+
     const FILE_A: u64 = 0x0101_0101_0101_0101;
     const FILE_H: u64 = 0x8080_8080_8080_8080;
 
@@ -0,0 +1,306 @@
+use crate::{
+    Bitboard, CastlingRights, ChessBoard, ChessPiece, ChessPosition, ChessSquare, Color, FenError, MoveList, PieceType, Variant, fen::parse_castling,
+};
+
+// Sets up a position piece by piece, or edits one, and checks it like a FEN plus that the side
+// that just moved isn't in check before handing it out with its hashes and pseudolegal moves in
+// place. Defaults are an empty standard board with white to move, no castling rights or en
+// passant square and the clocks at 0 and 1.
+#[derive(Debug, Clone)]
+pub struct PositionBuilder {
+    chessboard: ChessBoard,
+    side_to_move: Color,
+    castling_rights: CastlingRights,
+    en_passant: Option<ChessSquare>,
+    halfmove_clock: u32,
+    fullmove_counter: u32,
+    chess960: Option<bool>, // None works it out from the castling rights, and moving pieces or rights resets it
+    variant: Variant,
+    checks: [u8; 2],
+    pockets: [[u8; 5]; 2],
+    promoted: Bitboard,
+}
+
+impl Default for PositionBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PositionBuilder {
+    pub fn new() -> Self {
+        Self::from_board(ChessBoard::empty())
+    }
+
+    pub fn from_board(chessboard: ChessBoard) -> Self {
+        Self {
+            chessboard,
+            side_to_move: Color::White,
+            castling_rights: CastlingRights::empty(),
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_counter: 1,
+            chess960: None,
+            variant: Variant::Standard,
+            checks: [0, 0],
+            pockets: [[0; 5]; 2],
+            promoted: Bitboard::EMPTY,
+        }
+    }
+
+    // everything of an existing position, to edit it
+    pub fn from_position(position: &ChessPosition) -> Self {
+        Self {
+            chessboard: position.chessboard,
+            side_to_move: position.side_to_move,
+            castling_rights: position.castling_rights,
+            en_passant: position.en_passant,
+            halfmove_clock: position.halfmove_clock,
+            fullmove_counter: position.fullmove_counter,
+            chess960: Some(position.chess960),
+            variant: position.variant,
+            checks: position.checks,
+            pockets: position.pockets,
+            promoted: position.promoted,
+        }
+    }
+
+    // replaces whatever stood on the square
+    pub fn piece(self, piece: ChessPiece, square: ChessSquare) -> Self {
+        let mut builder = self.remove(square);
+        builder.chessboard.add_piece(piece, square);
+        builder.chess960 = None;
+        builder
+    }
+
+    pub fn remove(mut self, square: ChessSquare) -> Self {
+        if let Some(piece) = self.chessboard.get_piece_at(square) {
+            self.chessboard.remove_piece(piece, square);
+            self.chess960 = None;
+        }
+        self
+    }
+
+    pub fn get(&self, square: ChessSquare) -> Option<ChessPiece> {
+        self.chessboard.get_piece_at(square)
+    }
+
+    pub fn side_to_move(mut self, color: Color) -> Self {
+        self.side_to_move = color;
+        self
+    }
+
+    pub fn castling_rights(mut self, rights: CastlingRights) -> Self {
+        self.castling_rights = rights;
+        self.chess960 = None;
+        self
+    }
+
+    // the castling field of a FEN, KQkq or rook files, read against the pieces placed so far
+    pub fn castling(self, field: &str) -> Result<Self, FenError> {
+        let rights = parse_castling(field, 0, &self.chessboard)?;
+        Ok(self.castling_rights(rights))
+    }
+
+    pub fn en_passant(mut self, square: Option<ChessSquare>) -> Self {
+        self.en_passant = square;
+        self
+    }
+
+    pub fn halfmove_clock(mut self, halfmove_clock: u32) -> Self {
+        self.halfmove_clock = halfmove_clock;
+        self
+    }
+
+    pub fn fullmove_counter(mut self, fullmove_counter: u32) -> Self {
+        self.fullmove_counter = fullmove_counter;
+        self
+    }
+
+    // call after the pieces and castling rights are in place, editing those works it out again
+    pub fn chess960(mut self, chess960: bool) -> Self {
+        self.chess960 = Some(chess960);
+        self
+    }
+
+    pub fn variant(mut self, variant: Variant) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    // the checks white and black have given, only counted in Three-check
+    pub fn checks(mut self, checks: [u8; 2]) -> Self {
+        self.checks = checks;
+        self
+    }
+
+    // the pieces white and black can drop in Crazyhouse, counted pawn to queen
+    pub fn pockets(mut self, pockets: [[u8; 5]; 2]) -> Self {
+        self.pockets = pockets;
+        self
+    }
+
+    // pieces that came from a promotion and go back to a pocket as pawns when captured
+    pub fn promoted(mut self, promoted: Bitboard) -> Self {
+        self.promoted = promoted;
+        self
+    }
+
+    pub fn build(&self) -> Result<ChessPosition, FenError> {
+        let position = self.build_allowing_check()?;
+        let them = self.side_to_move.opposite();
+        if self.variant.has_royal_king(them)
+            && let Some(their_king) = position.chessboard.get_piece_bitboard(them, PieceType::King).lsb_square()
+            && position.chessboard.is_square_attacked(their_king, self.side_to_move)
+        {
+            return Err(FenError::OpponentInCheck { color: them });
+        }
+        Ok(position)
+    }
+
+    // FENs and packed positions may leave the side that just moved in check, king capture games
+    // get there and a king left attacked is simply taken
+    pub(crate) fn build_allowing_check(&self) -> Result<ChessPosition, FenError> {
+        let board = &self.chessboard;
+        validate_material(board, self.variant)?;
+
+        // a Crazyhouse game keeps the 32 pieces it started with, between the board and the pockets
+        let (promoted, pockets) = match self.variant {
+            Variant::Crazyhouse => (self.promoted & board.all_pieces, self.pockets),
+            _ => (Bitboard::EMPTY, [[0; 5]; 2]),
+        };
+        let count = board.all_pieces.count() + pockets.iter().flatten().map(|&count| count as u32).sum::<u32>();
+        if self.variant == Variant::Crazyhouse && count > 32 {
+            return Err(FenError::TooManyPocketPieces { count });
+        }
+
+        for right in
+            [CastlingRights::WHITE_KINGSIDE, CastlingRights::WHITE_QUEENSIDE, CastlingRights::BLACK_KINGSIDE, CastlingRights::BLACK_QUEENSIDE]
+        {
+            if !self.castling_rights.has(right) {
+                continue;
+            }
+            let rook_sq = self.castling_rights.rook_square(right);
+            let color = if rook_sq.rank() == 0 { Color::White } else { Color::Black };
+            let Some(king_sq) = board.get_piece_bitboard(color, PieceType::King).lsb_square() else {
+                return Err(FenError::CastlingRightWithoutPieces { square: rook_sq });
+            };
+            let kingside = matches!(right, CastlingRights::WHITE_KINGSIDE | CastlingRights::BLACK_KINGSIDE);
+            let rook_beside_king = king_sq.rank() == rook_sq.rank() && (rook_sq.file() > king_sq.file()) == kingside;
+            if !board.get_piece_bitboard(color, PieceType::Rook).is_set(rook_sq) || !rook_beside_king {
+                return Err(FenError::CastlingRightWithoutPieces { square: rook_sq });
+            }
+        }
+
+        if let Some(square) = self.en_passant
+            && !plausible_en_passant(board, self.side_to_move, square)
+        {
+            return Err(FenError::EnPassantWithoutPawn { square });
+        }
+
+        // castling with a king off the e file or a rook off the a/h files only exists in Chess960
+        let chess960 = self.chess960.unwrap_or_else(|| {
+            let king_off_e = [Color::White, Color::Black].into_iter().any(|color| {
+                let (kingside, queenside) = CastlingRights::for_color(color);
+                let king_file = board.get_piece_bitboard(color, PieceType::King).lsb_square().map(|sq| sq.file());
+                self.castling_rights.has(kingside | queenside) && king_file != Some(4)
+            });
+            king_off_e || !self.castling_rights.is_standard()
+        });
+
+        let mut position = ChessPosition {
+            chessboard: *board,
+            side_to_move: self.side_to_move,
+            castling_rights: self.castling_rights,
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+            fullmove_counter: self.fullmove_counter,
+            zobrist_hash: 0,
+            pawn_hash: 0,
+            material_hash: 0,
+            chess960,
+            variant: self.variant,
+            checks: self.checks,
+            pockets,
+            promoted,
+            pseudolegal_moves: MoveList::new(),
+        };
+
+        position.refresh_hashes();
+        position.generate_pseudolegal();
+
+        Ok(position)
+    }
+}
+
+const RANK_8: Bitboard = Bitboard(0xFF00000000000000);
+
+// Antichess allows any number of kings and Horde none for white, whose up to 36 pawns may also
+// stand on the first rank. Crazyhouse drops captured pieces back in, the board alone says little
+// about how many of a kind there can be.
+pub(crate) fn validate_material(board: &ChessBoard, variant: Variant) -> Result<(), FenError> {
+    for color in [Color::White, Color::Black] {
+        let count = |piece_type: PieceType| board.get_piece_bitboard(color, piece_type).count();
+
+        let kings = count(PieceType::King);
+        let royal_kings = if variant.has_royal_king(color) { 1 } else { 0 };
+        if kings != royal_kings && variant != Variant::Antichess {
+            return Err(FenError::KingCount { color, count: kings });
+        }
+
+        if variant == Variant::Horde && color == Color::White {
+            let total = board.white_occupancy.count();
+            if total > 36 {
+                return Err(FenError::TooManyPieces { color, count: total });
+            }
+            if let Some(square) = (board.get_piece_bitboard(color, PieceType::Pawn) & RANK_8).lsb_square() {
+                return Err(FenError::PawnOnBackRank { square });
+            }
+            continue;
+        }
+
+        if variant == Variant::Crazyhouse {
+            if let Some(square) = (board.get_piece_bitboard(color, PieceType::Pawn) & Bitboard::BACK_RANKS).lsb_square() {
+                return Err(FenError::PawnOnBackRank { square });
+            }
+            continue;
+        }
+
+        let pawns = count(PieceType::Pawn);
+        if pawns > 8 {
+            return Err(FenError::TooManyPawns { color, count: pawns });
+        }
+
+        // anything beyond the starting set must have come from a promotion
+        let promoted = count(PieceType::Knight).saturating_sub(2)
+            + count(PieceType::Bishop).saturating_sub(2)
+            + count(PieceType::Rook).saturating_sub(2)
+            + count(PieceType::Queen).saturating_sub(1)
+            + count(PieceType::King).saturating_sub(1);
+        let total = match color {
+            Color::White => board.white_occupancy.count(),
+            Color::Black => board.black_occupancy.count(),
+        };
+        if total > 16 || pawns + promoted > 8 {
+            return Err(FenError::TooManyPieces { color, count: total });
+        }
+
+        if let Some(square) = (board.get_piece_bitboard(color, PieceType::Pawn) & Bitboard::BACK_RANKS).lsb_square() {
+            return Err(FenError::PawnOnBackRank { square });
+        }
+    }
+    Ok(())
+}
+
+// the pawn that just moved two squares sits in front of the target, which it passed over from an empty square
+pub(crate) fn plausible_en_passant(board: &ChessBoard, side_to_move: Color, square: ChessSquare) -> bool {
+    let (ep_rank, pusher, pushed_sq, origin_sq) = match side_to_move {
+        Color::White => (5, Color::Black, square.square_south(), square.square_north()),
+        Color::Black => (2, Color::White, square.square_north(), square.square_south()),
+    };
+
+    square.rank() == ep_rank
+        && !board.all_pieces.is_set(square)
+        && pushed_sq.is_some_and(|sq| board.get_piece_bitboard(pusher, PieceType::Pawn).is_set(sq))
+        && origin_sq.is_some_and(|sq| !board.all_pieces.is_set(sq))
+}
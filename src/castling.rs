@@ -1,18 +1,82 @@
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
-pub struct CastlingRights(pub u8);
+use crate::{ChessBoard, ChessSquare, Color, PieceType};
+
+// Each right remembers the file of the rook it castles with, which is only ever different
+// from the a/h files in Chess960. Files of rights that are not held stay at their defaults.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CastlingRights {
+    pub bits: u8,
+    pub rook_files: [u8; 4],
+}
+
+impl Default for CastlingRights {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
 
 impl CastlingRights {
-    pub const WHITE_KINGSIDE: CastlingRights = CastlingRights(0b0001);
-    pub const WHITE_QUEENSIDE: CastlingRights = CastlingRights(0b0010);
-    pub const BLACK_KINGSIDE: CastlingRights = CastlingRights(0b0100);
-    pub const BLACK_QUEENSIDE: CastlingRights = CastlingRights(0b1000);
+    pub const WHITE_KINGSIDE: CastlingRights = CastlingRights { bits: 0b0001, rook_files: Self::DEFAULT_FILES };
+    pub const WHITE_QUEENSIDE: CastlingRights = CastlingRights { bits: 0b0010, rook_files: Self::DEFAULT_FILES };
+    pub const BLACK_KINGSIDE: CastlingRights = CastlingRights { bits: 0b0100, rook_files: Self::DEFAULT_FILES };
+    pub const BLACK_QUEENSIDE: CastlingRights = CastlingRights { bits: 0b1000, rook_files: Self::DEFAULT_FILES };
+
+    pub const DEFAULT_FILES: [u8; 4] = [7, 0, 7, 0];
 
     pub fn new() -> Self {
-        Self(0b1111)
+        Self { bits: 0b1111, rook_files: Self::DEFAULT_FILES }
     }
 
     pub fn empty() -> Self {
-        Self(0b0000)
+        Self { bits: 0b0000, rook_files: Self::DEFAULT_FILES }
+    }
+
+    // the kingside and queenside right of a color
+    pub fn for_color(color: Color) -> (CastlingRights, CastlingRights) {
+        match color {
+            Color::White => (Self::WHITE_KINGSIDE, Self::WHITE_QUEENSIDE),
+            Color::Black => (Self::BLACK_KINGSIDE, Self::BLACK_QUEENSIDE),
+        }
+    }
+
+    // a single right with its rook on the given file
+    pub fn with_rook_file(right: CastlingRights, file: u8) -> Self {
+        let mut rook_files = Self::DEFAULT_FILES;
+        rook_files[right.index()] = file;
+        Self { bits: right.bits, rook_files }
+    }
+
+    fn index(&self) -> usize {
+        debug_assert_eq!(self.bits.count_ones(), 1, "expected a single castling right");
+        self.bits.trailing_zeros() as usize
+    }
+
+    fn color(&self) -> Color {
+        if self.bits & 0b0011 != 0 { Color::White } else { Color::Black }
+    }
+
+    pub fn rook_file(&self, right: CastlingRights) -> u8 {
+        self.rook_files[right.index()]
+    }
+
+    pub fn rook_square(&self, right: CastlingRights) -> ChessSquare {
+        let rank = if right.color() == Color::White { 0 } else { 7 };
+        ChessSquare::from_coords(self.rook_file(right), rank).unwrap()
+    }
+
+    // the rights that are lost when a piece moves from or to sq
+    pub fn touching(&self, sq: ChessSquare) -> CastlingRights {
+        let mut rights = CastlingRights::empty();
+        for right in [Self::WHITE_KINGSIDE, Self::WHITE_QUEENSIDE, Self::BLACK_KINGSIDE, Self::BLACK_QUEENSIDE] {
+            if self.has(right) && self.rook_square(right) == sq {
+                rights |= right;
+            }
+        }
+        rights
+    }
+
+    // true when every held right uses an a or h file rook
+    pub fn is_standard(&self) -> bool {
+        (0..4).all(|i| self.bits >> i & 1 == 0 || self.rook_files[i] == Self::DEFAULT_FILES[i])
     }
 
     pub fn from_fen(fen_part: &str) -> Self {
@@ -49,65 +113,78 @@ impl CastlingRights {
         if s.is_empty() { "-".to_string() } else { s }
     }
 
+    // X-FEN: KQkq when the right belongs to the outermost rook on that wing, the rook file otherwise
+    pub fn to_xfen(&self, board: &ChessBoard) -> String {
+        self.letters(|right, file| {
+            let color = right.color();
+            let rank = if color == Color::White { 0 } else { 7 };
+            let rooks = board.get_piece_bitboard(color, PieceType::Rook);
+            let kingside = right.bits & 0b0101 != 0;
+            let mut beyond = if kingside { file + 1..8 } else { 0..file };
+            let outermost = beyond.all(|f| !rooks.is_set(ChessSquare::from_coords(f, rank).unwrap()));
+            let letter = match (outermost, kingside) {
+                (true, true) => 'k',
+                (true, false) => 'q',
+                (false, _) => (b'a' + file) as char,
+            };
+            if color == Color::White { letter.to_ascii_uppercase() } else { letter }
+        })
+    }
+
+    // Shredder-FEN: always the rook files, HAha for the standard start
+    pub fn to_shredder_fen(&self) -> String {
+        self.letters(|right, file| {
+            let letter = (b'a' + file) as char;
+            if right.color() == Color::White { letter.to_ascii_uppercase() } else { letter }
+        })
+    }
+
+    fn letters(&self, letter: impl Fn(CastlingRights, u8) -> char) -> String {
+        let s: String = [Self::WHITE_KINGSIDE, Self::WHITE_QUEENSIDE, Self::BLACK_KINGSIDE, Self::BLACK_QUEENSIDE]
+            .into_iter()
+            .filter(|right| self.has(*right))
+            .map(|right| letter(right, self.rook_file(right)))
+            .collect();
+        if s.is_empty() { "-".to_string() } else { s }
+    }
+
     pub fn flip_perspective(&self) -> CastlingRights {
-        let val = self.0;
+        let val = self.bits;
         let flipped = ((val & 0b0011) << 2) | ((val & 0b1100) >> 2);
-        CastlingRights(flipped)
+        let [wk, wq, bk, bq] = self.rook_files;
+        CastlingRights { bits: flipped, rook_files: [bk, bq, wk, wq] }
     }
 
     pub fn has(&self, right: CastlingRights) -> bool {
-        (self.0 & right.0) != 0
+        (self.bits & right.bits) != 0
     }
 
     pub fn remove(&mut self, rights_to_remove: CastlingRights) {
-        *self &= !rights_to_remove;
+        for i in 0..4 {
+            if rights_to_remove.bits >> i & 1 == 1 {
+                self.rook_files[i] = Self::DEFAULT_FILES[i];
+            }
+        }
+        self.bits &= !rights_to_remove.bits;
     }
 }
 
-// Implement bitwise operations
+// the union of two sets of rights, each right keeps the rook file of the side that holds it
 impl std::ops::BitOr for CastlingRights {
     type Output = Self;
-    fn bitor(self, rhs: Self) -> Self::Output {
-        Self(self.0 | rhs.0)
-    }
-}
-
-impl std::ops::BitOr<u8> for CastlingRights {
-    type Output = Self;
-    fn bitor(self, rhs: u8) -> Self::Output {
-        Self(self.0 | rhs)
-    }
-}
-
-impl std::ops::BitOr<CastlingRights> for u8 {
-    type Output = CastlingRights;
-    fn bitor(self, rhs: CastlingRights) -> Self::Output {
-        CastlingRights(self | rhs.0)
+    fn bitor(mut self, rhs: Self) -> Self::Output {
+        self |= rhs;
+        self
     }
 }
 
 impl std::ops::BitOrAssign for CastlingRights {
     fn bitor_assign(&mut self, rhs: Self) {
-        self.0 |= rhs.0;
-    }
-}
-
-impl std::ops::BitAndAssign for CastlingRights {
-    fn bitand_assign(&mut self, rhs: Self) {
-        self.0 &= rhs.0;
-    }
-}
-
-impl std::ops::BitXor for CastlingRights {
-    type Output = Self;
-    fn bitxor(self, rhs: Self) -> Self::Output {
-        Self(self.0 ^ rhs.0)
-    }
-}
-
-impl std::ops::Not for CastlingRights {
-    type Output = Self;
-    fn not(self) -> Self::Output {
-        Self(!self.0)
+        for i in 0..4 {
+            if rhs.bits >> i & 1 == 1 {
+                self.rook_files[i] = rhs.rook_files[i];
+            }
+        }
+        self.bits |= rhs.bits;
     }
 }
@@ -1,4 +1,6 @@
-use super::{Bitboard, ChessMove, ChessPiece, ChessSquare, Color, PieceType};
+use arrayvec::ArrayVec;
+
+use super::{Bitboard, ChessMove, ChessPiece, ChessSquare, Color, MoveKind, PackedMove, PieceType, SliderAttacks};
 
 #[derive(Debug, Clone, Copy, Default)]
 #[repr(align(64))]
@@ -9,6 +11,21 @@ pub struct ChessBoard {
     pub all_pieces: Bitboard,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Castling {
+    pub king_from: ChessSquare,
+    pub king_to:   ChessSquare,
+    pub rook_from: ChessSquare,
+    pub rook_to:   ChessSquare,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pin {
+    pub pinned: ChessSquare,
+    pub pinner: ChessSquare,
+    pub ray:    Bitboard,
+}
+
 const fn piece_type_to_index(pt: PieceType) -> usize {
     pt as usize
 }
@@ -160,9 +177,6 @@ impl ChessBoard {
 
     pub fn is_square_attacked(&self, sq: ChessSquare, attacker_color: Color) -> bool {
         let enemy_pieces = &self.pieces[attacker_color as usize];
-        let all_pieces = self.all_pieces;
-        let bishop_attacks = ChessBoard::BISHOP_ATTACKS_ALL;
-        let rook_attacks = ChessBoard::ROOK_ATTACKS_ALL;
 
         let incoming_pawn_mask = match attacker_color {
             Color::White => ChessBoard::PAWN_ATTACKS_BLACK[sq.0 as usize],
@@ -181,26 +195,141 @@ impl ChessBoard {
             return true;
         }
 
-        let mut diagonal_attackers =
-            (enemy_pieces[PieceType::Bishop as usize] | enemy_pieces[PieceType::Queen as usize]) & bishop_attacks[sq.0 as usize];
+        let queens = enemy_pieces[PieceType::Queen as usize];
 
-        while let Some(attacker_sq) = diagonal_attackers.pop_lsb() {
-            let path = ChessBoard::BETWEEN[sq.0 as usize][attacker_sq.0 as usize].unwrap();
-            if (path & all_pieces).is_empty() {
-                return true;
-            }
+        let diagonal_attackers = enemy_pieces[PieceType::Bishop as usize] | queens;
+        if !(ChessBoard::bishop_attacks(sq, self.all_pieces) & diagonal_attackers).is_empty() {
+            return true;
+        }
+
+        let straight_attackers = enemy_pieces[PieceType::Rook as usize] | queens;
+        if !(ChessBoard::rook_attacks(sq, self.all_pieces) & straight_attackers).is_empty() {
+            return true;
+        }
+
+        false
+    }
+
+    // every piece of attacker_color attacking sq, sliders see through anything missing from occupancy
+    pub fn attackers_to(&self, sq: ChessSquare, attacker_color: Color, occupancy: Bitboard) -> Bitboard {
+        let enemy_pieces = &self.pieces[attacker_color as usize];
+        let queens = enemy_pieces[PieceType::Queen as usize];
+
+        let incoming_pawn_mask = match attacker_color {
+            Color::White => ChessBoard::PAWN_ATTACKS_BLACK[sq.0 as usize],
+            Color::Black => ChessBoard::PAWN_ATTACKS_WHITE[sq.0 as usize],
+        };
+
+        (incoming_pawn_mask & enemy_pieces[PieceType::Pawn as usize])
+            | (ChessBoard::KNIGHT_ATTACKS[sq.0 as usize] & enemy_pieces[PieceType::Knight as usize])
+            | (ChessBoard::KING_ATTACKS[sq.0 as usize] & enemy_pieces[PieceType::King as usize])
+            | (ChessBoard::bishop_attacks(sq, occupancy) & (enemy_pieces[PieceType::Bishop as usize] | queens))
+            | (ChessBoard::rook_attacks(sq, occupancy) & (enemy_pieces[PieceType::Rook as usize] | queens))
+    }
+
+    // attackers_to with the board's own occupancy
+    pub fn attackers(&self, sq: ChessSquare, attacker_color: Color) -> Bitboard {
+        self.attackers_to(sq, attacker_color, self.all_pieces)
+    }
+
+    // the pieces giving check to the king of color, empty when it has no king
+    pub fn checkers(&self, color: Color) -> Bitboard {
+        match self.get_piece_bitboard(color, PieceType::King).lsb_square() {
+            Some(king_sq) => self.attackers(king_sq, color.opposite()),
+            None => Bitboard::EMPTY,
         }
+    }
+
+    // Sliders of attacker_color that would attack sq once the first piece in their way, of either
+    // colour, is gone. Direct attackers are not included.
+    pub fn xray_attackers(&self, sq: ChessSquare, attacker_color: Color, occupancy: Bitboard) -> Bitboard {
+        let queens = self.get_piece_bitboard(attacker_color, PieceType::Queen);
+        let diagonal = self.get_piece_bitboard(attacker_color, PieceType::Bishop) | queens;
+        let straight = self.get_piece_bitboard(attacker_color, PieceType::Rook) | queens;
+
+        let bishop_direct = ChessBoard::bishop_attacks(sq, occupancy);
+        let rook_direct = ChessBoard::rook_attacks(sq, occupancy);
+        let bishop_xray = ChessBoard::bishop_attacks(sq, occupancy & !(bishop_direct & occupancy)) & !bishop_direct;
+        let rook_xray = ChessBoard::rook_attacks(sq, occupancy & !(rook_direct & occupancy)) & !rook_direct;
+
+        ((bishop_xray & diagonal) | (rook_xray & straight)) & occupancy
+    }
 
-        let mut straight_attackers = (enemy_pieces[PieceType::Rook as usize] | enemy_pieces[PieceType::Queen as usize]) & rook_attacks[sq.0 as usize];
+    // Absolute pins on the king of color. Each ray runs from next to the king up to and
+    // including the pinner, so a pinned piece may only move within it.
+    pub fn pins(&self, color: Color) -> ArrayVec<Pin, 8> {
+        let mut pins = ArrayVec::new();
+        let Some(king_sq) = self.get_piece_bitboard(color, PieceType::King).lsb_square() else {
+            return pins;
+        };
+        let them = color.opposite();
+        let (allies, opps) = match color {
+            Color::White => (self.white_occupancy, self.black_occupancy),
+            Color::Black => (self.black_occupancy, self.white_occupancy),
+        };
 
-        while let Some(attacker_sq) = straight_attackers.pop_lsb() {
-            let path = ChessBoard::BETWEEN[sq.0 as usize][attacker_sq.0 as usize].unwrap();
-            if (path & all_pieces).is_empty() {
-                return true;
+        let queens = self.get_piece_bitboard(them, PieceType::Queen);
+        let diagonal = self.get_piece_bitboard(them, PieceType::Bishop) | queens;
+        let straight = self.get_piece_bitboard(them, PieceType::Rook) | queens;
+
+        // sliders that would see the king through our own pieces
+        let mut snipers = (ChessBoard::rook_attacks(king_sq, opps) & straight) | (ChessBoard::bishop_attacks(king_sq, opps) & diagonal);
+        while let Some(pinner) = snipers.pop_lsb() {
+            let between = ChessBoard::BETWEEN[king_sq.0 as usize][pinner.0 as usize].unwrap_or_default();
+            let blockers = between & self.all_pieces;
+            if blockers.count() == 1
+                && let Some(pinned) = (blockers & allies).lsb_square()
+            {
+                pins.push(Pin { pinned, pinner, ray: between | pinner.bitboard() });
             }
         }
+        pins
+    }
 
-        false
+    pub fn pinned(&self, color: Color) -> Bitboard {
+        self.pins(color).iter().fold(Bitboard::EMPTY, |pinned, pin| pinned | pin.pinned.bitboard())
+    }
+
+    // the squares the piece on sq attacks, empty squares attack nothing
+    pub fn attacks_from(&self, sq: ChessSquare, occupancy: Bitboard) -> Bitboard {
+        let Some(piece) = self.get_piece_at(sq) else {
+            return Bitboard::EMPTY;
+        };
+        match (piece.piece_type, piece.color) {
+            (PieceType::Pawn, Color::White) => ChessBoard::PAWN_ATTACKS_WHITE[sq.0 as usize],
+            (PieceType::Pawn, Color::Black) => ChessBoard::PAWN_ATTACKS_BLACK[sq.0 as usize],
+            (PieceType::Knight, _) => ChessBoard::KNIGHT_ATTACKS[sq.0 as usize],
+            (PieceType::Bishop, _) => ChessBoard::bishop_attacks(sq, occupancy),
+            (PieceType::Rook, _) => ChessBoard::rook_attacks(sq, occupancy),
+            (PieceType::Queen, _) => ChessBoard::queen_attacks(sq, occupancy),
+            (PieceType::King, _) => ChessBoard::KING_ATTACKS[sq.0 as usize],
+        }
+    }
+
+    // every square attacked by at least one piece of color
+    pub fn attack_map(&self, color: Color) -> Bitboard {
+        let mut map = Bitboard::EMPTY;
+        let mut pieces = match color {
+            Color::White => self.white_occupancy,
+            Color::Black => self.black_occupancy,
+        };
+        while let Some(sq) = pieces.pop_lsb() {
+            map |= self.attacks_from(sq, self.all_pieces);
+        }
+        map
+    }
+
+    pub fn bishop_attacks(sq: ChessSquare, occupancy: Bitboard) -> Bitboard {
+        SliderAttacks::get().bishop(sq, occupancy)
+    }
+
+    pub fn rook_attacks(sq: ChessSquare, occupancy: Bitboard) -> Bitboard {
+        SliderAttacks::get().rook(sq, occupancy)
+    }
+
+    pub fn queen_attacks(sq: ChessSquare, occupancy: Bitboard) -> Bitboard {
+        let sliders = SliderAttacks::get();
+        sliders.bishop(sq, occupancy) | sliders.rook(sq, occupancy)
     }
 
     pub const fn generate_rook_direction_masks() -> [[Bitboard; 4]; 64] {
@@ -391,41 +520,130 @@ impl ChessBoard {
         ChessBoard { pieces, ..Default::default() }
     }
 
-    pub fn apply_move(&mut self, mov: &ChessMove, side_to_move: Color, en_passant_sq: Option<ChessSquare>) {
-        let moving_piece = self.get_piece_at(mov.from).expect("No piece selected");
-        let is_en_passant = moving_piece.piece_type == PieceType::Pawn && en_passant_sq.is_some_and(|sq| sq == mov.to);
+    pub fn apply_move(&mut self, mov: PackedMove, side_to_move: Color) {
+        if mov.is_castling() {
+            let castling = self.castling_of(mov, side_to_move);
+            self.apply_castling(&castling, side_to_move);
+            return;
+        }
+        if let Some(piece_type) = mov.dropped() {
+            self.add_piece(ChessPiece::new(side_to_move, piece_type), mov.to());
+            return;
+        }
 
-        if is_en_passant {
-            let cap_sq = if side_to_move == Color::White {
-                ChessSquare(mov.to.0 - 8)
-            } else {
-                ChessSquare(mov.to.0 + 8)
-            };
-            let captured_pawn = ChessPiece { color: side_to_move.opposite(), piece_type: PieceType::Pawn };
-            self.remove_piece(captured_pawn, cap_sq);
-        } else {
-            if let Some(cap_piece) = self.get_piece_at(mov.to) {
-                self.remove_piece(cap_piece, mov.to);
+        let moving_piece = self.get_piece_at(mov.from()).expect("No piece selected");
+        match mov.kind() {
+            MoveKind::EnPassant => {
+                let cap_sq = ChessSquare::from_coords(mov.to().file(), mov.from().rank()).unwrap();
+                self.remove_piece(ChessPiece::new(side_to_move.opposite(), PieceType::Pawn), cap_sq);
+            }
+            MoveKind::Capture | MoveKind::PromotionCapture(_) => {
+                let cap_piece = self.get_piece_at(mov.to()).expect("chessboard desync: nothing to capture");
+                self.remove_piece(cap_piece, mov.to());
             }
+            _ => {}
         }
 
-        self.move_piece(mov.from, mov.to, moving_piece);
+        self.move_piece(mov.from(), mov.to(), moving_piece);
 
-        if let Some(promo_type) = mov.promotion {
-            self.remove_piece(moving_piece, mov.to);
-            self.add_piece(ChessPiece::new(side_to_move, promo_type), mov.to);
+        if let Some(promo_type) = mov.promotion() {
+            self.remove_piece(moving_piece, mov.to());
+            self.add_piece(ChessPiece::new(side_to_move, promo_type), mov.to());
         }
+    }
 
-        if moving_piece.piece_type == PieceType::King && (mov.from.file() as i8 - mov.to.file() as i8).abs() == 2 {
-            let (rook_from, rook_to) = match (side_to_move, mov.to.file()) {
-                (Color::White, f) if f > mov.from.file() => (ChessSquare::H1, ChessSquare::F1),
-                (Color::White, _) => (ChessSquare::A1, ChessSquare::D1),
-                (Color::Black, f) if f > mov.from.file() => (ChessSquare::H8, ChessSquare::F8),
-                (Color::Black, _) => (ChessSquare::A8, ChessSquare::D8),
-            };
+    // inverse of apply_move, captured is whatever apply_move took off the board (the pawn for en passant)
+    // and castling is what castling_of returned for the move before it was applied
+    pub fn unapply_move(&mut self, mov: PackedMove, side_to_move: Color, captured: Option<ChessPiece>, castling: Option<Castling>) {
+        if let Some(castling) = castling {
+            let king = ChessPiece::new(side_to_move, PieceType::King);
             let rook = ChessPiece::new(side_to_move, PieceType::Rook);
-            self.move_piece(rook_from, rook_to, rook);
+            self.remove_piece(king, castling.king_to);
+            self.remove_piece(rook, castling.rook_to);
+            self.add_piece(king, castling.king_from);
+            self.add_piece(rook, castling.rook_from);
+            return;
+        }
+        if let Some(piece_type) = mov.dropped() {
+            self.remove_piece(ChessPiece::new(side_to_move, piece_type), mov.to());
+            return;
+        }
+
+        let placed_piece = self.get_piece_at(mov.to()).expect("chessboard desync: Piece missing on unmake");
+        let moving_piece = if mov.promotion().is_some() {
+            ChessPiece::new(side_to_move, PieceType::Pawn)
+        } else {
+            placed_piece
+        };
+
+        self.remove_piece(placed_piece, mov.to());
+        self.add_piece(moving_piece, mov.from());
+
+        if let Some(cap_piece) = captured {
+            let cap_sq = if mov.kind() == MoveKind::EnPassant {
+                ChessSquare::from_coords(mov.to().file(), mov.from().rank()).unwrap()
+            } else {
+                mov.to()
+            };
+            self.add_piece(cap_piece, cap_sq);
+        }
+    }
+
+    // The squares of a castling move with its side already known from the flag. Only the rook
+    // is left to find, the move lands on it in Chess960 and on the king's square otherwise.
+    pub fn castling_of(&self, mov: PackedMove, side_to_move: Color) -> Castling {
+        let rank = mov.from().rank();
+        let kingside = mov.kind() == MoveKind::KingsideCastle;
+        let rook_from = if self.get_piece_bitboard(side_to_move, PieceType::Rook).is_set(mov.to()) {
+            mov.to()
+        } else {
+            ChessSquare::from_coords(if kingside { 7 } else { 0 }, rank).unwrap()
+        };
+        let (king_file, rook_file) = if kingside { (6, 5) } else { (2, 3) };
+
+        Castling {
+            king_from: mov.from(),
+            king_to: ChessSquare::from_coords(king_file, rank).unwrap(),
+            rook_from,
+            rook_to: ChessSquare::from_coords(rook_file, rank).unwrap(),
+        }
+    }
+
+    // Castling is encoded as the king moving two files towards an a/h rook in standard chess, and
+    // as the king taking its own rook in Chess960 (where the king may move one file or none at all).
+    pub fn castling(&self, mov: &ChessMove) -> Option<Castling> {
+        let king = self.get_piece_at(mov.from).filter(|piece| piece.piece_type == PieceType::King)?;
+        let takes_own_rook = self.get_piece_at(mov.to) == Some(ChessPiece::new(king.color, PieceType::Rook));
+        let two_files = mov.from.rank() == mov.to.rank() && (mov.from.file() as i8 - mov.to.file() as i8).abs() == 2;
+        if !takes_own_rook && !two_files {
+            return None;
         }
+
+        let rank = mov.from.rank();
+        let kingside = mov.to.file() > mov.from.file();
+        let rook_from = match (takes_own_rook, kingside) {
+            (true, _) => mov.to,
+            (false, true) => ChessSquare::from_coords(7, rank).unwrap(),
+            (false, false) => ChessSquare::from_coords(0, rank).unwrap(),
+        };
+        let (king_file, rook_file) = if kingside { (6, 5) } else { (2, 3) };
+
+        Some(Castling {
+            king_from: mov.from,
+            king_to: ChessSquare::from_coords(king_file, rank).unwrap(),
+            rook_from,
+            rook_to: ChessSquare::from_coords(rook_file, rank).unwrap(),
+        })
+    }
+
+    // both pieces come off before either goes back on, in Chess960 their squares can overlap
+    fn apply_castling(&mut self, castling: &Castling, side_to_move: Color) {
+        let king = ChessPiece::new(side_to_move, PieceType::King);
+        let rook = ChessPiece::new(side_to_move, PieceType::Rook);
+        self.remove_piece(king, castling.king_from);
+        self.remove_piece(rook, castling.rook_from);
+        self.add_piece(king, castling.king_to);
+        self.add_piece(rook, castling.rook_to);
     }
 
     pub fn get_piece_at(&self, square: ChessSquare) -> Option<ChessPiece> {
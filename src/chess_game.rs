@@ -1,118 +1,142 @@
-use arrayvec::ArrayVec;
+use core::fmt;
 
-use super::{CastlingRights, ChessBoard, ChessMove, ChessPiece, ChessPosition, ChessSquare, Color, PieceType};
+use super::{
+    ChessMove, ChessPosition, ChessSquare, Color, DrawRules, FenError, HashHistory, PackedMove, PgnGame, PieceType, Ruleset, SanError, UndoInfo,
+    Variant, chess960_fen, parse_fen,
+};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Outcome {
     Unfinished,
-    Finished(Option<Color>),
+    Finished(Option<Color>, Termination),
 }
 
 impl Outcome {
     pub fn to_f32(&self) -> Option<[f32; 3]> {
         match self {
-            Outcome::Finished(Some(Color::White)) => Some([1.0, 0.0, 0.0]),
-            Outcome::Finished(None) => Some([0.0, 1.0, 0.0]),
-            Outcome::Finished(Some(Color::Black)) => Some([0.0, 0.0, 1.0]),
+            Outcome::Finished(Some(Color::White), _) => Some([1.0, 0.0, 0.0]),
+            Outcome::Finished(None, _) => Some([0.0, 1.0, 0.0]),
+            Outcome::Finished(Some(Color::Black), _) => Some([0.0, 0.0, 1.0]),
             _ => None,
         }
     }
 }
 
+// why a game ended, adjudication covers every result decided off the board
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Termination {
+    Checkmate,
+    Stalemate,
+    KingCaptured,
+    ThreefoldRepetition,
+    FivefoldRepetition,
+    FiftyMoves,
+    SeventyFiveMoves,
+    InsufficientMaterial,
+    Adjudication,
+    KingInCenter,
+    ThreeChecks,
+    OutOfPieces,
+}
+
+impl Termination {
+    // new terminations go at the end, packed games store the index
+    pub const ALL: [Termination; 12] = [
+        Termination::Checkmate,
+        Termination::Stalemate,
+        Termination::KingCaptured,
+        Termination::ThreefoldRepetition,
+        Termination::FivefoldRepetition,
+        Termination::FiftyMoves,
+        Termination::SeventyFiveMoves,
+        Termination::InsufficientMaterial,
+        Termination::Adjudication,
+        Termination::KingInCenter,
+        Termination::ThreeChecks,
+        Termination::OutOfPieces,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Termination::Checkmate => "checkmate",
+            Termination::Stalemate => "stalemate",
+            Termination::KingCaptured => "king captured",
+            Termination::ThreefoldRepetition => "threefold repetition",
+            Termination::FivefoldRepetition => "fivefold repetition",
+            Termination::FiftyMoves => "50-move rule",
+            Termination::SeventyFiveMoves => "75-move rule",
+            Termination::InsufficientMaterial => "insufficient material",
+            Termination::Adjudication => "adjudication",
+            Termination::KingInCenter => "king in the center",
+            Termination::ThreeChecks => "three checks",
+            Termination::OutOfPieces => "out of pieces",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|termination| termination.name().eq_ignore_ascii_case(name.trim()))
+    }
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ChessGame {
     // this holds global data for the mcts arena
     pub position: ChessPosition,
     pub fullmove_counter: u32,
-    pub game_history: Vec<ChessPosition>,
+    pub start_position: ChessPosition,
+    pub history: HashHistory, // the start position and one entry per move after it
     pub move_list: Vec<ChessMove>,
+    pub undo_stack: Vec<UndoInfo>,
     pub outcome: Outcome,
 }
 
 impl Default for ChessGame {
     fn default() -> Self {
-        let mut game = ChessGame::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
-        let hash = game.position.calculate_hash();
-        game.position.zobrist_hash = hash;
-        game.game_history.push(game.position.clone());
-        game
+        ChessGame::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap()
     }
 }
 
 impl ChessGame {
-    pub fn from_fen(fen: &str) -> Result<Self, &str> {
-        let mut parts = fen.split(' ');
-        let board_str = parts.next().ok_or("FEN missing board")?;
-        let side_str = parts.next().ok_or("FEN missing side to move")?;
-        let castling_str = parts.next().ok_or("FEN missing castling rights")?;
-        let ep_str = parts.next().ok_or("FEN missing en passant square")?;
-        let halfmove_clock: u32 = parts.next().and_then(|s| s.parse().ok()).unwrap_or(0);
-        let fullmove_counter: u32 = parts.next().and_then(|s| s.parse().ok()).unwrap_or(1);
-
-        let mut board_array = [None; 64];
-
-        let mut rank: u8 = 7;
-        let mut file: u8 = 0;
-
-        for c in board_str.chars() {
-            match c {
-                '/' => {
-                    rank -= 1;
-                    file = 0;
-                }
-                '1'..='8' => {
-                    file += c.to_digit(10).ok_or("from_fen")? as u8;
-                }
-                _ => {
-                    let color = if c.is_uppercase() { Color::White } else { Color::Black };
-
-                    let piece_type = match c {
-                        'P' | 'p' => PieceType::Pawn,
-                        'N' | 'n' => PieceType::Knight,
-                        'B' | 'b' => PieceType::Bishop,
-                        'R' | 'r' => PieceType::Rook,
-                        'Q' | 'q' => PieceType::Queen,
-                        'K' | 'k' => PieceType::King,
-                        _ => return Err("Invalid piece type"),
-                    };
-
-                    let index = (rank as usize) * 8 + (file as usize);
-
-                    board_array[index] = Some(ChessPiece::new(color, piece_type));
-                    file += 1;
-                }
-            }
-        }
-
-        let en_passant = match ep_str {
-            "-" => None,
-            s => Some(ChessSquare::from_name(s).ok_or("Invalid en passant square")?),
-        };
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        Ok(ChessGame::from_position(parse_fen(fen)?))
+    }
 
-        let mut chessboard = ChessBoard::empty();
-        for (index, piece_option) in board_array.into_iter().enumerate() {
-            if let Some(piece) = piece_option {
-                chessboard.add_piece(piece, ChessSquare::new(index as u8).ok_or("from_fen")?);
-            }
+    // a game with no history that starts at position
+    pub fn from_position(position: ChessPosition) -> Self {
+        ChessGame {
+            fullmove_counter: position.fullmove_counter,
+            start_position: position.clone(),
+            history: HashHistory::new(&position),
+            position,
+            move_list: Vec::new(),
+            undo_stack: Vec::new(),
+            outcome: Outcome::Unfinished,
         }
+    }
 
-        let mut position = ChessPosition {
-            chessboard,
-            side_to_move: if side_str == "w" { Color::White } else { Color::Black },
-            castling_rights: CastlingRights::from_fen(castling_str),
-            en_passant,
-            halfmove_clock,
-            fullmove_counter,
-            zobrist_hash: 0,
-            pseudolegal_moves: ArrayVec::<ChessMove, 128>::new(),
-        };
-
-        position.generate_pseudolegal();
+    // the start position of a variant, Horde's has white's pawns and Three-check its check counters
+    pub fn from_variant(variant: Variant) -> Self {
+        ChessGame::from_position(variant.start_position())
+    }
 
-        Ok(ChessGame { position, fullmove_counter, game_history: Vec::new(), move_list: Vec::new(), outcome: Outcome::Unfinished })
+    // the Chess960 start position with the given number, castling is encoded as king takes rook
+    pub fn chess960(index: usize) -> Self {
+        let mut game = ChessGame::from_fen(&chess960_fen(index)).expect("invalid Chess960 start position");
+        game.position.chess960 = true;
+        game.start_position.chess960 = true;
+        game
     }
 
     pub fn uci_to_move(&self, input: &str) -> Result<ChessMove, &str> {
+        if input.contains('@') {
+            return ChessMove::from_uci(input);
+        }
         let mut chars = input.chars();
         let from_str: String = chars.by_ref().take(2).collect();
         let to_str: String = chars.by_ref().take(2).collect();
@@ -127,6 +151,7 @@ impl ChessGame {
                 'r' => Some(PieceType::Rook),
                 'b' => Some(PieceType::Bishop),
                 'n' => Some(PieceType::Knight),
+                'k' => Some(PieceType::King),
                 _ => return Err("Invalid promotion"),
             }
         } else {
@@ -136,6 +161,10 @@ impl ChessGame {
         Ok(ChessMove { from: from_sq, to: to_sq, promotion: promoted_type })
     }
 
+    pub fn san_to_move(&self, input: &str) -> Result<ChessMove, SanError> {
+        ChessMove::from_san(input, &self.position)
+    }
+
     pub fn fen_to_ascii(fen: &str) {
         let mut board = String::new();
         let rows = fen.split_whitespace().next().unwrap_or("").split('/');
@@ -161,72 +190,42 @@ impl ChessGame {
 
     // should make pseudolegal/legal moves indiscriminantly. should never be passed impossible moves.
     pub fn make_move(&mut self, mov: &ChessMove) {
+        self.make_packed(self.position.encode_move(mov));
+    }
+
+    // for moves taken from the move generator, which already carry their kind
+    pub fn make_packed(&mut self, mov: PackedMove) {
         if self.position.side_to_move == Color::Black {
             self.fullmove_counter += 1;
         }
-        self.move_list.push(*mov);
-        self.position.make_move(mov);
+        self.move_list.push(mov.into());
+        let undo = self.position.make_packed(mov);
+        self.undo_stack.push(undo);
 
-        self.game_history.push(self.position.clone());
-        self.position.generate_pseudolegal();
+        self.history.push(&self.position);
     }
 
     pub fn to_pgn(&self) -> String {
-        let mut pgn = String::new();
-        for (i, mov) in self.move_list.iter().enumerate() {
-            let bruh = format!("{}. {}", i, mov.to_uci());
-            pgn.push_str(&bruh);
-        }
-        pgn
+        PgnGame::from_game(self).to_string()
     }
 
     pub fn unmake_move(&mut self) {
-        // this defeats the purpose of make unmake. unused.
-        let entry = self.game_history.pop().expect("No history to unmake");
-        self.position = entry;
-        self.position.halfmove_clock -= 1;
-        self.position.zobrist_hash = self.position.calculate_hash();
-
-        // let current_piece = self.chessboard.get_piece_at(mov.to).expect("chessboard desync: Piece missing on unmake");
-        //
-        // if mov.promotion.is_some() {
-        //     self.chessboard.remove_piece(current_piece, mov.to);
-        //     let pawn = ChessPiece { color: self.side_to_move, piece_type: PieceType::Pawn };
-        //     self.chessboard.add_piece(pawn, mov.from);
-        // } else {
-        //     self.chessboard.move_piece(mov.to, mov.from, current_piece);
-        // }
-        //
-        // if let Some(cap_piece) = entry.captured_piece {
-        //     let mut cap_sq = mov.to;
-        //     if current_piece.piece_type == PieceType::Pawn && entry.en_passant == Some(mov.to) {
-        //         cap_sq = ChessSquare::from_coords(mov.to.file(), mov.from.rank()).unwrap();
-        //     }
-        //     self.chessboard.add_piece(cap_piece, cap_sq);
-        // }
-        //
-        // if current_piece.piece_type == PieceType::King && (mov.from.file() as i8 - mov.to.file() as i8).abs() == 2 {
-        //     let (rook_now, rook_orig) = match mov.to {
-        //         ChessSquare::G1 => (ChessSquare::F1, ChessSquare::H1),
-        //         ChessSquare::C1 => (ChessSquare::D1, ChessSquare::A1),
-        //         ChessSquare::G8 => (ChessSquare::F8, ChessSquare::H8),
-        //         ChessSquare::C8 => (ChessSquare::D8, ChessSquare::A8),
-        //         _ => panic!("Invalid castle unmake state"),
-        //     };
-        //
-        //     let rook = self.chessboard.get_piece_at(rook_now).expect("Rook missing in un-castle");
-        //
-        // self.chessboard.move_piece(rook_now, rook_orig, rook);
-        //}
-    }
-
-    pub fn check_game_state(&self, legal: bool) -> Outcome {
-        let repetition_count = self.game_history.iter().filter(|entry| entry.zobrist_hash == self.position.zobrist_hash).count();
-
-        if repetition_count >= 3 {
-            return Outcome::Finished(None);
+        let mov = self.move_list.pop().expect("No move to unmake");
+        let undo = self.undo_stack.pop().expect("No undo record to unmake");
+        self.position.unmake_move(&mov, &undo);
+        self.history.pop();
+
+        if self.position.side_to_move == Color::Black {
+            self.fullmove_counter -= 1;
         }
+        self.outcome = Outcome::Unfinished;
+    }
 
-        self.position.check_game_state(legal)
+    // an outcome already set on the game, e.g. by adjudication, takes precedence
+    pub fn check_game_state(&self, ruleset: Ruleset, draw_rules: &DrawRules) -> Outcome {
+        if let Outcome::Finished(..) = self.outcome {
+            return self.outcome;
+        }
+        ruleset.outcome(&self.position, self.history.repetitions(), draw_rules)
     }
 }
@@ -1,5 +1,11 @@
-use super::{ChessSquare, PieceType};
+use core::iter::Chain;
+use core::slice;
 
+use arrayvec::ArrayVec;
+
+use super::{ChessPosition, ChessSquare, PieceType};
+
+// A Crazyhouse drop stays on its square, with the dropped piece in promotion.
 #[derive(Hash, Copy, Clone, Debug, PartialEq, Eq)]
 pub struct ChessMove {
     pub from: ChessSquare,
@@ -16,17 +22,38 @@ impl ChessMove {
         Self { from: self.from, to: self.to, promotion: Some(piece) }
     }
 
+    pub fn drop(piece: PieceType, to: ChessSquare) -> Self {
+        Self { from: to, to, promotion: Some(piece) }
+    }
+
+    pub fn dropped(&self) -> Option<PieceType> {
+        if self.from == self.to { self.promotion } else { None }
+    }
+
+    // drops are written like N@f3, with P for pawns
     pub fn from_uci(uci: &str) -> Result<Self, &'static str> {
+        if let Some((piece, square)) = uci.split_once('@') {
+            let piece = match piece {
+                "P" | "N" | "B" | "R" | "Q" => PieceType::from_char(piece.chars().next().unwrap()).unwrap(),
+                _ => return Err("Invalid drop piece"),
+            };
+            let to = ChessSquare::from_name(square).ok_or("Invalid drop square")?;
+            return Ok(ChessMove::drop(piece, to));
+        }
+
+        if !uci.is_ascii() {
+            return Err("Invalid UCI character");
+        }
         if uci.len() < 4 || uci.len() > 5 {
             return Err("Invalid UCI length");
         }
 
-        let from_sq = ChessSquare::from_name(&uci[0..2]);
-        let to_sq = ChessSquare::from_name(&uci[2..4]);
+        let from_sq = ChessSquare::from_name(&uci[0..2]).ok_or("Invalid from square")?;
+        let to_sq = ChessSquare::from_name(&uci[2..4]).ok_or("Invalid to square")?;
 
         let promotion = if uci.len() == 5 {
             let promo_char = uci.chars().nth(4).ok_or("Invalid promotion character")?;
-            if promo_char != 'Q' && promo_char != 'R' && promo_char != 'B' && promo_char != 'N' {
+            if !matches!(promo_char.to_ascii_uppercase(), 'Q' | 'R' | 'B' | 'N' | 'K') {
                 return Err("Invalid promotion piece");
             }
             Some(PieceType::from_char(promo_char).ok_or("Invalid promotion piece type")?)
@@ -34,10 +61,13 @@ impl ChessMove {
             None
         };
 
-        Ok(ChessMove { from: from_sq.unwrap(), to: to_sq.unwrap(), promotion })
+        Ok(ChessMove { from: from_sq, to: to_sq, promotion })
     }
 
     pub fn to_uci(&self) -> String {
+        if let Some(piece) = self.dropped() {
+            return format!("{}@{}", piece.to_char(crate::Color::White), self.to.name());
+        }
         let mut uci = format!("{}{}", self.from.name(), self.to.name());
         if let Some(promotion) = self.promotion {
             uci.push(match promotion {
@@ -45,9 +75,234 @@ impl ChessMove {
                 PieceType::Rook => 'r',
                 PieceType::Bishop => 'b',
                 PieceType::Knight => 'n',
+                PieceType::King => 'k',
                 _ => ' ',
             });
         }
         uci
     }
 }
+
+// What a move does besides moving a piece, decided by the move generator so that making the
+// move doesn't have to work it out from the board again.
+#[derive(Hash, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MoveKind {
+    Quiet,
+    DoublePush,
+    KingsideCastle,
+    QueensideCastle,
+    Capture,
+    EnPassant,
+    Promotion(PieceType),
+    PromotionCapture(PieceType),
+    Drop(PieceType),
+}
+
+impl MoveKind {
+    // 0-5 as listed, 8-11 promotions and 12-15 capturing promotions to knight, bishop, rook, queen,
+    // 6 and 7 the Antichess promotions to king. A drop reuses 0-4 for the piece, it is told apart
+    // by staying on its square.
+    fn flag(self) -> u16 {
+        match self {
+            MoveKind::Quiet => 0,
+            MoveKind::DoublePush => 1,
+            MoveKind::KingsideCastle => 2,
+            MoveKind::QueensideCastle => 3,
+            MoveKind::Capture => 4,
+            MoveKind::EnPassant => 5,
+            MoveKind::Promotion(PieceType::King) => 6,
+            MoveKind::PromotionCapture(PieceType::King) => 7,
+            MoveKind::Promotion(piece) => 8 | (piece as u16 - 1),
+            MoveKind::PromotionCapture(piece) => 12 | (piece as u16 - 1),
+            MoveKind::Drop(piece) => piece as u16,
+        }
+    }
+
+    fn from_flag(flag: u16) -> Option<Self> {
+        let promotion = || PieceType::from_idx((flag & 3) as usize + 1).unwrap();
+        match flag {
+            0 => Some(MoveKind::Quiet),
+            1 => Some(MoveKind::DoublePush),
+            2 => Some(MoveKind::KingsideCastle),
+            3 => Some(MoveKind::QueensideCastle),
+            4 => Some(MoveKind::Capture),
+            5 => Some(MoveKind::EnPassant),
+            6 => Some(MoveKind::Promotion(PieceType::King)),
+            7 => Some(MoveKind::PromotionCapture(PieceType::King)),
+            8..=11 => Some(MoveKind::Promotion(promotion())),
+            12..=15 => Some(MoveKind::PromotionCapture(promotion())),
+            _ => None,
+        }
+    }
+}
+
+// A move in 16 bits, from and to square in the low twelve and the MoveKind flag in the top four.
+// The squares are the ones UCI uses, so castling goes to the king's square in standard chess and
+// to the castling rook in Chess960. A drop has both squares on its target.
+#[derive(Hash, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PackedMove(u16);
+
+impl PackedMove {
+    pub fn new(from: ChessSquare, to: ChessSquare, kind: MoveKind) -> Self {
+        Self(from.0 as u16 | (to.0 as u16) << 6 | kind.flag() << 12)
+    }
+
+    // every flag is in use, None for a move that stays on its square without dropping a piece
+    pub fn from_raw(raw: u16) -> Option<Self> {
+        (raw & 63 != raw >> 6 & 63 || raw >> 12 <= PieceType::Queen as u16).then_some(Self(raw))
+    }
+
+    pub fn drop(piece: PieceType, to: ChessSquare) -> Self {
+        Self::new(to, to, MoveKind::Drop(piece))
+    }
+
+    pub fn raw(self) -> u16 {
+        self.0
+    }
+
+    pub fn from(self) -> ChessSquare {
+        ChessSquare((self.0 & 63) as u8)
+    }
+
+    pub fn to(self) -> ChessSquare {
+        ChessSquare((self.0 >> 6 & 63) as u8)
+    }
+
+    pub fn kind(self) -> MoveKind {
+        if self.from() == self.to() {
+            return MoveKind::Drop(PieceType::from_idx((self.0 >> 12) as usize).unwrap());
+        }
+        MoveKind::from_flag(self.0 >> 12).unwrap()
+    }
+
+    pub fn dropped(self) -> Option<PieceType> {
+        match self.kind() {
+            MoveKind::Drop(piece) => Some(piece),
+            _ => None,
+        }
+    }
+
+    pub fn promotion(self) -> Option<PieceType> {
+        match self.kind() {
+            MoveKind::Promotion(piece) | MoveKind::PromotionCapture(piece) => Some(piece),
+            _ => None,
+        }
+    }
+
+    // en passant included
+    pub fn is_capture(self) -> bool {
+        matches!(self.kind(), MoveKind::Capture | MoveKind::EnPassant | MoveKind::PromotionCapture(_))
+    }
+
+    pub fn is_castling(self) -> bool {
+        matches!(self.kind(), MoveKind::KingsideCastle | MoveKind::QueensideCastle)
+    }
+
+    // Works the kind out from the board, for moves that didn't come from the move generator.
+    // A move without a piece on its from square comes out as a quiet move.
+    pub fn from_move(mov: &ChessMove, position: &ChessPosition) -> Self {
+        if let Some(piece) = mov.dropped() {
+            return Self::drop(piece, mov.to);
+        }
+        let board = &position.chessboard;
+        let is_pawn = board.get_piece_at(mov.from).is_some_and(|piece| piece.piece_type == PieceType::Pawn);
+        let is_capture = board.get_piece_at(mov.to).is_some();
+
+        let kind = if let Some(castling) = board.castling(mov) {
+            if castling.rook_from.file() > castling.king_from.file() {
+                MoveKind::KingsideCastle
+            } else {
+                MoveKind::QueensideCastle
+            }
+        } else if let Some(piece) = mov.promotion {
+            if is_capture {
+                MoveKind::PromotionCapture(piece)
+            } else {
+                MoveKind::Promotion(piece)
+            }
+        } else if is_capture {
+            MoveKind::Capture
+        } else if is_pawn && position.en_passant == Some(mov.to) && mov.from.file() != mov.to.file() {
+            MoveKind::EnPassant
+        } else if is_pawn && mov.from.rank().abs_diff(mov.to.rank()) == 2 && matches!(mov.from.rank(), 1 | 6) {
+            MoveKind::DoublePush
+        } else {
+            MoveKind::Quiet
+        };
+        Self::new(mov.from, mov.to, kind)
+    }
+
+    pub fn from_uci(uci: &str, position: &ChessPosition) -> Result<Self, &'static str> {
+        Ok(position.encode_move(&ChessMove::from_uci(uci)?))
+    }
+
+    pub fn to_uci(self) -> String {
+        ChessMove::from(self).to_uci()
+    }
+}
+
+impl From<PackedMove> for ChessMove {
+    fn from(mov: PackedMove) -> Self {
+        match mov.dropped() {
+            Some(piece) => ChessMove::drop(piece, mov.to()),
+            None => ChessMove::new(mov.from(), mov.to(), mov.promotion()),
+        }
+    }
+}
+
+// The pseudolegal moves a position keeps. Only Crazyhouse drops go past the 256 kept inline, the
+// rest of those spill onto the heap, so positions stay small in every variant.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MoveList {
+    inline:  ArrayVec<PackedMove, 256>,
+    spilled: Vec<PackedMove>,
+}
+
+impl MoveList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, mov: PackedMove) {
+        if let Err(full) = self.inline.try_push(mov) {
+            self.spilled.push(full.element());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inline.len() + self.spilled.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inline.is_empty()
+    }
+
+    pub fn iter(&self) -> Chain<slice::Iter<'_, PackedMove>, slice::Iter<'_, PackedMove>> {
+        self.inline.iter().chain(&self.spilled)
+    }
+
+    pub fn contains(&self, mov: &PackedMove) -> bool {
+        self.inline.contains(mov) || self.spilled.contains(mov)
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&PackedMove) -> bool) {
+        *self = self.iter().copied().filter(|mov| keep(mov)).collect();
+    }
+}
+
+impl<'a> IntoIterator for &'a MoveList {
+    type Item = &'a PackedMove;
+    type IntoIter = Chain<slice::Iter<'a, PackedMove>, slice::Iter<'a, PackedMove>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl FromIterator<PackedMove> for MoveList {
+    fn from_iter<I: IntoIterator<Item = PackedMove>>(iter: I) -> Self {
+        let mut moves = MoveList::new();
+        iter.into_iter().for_each(|mov| moves.push(mov));
+        moves
+    }
+}
@@ -88,6 +88,18 @@ impl PieceType {
         };
         if color == Color::White { lower.to_ascii_uppercase() } else { lower }
     }
+
+    // material in centipawns, the king outweighs everything else combined
+    pub fn value(&self) -> i32 {
+        match self {
+            PieceType::Pawn => 100,
+            PieceType::Knight => 320,
+            PieceType::Bishop => 330,
+            PieceType::Rook => 500,
+            PieceType::Queen => 900,
+            PieceType::King => 20000,
+        }
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -2,7 +2,14 @@ use core::fmt;
 
 use arrayvec::ArrayVec;
 
-use crate::{Bitboard, CastlingRights, ChessBoard, ChessMove, ChessPiece, ChessSquare, Color, PieceType, ZobristKeys, chess_game::Outcome};
+use crate::{
+    Bitboard, CastlingRights, ChessBoard, ChessMove, ChessPiece, ChessSquare, Color, MoveKind, MoveList, PackedMove, PieceType, Variant, ZobristKeys,
+    chess_board::Castling,
+};
+
+// the bound of the move lists built on the stack, standard chess tops out at 218 moves and
+// Crazyhouse drops onto an emptier board go past 300
+pub const MAX_MOVES: usize = 512;
 
 #[derive(Debug, Clone, Default)]
 pub struct ChessPosition {
@@ -13,7 +20,31 @@ pub struct ChessPosition {
     pub halfmove_clock: u32,
     pub fullmove_counter: u32,
     pub zobrist_hash: u64,
-    pub pseudolegal_moves: ArrayVec<ChessMove, 128>,
+    pub pawn_hash: u64,
+    pub material_hash: u64,
+    pub chess960: bool, // castling moves are encoded as the king taking its own rook
+    pub variant: Variant,
+    pub checks: [u8; 2],       // given by white and black, only counted in Three-check
+    pub pockets: [[u8; 5]; 2], // [color][pawn to queen], the pieces Crazyhouse players can drop
+    pub promoted: Bitboard,    // promoted pieces go back to a pocket as pawns, only kept in Crazyhouse
+    pub pseudolegal_moves: MoveList,
+}
+
+// everything make_move overwrites that can't be recovered from the move itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UndoInfo {
+    pub captured_piece: Option<ChessPiece>,
+    pub castling_rights: CastlingRights,
+    pub en_passant: Option<ChessSquare>,
+    pub halfmove_clock: u32,
+    pub zobrist_hash: u64,
+    pub pawn_hash: u64,
+    pub material_hash: u64,
+    pub castling: Option<Castling>,
+    pub checks: [u8; 2],
+    pub pockets: [[u8; 5]; 2],
+    pub promoted: Bitboard,
+    pub mov: PackedMove,
 }
 
 impl fmt::Display for ChessPosition {
@@ -25,7 +56,7 @@ impl fmt::Display for ChessPosition {
 
 impl ChessPosition {
     pub fn generate_pseudolegal(&mut self) {
-        let mut moves = ArrayVec::<ChessMove, 128>::new();
+        let mut moves = MoveList::new();
 
         let (allies, opps) = match self.side_to_move {
             Color::White => (self.chessboard.white_occupancy, self.chessboard.black_occupancy),
@@ -43,14 +74,27 @@ impl ChessPosition {
             let side = self.side_to_move;
             let rank_7 = if side == Color::White { 6 } else { 1 };
             let rank_2 = if side == Color::White { 1 } else { 6 };
+            // the horde's pawns on the first rank may also step two squares, without an en passant square
+            let horde_first_rank = self.variant == Variant::Horde && side == Color::White && from_sq.rank() == 0;
+            let promotions: &[PieceType] = if self.variant == Variant::Antichess {
+                &[PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::King]
+            } else {
+                &[PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight]
+            };
 
-            let add_move = |moves2: &mut ArrayVec<ChessMove, 128>, from: ChessSquare, to: ChessSquare| {
+            let add_move = |moves2: &mut MoveList, from: ChessSquare, to: ChessSquare, capture: bool| {
                 if from.rank() == rank_7 {
-                    for piece in [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight] {
-                        let _ = moves2.try_push(ChessMove::new(from, to, Some(piece)));
+                    for &piece in promotions {
+                        let kind = if capture {
+                            MoveKind::PromotionCapture(piece)
+                        } else {
+                            MoveKind::Promotion(piece)
+                        };
+                        moves2.push(PackedMove::new(from, to, kind));
                     }
                 } else {
-                    let _ = moves2.try_push(ChessMove::new(from, to, None));
+                    let kind = if capture { MoveKind::Capture } else { MoveKind::Quiet };
+                    moves2.push(PackedMove::new(from, to, kind));
                 }
             };
 
@@ -63,8 +107,8 @@ impl ChessPosition {
             if let Some(to_sq) = square_ahead
                 && !self.chessboard.all_pieces.is_set(to_sq)
             {
-                add_move(&mut moves, from_sq, to_sq);
-                if from_sq.rank() == rank_2 {
+                add_move(&mut moves, from_sq, to_sq, false);
+                if from_sq.rank() == rank_2 || horde_first_rank {
                     let square_ahead = if self.side_to_move == Color::White {
                         to_sq.square_north()
                     } else {
@@ -73,7 +117,8 @@ impl ChessPosition {
                     if let Some(to_sq) = square_ahead
                         && !self.chessboard.all_pieces.is_set(to_sq)
                     {
-                        let _ = moves.try_push(ChessMove::new(from_sq, to_sq, None));
+                        let kind = if horde_first_rank { MoveKind::Quiet } else { MoveKind::DoublePush };
+                        moves.push(PackedMove::new(from_sq, to_sq, kind));
                     }
                 }
             }
@@ -85,114 +130,304 @@ impl ChessPosition {
                 ChessBoard::PAWN_ATTACKS_BLACK[from_sq.0 as usize]
             };
 
-            let mut targets = opps;
-            if let Some(ep_sq) = self.en_passant {
-                targets |= Bitboard::from_square(ep_sq);
+            if let Some(ep_sq) = self.en_passant
+                && attacks.is_set(ep_sq)
+            {
+                moves.push(PackedMove::new(from_sq, ep_sq, MoveKind::EnPassant));
             }
 
-            attacks &= targets;
+            attacks &= opps;
 
             while let Some(to_sq) = attacks.pop_lsb() {
-                add_move(&mut moves, from_sq, to_sq);
+                add_move(&mut moves, from_sq, to_sq, true);
             }
         }
 
         while let Some(from_sq) = knights.pop_lsb() {
             let mut to_squares = ChessBoard::KNIGHT_ATTACKS[from_sq.0 as usize] & !allies;
             while let Some(to_sq) = to_squares.pop_lsb() {
-                let mv = ChessMove::new(from_sq, to_sq, None);
-                let _ = moves.try_push(mv);
+                let kind = if opps.is_set(to_sq) { MoveKind::Capture } else { MoveKind::Quiet };
+                moves.push(PackedMove::new(from_sq, to_sq, kind));
             }
         }
 
-        let mut move_pusher = |from_sq: ChessSquare, ray_bb: [Bitboard; 4]| {
-            let mut ray = Bitboard::EMPTY;
-
-            for (i, mask) in ray_bb.iter().enumerate() {
-                let mut blockers = mask & self.chessboard.all_pieces;
-                if blockers.is_empty() {
-                    ray |= *mask
-                } else {
-                    let to_sq = if i < 2 { blockers.pop_msb() } else { blockers.pop_lsb() };
-                    let to_sq = to_sq.expect("No to_sq found in blockers");
-                    if opps.is_set(to_sq) {
-                        ray.set(to_sq);
-                    }
-                    // for some reason, i made all empty bitboards None, when adjacent squares
-                    // should be empty instead, so i have to use unwrap or default
-                    ray |= ChessBoard::BETWEEN[from_sq.0 as usize][to_sq.0 as usize].unwrap_or_default()
-                }
-            }
-
-            while let Some(to_sq) = ray.pop_lsb() {
-                let _ = moves.try_push(ChessMove::new(from_sq, to_sq, None));
+        let mut push_attacks = |from_sq: ChessSquare, attacks: Bitboard| {
+            let mut targets = attacks & !allies;
+            while let Some(to_sq) = targets.pop_lsb() {
+                let kind = if opps.is_set(to_sq) { MoveKind::Capture } else { MoveKind::Quiet };
+                moves.push(PackedMove::new(from_sq, to_sq, kind));
             }
         };
 
+        let occupancy = self.chessboard.all_pieces;
+
         while let Some(from_sq) = bishops.pop_lsb() {
-            let ray_bb = ChessBoard::BISHOP_ATTACKS[from_sq.0 as usize];
-            move_pusher(from_sq, ray_bb);
+            push_attacks(from_sq, ChessBoard::bishop_attacks(from_sq, occupancy));
         }
 
         while let Some(from_sq) = rooks.pop_lsb() {
-            let ray_bb = ChessBoard::ROOK_ATTACKS[from_sq.0 as usize];
-            move_pusher(from_sq, ray_bb);
+            push_attacks(from_sq, ChessBoard::rook_attacks(from_sq, occupancy));
         }
 
         while let Some(from_sq) = queens.pop_lsb() {
-            let rooks = ChessBoard::ROOK_ATTACKS[from_sq.0 as usize];
-            let bishops = ChessBoard::BISHOP_ATTACKS[from_sq.0 as usize];
-            move_pusher(from_sq, rooks);
-            move_pusher(from_sq, bishops);
+            push_attacks(from_sq, ChessBoard::queen_attacks(from_sq, occupancy));
         }
 
-        if let Some(from_sq) = king.pop_lsb() {
+        // Antichess promotes to kings, so there can be several
+        while let Some(from_sq) = king.pop_lsb() {
             let mut bb = ChessBoard::KING_ATTACKS[from_sq.0 as usize] & !allies;
             while let Some(sq) = bb.pop_lsb() {
-                let _ = moves.try_push(ChessMove::new(from_sq, sq, None));
+                let kind = if opps.is_set(sq) { MoveKind::Capture } else { MoveKind::Quiet };
+                moves.push(PackedMove::new(from_sq, sq, kind));
             }
-            let clear = |from: ChessSquare, to: ChessSquare| -> bool {
-                let mut between = ChessBoard::BETWEEN[from.0 as usize][to.0 as usize].expect("failed to find between sq castling");
-                // If no blockers
-                if (between & self.chessboard.all_pieces).is_empty() {
-                    // If no squares in check (castling into check is pseudo legal, but not
-                    // out of or through check)
-                    let sq = between.pop_lsb().unwrap();
-                    if !(self.chessboard.is_square_attacked(from, self.side_to_move.opposite())
-                        || self.chessboard.is_square_attacked(sq, self.side_to_move.opposite()))
-                    {
-                        return true;
+            // castling out of, through or into check is left to the ruleset
+            let (kingside, queenside) = CastlingRights::for_color(self.side_to_move);
+            for right in [kingside, queenside] {
+                if let Some((mov, _)) = self.castling_move(from_sq, right) {
+                    moves.push(mov);
+                }
+            }
+        }
+
+        for mov in self.drops(Bitboard::ALL) {
+            moves.push(mov);
+        }
+
+        if self.variant == Variant::Antichess && moves.iter().any(|mov| mov.is_capture()) {
+            moves.retain(|mov| mov.is_capture());
+        }
+
+        self.pseudolegal_moves = moves;
+    }
+
+    // Drops from the pocket of the side to move onto the empty squares of allowed, pawns not onto
+    // the first or last rank.
+    fn drops(&self, allowed: Bitboard) -> impl Iterator<Item = PackedMove> {
+        let pocket = self.pockets[self.side_to_move as usize];
+        let empty = !self.chessboard.all_pieces & allowed;
+        [PieceType::Pawn, PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen]
+            .into_iter()
+            .filter(move |&piece_type| pocket[piece_type as usize] > 0)
+            .flat_map(move |piece_type| {
+                let mut targets = if piece_type == PieceType::Pawn {
+                    empty & !Bitboard::BACK_RANKS
+                } else {
+                    empty
+                };
+                std::iter::from_fn(move || targets.pop_lsb()).map(move |to_sq| PackedMove::drop(piece_type, to_sq))
+            })
+    }
+
+    // The castling move for a held right when every square the king and rook cross is empty,
+    // apart from the two of them. Attacks on those squares are left to the caller.
+    fn castling_move(&self, king_sq: ChessSquare, right: CastlingRights) -> Option<(PackedMove, Castling)> {
+        if !self.castling_rights.has(right) {
+            return None;
+        }
+        let rook_sq = self.castling_rights.rook_square(right);
+        if !self.chessboard.get_piece_bitboard(self.side_to_move, PieceType::Rook).is_set(rook_sq) || rook_sq.rank() != king_sq.rank() {
+            return None;
+        }
+
+        let (king_file, rook_file) = if rook_sq.file() > king_sq.file() { (6, 5) } else { (2, 3) };
+        let castling = Castling {
+            king_from: king_sq,
+            king_to:   ChessSquare::from_coords(king_file, king_sq.rank()).unwrap(),
+            rook_from: rook_sq,
+            rook_to:   ChessSquare::from_coords(rook_file, king_sq.rank()).unwrap(),
+        };
+
+        let span = |from: ChessSquare, to: ChessSquare| ChessBoard::BETWEEN[from.0 as usize][to.0 as usize].unwrap_or_default() | to.bitboard();
+        let crossed = span(king_sq, castling.king_to) | span(rook_sq, castling.rook_to);
+        let others = self.chessboard.all_pieces & !king_sq.bitboard() & !rook_sq.bitboard();
+        if !(crossed & others).is_empty() {
+            return None;
+        }
+
+        let to = if self.chess960 { rook_sq } else { castling.king_to };
+        let kind = if rook_file == 5 {
+            MoveKind::KingsideCastle
+        } else {
+            MoveKind::QueensideCastle
+        };
+        Some((PackedMove::new(king_sq, to, kind), castling))
+    }
+
+    // Legal moves only, computed from the checkers and pins up front instead of trying each
+    // pseudolegal move on a board copy. Positions without a king (pseudolegal mode) have none,
+    // a side the variant gives no royal king may play every pseudolegal move.
+    pub fn generate_legal(&self) -> ArrayVec<ChessMove, MAX_MOVES> {
+        self.generate_legal_packed().into_iter().map(ChessMove::from).collect()
+    }
+
+    // the same moves with their kinds worked out, ready for make_packed
+    pub fn generate_legal_packed(&self) -> ArrayVec<PackedMove, MAX_MOVES> {
+        let mut moves = ArrayVec::<PackedMove, MAX_MOVES>::new();
+        if !self.variant.has_royal_king(self.side_to_move) {
+            return self.pseudolegal_moves.iter().copied().collect();
+        }
+
+        let board = &self.chessboard;
+        let us = self.side_to_move;
+        let them = us.opposite();
+        let (allies, opps) = match us {
+            Color::White => (board.white_occupancy, board.black_occupancy),
+            Color::Black => (board.black_occupancy, board.white_occupancy),
+        };
+        let occupancy = board.all_pieces;
+
+        let Some(king_sq) = board.get_piece_bitboard(us, PieceType::King).lsb_square() else {
+            return moves;
+        };
+
+        let their_queens = board.get_piece_bitboard(them, PieceType::Queen);
+        let their_straight = board.get_piece_bitboard(them, PieceType::Rook) | their_queens;
+        let their_diagonal = board.get_piece_bitboard(them, PieceType::Bishop) | their_queens;
+
+        let checkers = board.checkers(us);
+
+        // the king can't hide from a slider by stepping along its ray, so look through the king
+        let without_king = occupancy & !king_sq.bitboard();
+        let mut king_targets = ChessBoard::KING_ATTACKS[king_sq.0 as usize] & !allies;
+        while let Some(to_sq) = king_targets.pop_lsb() {
+            if board.attackers_to(to_sq, them, without_king).is_empty() {
+                let kind = if opps.is_set(to_sq) { MoveKind::Capture } else { MoveKind::Quiet };
+                let _ = moves.try_push(PackedMove::new(king_sq, to_sq, kind));
+            }
+        }
+
+        // double check, only the king can move
+        if checkers.count() > 1 {
+            return moves;
+        }
+
+        // squares that capture the checker or block its ray
+        let evasions = match checkers.lsb_square() {
+            Some(checker_sq) => checker_sq.bitboard() | ChessBoard::BETWEEN[king_sq.0 as usize][checker_sq.0 as usize].unwrap_or_default(),
+            None => Bitboard::ALL,
+        };
+
+        // a pinned piece may only move along the ray between the king and the pinner
+        let mut pin_rays = [Bitboard::ALL; 64];
+        for pin in board.pins(us) {
+            pin_rays[pin.pinned.0 as usize] = pin.ray;
+        }
+
+        let push_targets = |moves: &mut ArrayVec<PackedMove, MAX_MOVES>, from_sq: ChessSquare, mut targets: Bitboard, promotes: bool| {
+            while let Some(to_sq) = targets.pop_lsb() {
+                let capture = opps.is_set(to_sq);
+                if promotes {
+                    for piece in [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight] {
+                        let kind = if capture {
+                            MoveKind::PromotionCapture(piece)
+                        } else {
+                            MoveKind::Promotion(piece)
+                        };
+                        let _ = moves.try_push(PackedMove::new(from_sq, to_sq, kind));
                     }
+                } else {
+                    let kind = if capture { MoveKind::Capture } else { MoveKind::Quiet };
+                    let _ = moves.try_push(PackedMove::new(from_sq, to_sq, kind));
+                }
+            }
+        };
+
+        let rank_7 = if us == Color::White { 6 } else { 1 };
+        let rank_2 = if us == Color::White { 1 } else { 6 };
+
+        let mut pawns = board.get_piece_bitboard(us, PieceType::Pawn);
+        while let Some(from_sq) = pawns.pop_lsb() {
+            let allowed = evasions & pin_rays[from_sq.0 as usize];
+            let promotes = from_sq.rank() == rank_7;
+
+            let forward = |sq: ChessSquare| if us == Color::White { sq.square_north() } else { sq.square_south() };
+            let mut pushes = Bitboard::EMPTY;
+            if let Some(one) = forward(from_sq)
+                && !occupancy.is_set(one)
+            {
+                pushes.set(one);
+                if from_sq.rank() == rank_2
+                    && let Some(two) = forward(one)
+                    && !occupancy.is_set(two)
+                    && allowed.is_set(two)
+                {
+                    let _ = moves.try_push(PackedMove::new(from_sq, two, MoveKind::DoublePush));
                 }
-                false
+            }
+
+            let attacks = match us {
+                Color::White => ChessBoard::PAWN_ATTACKS_WHITE[from_sq.0 as usize],
+                Color::Black => ChessBoard::PAWN_ATTACKS_BLACK[from_sq.0 as usize],
             };
-            match self.side_to_move {
-                Color::White => {
-                    if self.castling_rights.has(CastlingRights::WHITE_KINGSIDE) && clear(ChessSquare::E1, ChessSquare::G1) {
-                        let _ = moves.try_push(ChessMove::new(ChessSquare::E1, ChessSquare::G1, None));
-                    }
-                    if self.castling_rights.has(CastlingRights::WHITE_QUEENSIDE)
-                        && !self.chessboard.all_pieces.is_set(ChessSquare::B1)
-                        && clear(ChessSquare::E1, ChessSquare::C1)
-                    {
-                        let _ = moves.try_push(ChessMove::new(ChessSquare::E1, ChessSquare::C1, None));
+
+            push_targets(&mut moves, from_sq, (pushes | (attacks & opps)) & allowed, promotes);
+
+            // en passant removes two pieces from one rank, so check the resulting occupancy directly
+            // rather than through the pin rays, that catches the horizontal discovered check
+            if let Some(ep_sq) = self.en_passant
+                && attacks.is_set(ep_sq)
+            {
+                let cap_sq = ChessSquare::from_coords(ep_sq.file(), from_sq.rank()).unwrap();
+                if evasions.is_set(ep_sq) || checkers.is_set(cap_sq) {
+                    let after = (occupancy & !from_sq.bitboard() & !cap_sq.bitboard()) | ep_sq.bitboard();
+                    let exposed =
+                        (ChessBoard::rook_attacks(king_sq, after) & their_straight) | (ChessBoard::bishop_attacks(king_sq, after) & their_diagonal);
+                    if exposed.is_empty() {
+                        let _ = moves.try_push(PackedMove::new(from_sq, ep_sq, MoveKind::EnPassant));
                     }
                 }
-                Color::Black => {
-                    if self.castling_rights.has(CastlingRights::BLACK_KINGSIDE) && clear(ChessSquare::E8, ChessSquare::G8) {
-                        let _ = moves.try_push(ChessMove::new(ChessSquare::E8, ChessSquare::G8, None));
+            }
+        }
+
+        let mut knights = board.get_piece_bitboard(us, PieceType::Knight);
+        while let Some(from_sq) = knights.pop_lsb() {
+            let targets = ChessBoard::KNIGHT_ATTACKS[from_sq.0 as usize] & !allies & evasions & pin_rays[from_sq.0 as usize];
+            push_targets(&mut moves, from_sq, targets, false);
+        }
+
+        for (piece_type, attacks) in [
+            (PieceType::Bishop, ChessBoard::bishop_attacks as fn(ChessSquare, Bitboard) -> Bitboard),
+            (PieceType::Rook, ChessBoard::rook_attacks),
+            (PieceType::Queen, ChessBoard::queen_attacks),
+        ] {
+            let mut pieces = board.get_piece_bitboard(us, piece_type);
+            while let Some(from_sq) = pieces.pop_lsb() {
+                let targets = attacks(from_sq, occupancy) & !allies & evasions & pin_rays[from_sq.0 as usize];
+                push_targets(&mut moves, from_sq, targets, false);
+            }
+        }
+
+        // a drop can't uncover the king, in check it has to land between the king and the checker
+        for mov in self.drops(evasions) {
+            let _ = moves.try_push(mov);
+        }
+
+        // castling, never out of, through or into check. The castling rook is lifted off the board
+        // first, in Chess960 it can be what shields the king's destination.
+        if checkers.is_empty() {
+            let (kingside, queenside) = CastlingRights::for_color(us);
+            for right in [kingside, queenside] {
+                if let Some((mov, castling)) = self.castling_move(king_sq, right) {
+                    let lifted = occupancy & !king_sq.bitboard() & !castling.rook_from.bitboard();
+                    let mut path =
+                        ChessBoard::BETWEEN[king_sq.0 as usize][castling.king_to.0 as usize].unwrap_or_default() | castling.king_to.bitboard();
+                    let mut safe = true;
+                    while let Some(sq) = path.pop_lsb() {
+                        safe &= board.attackers_to(sq, them, lifted).is_empty();
                     }
-                    if self.castling_rights.has(CastlingRights::BLACK_QUEENSIDE)
-                        && !self.chessboard.all_pieces.is_set(ChessSquare::B8)
-                        && clear(ChessSquare::E8, ChessSquare::C8)
-                    {
-                        let _ = moves.try_push(ChessMove::new(ChessSquare::E8, ChessSquare::C8, None));
+                    if safe {
+                        let _ = moves.try_push(mov);
                     }
                 }
             }
         }
 
-        self.pseudolegal_moves = moves;
+        moves
+    }
+
+    // the pieces checking the side to move
+    pub fn checkers(&self) -> Bitboard {
+        self.chessboard.checkers(self.side_to_move)
     }
 
     pub fn to_fen(&self) -> String {
@@ -219,6 +454,9 @@ impl ChessPosition {
                     };
 
                     fen.push(if color == Color::White { c.to_ascii_uppercase() } else { c });
+                    if self.promoted.is_set(sq) {
+                        fen.push('~');
+                    }
                 } else {
                     empty += 1;
                 }
@@ -232,13 +470,35 @@ impl ChessPosition {
             }
         }
 
+        // the pockets, white's first, queens to pawns
+        if self.variant == Variant::Crazyhouse {
+            fen.push('[');
+            for color in [Color::White, Color::Black] {
+                for piece_type in [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn] {
+                    for _ in 0..self.pockets[color as usize][piece_type as usize] {
+                        fen.push(piece_type.to_char(color));
+                    }
+                }
+            }
+            fen.push(']');
+        }
+
         fen.push(' ');
         fen.push(if self.side_to_move == Color::White { 'w' } else { 'b' });
         fen.push(' ');
-        fen.push_str(&self.castling_rights.to_fen());
+        if self.chess960 {
+            fen.push_str(&self.castling_rights.to_xfen(&self.chessboard));
+        } else {
+            fen.push_str(&self.castling_rights.to_fen());
+        }
         fen.push(' ');
         fen.push_str(&self.en_passant.map_or("-".to_string(), |sq| sq.name()));
         fen.push(' ');
+        // the checks each side still needs, as Lichess writes them
+        if self.variant == Variant::ThreeCheck {
+            let remaining = |color: Color| 3 - self.checks[color as usize].min(3);
+            fen.push_str(&format!("{}+{} ", remaining(Color::White), remaining(Color::Black)));
+        }
         fen.push_str(&self.halfmove_clock.to_string());
         fen.push(' ');
         fen.push_str(&self.fullmove_counter.to_string());
@@ -246,30 +506,37 @@ impl ChessPosition {
         fen
     }
 
+    // The flagged form of a move, looked up in the pseudolegal moves and only worked out from
+    // the board for moves that aren't there.
+    pub fn encode_move(&self, mov: &ChessMove) -> PackedMove {
+        self.pseudolegal_moves.iter().copied().find(|packed| ChessMove::from(*packed) == *mov).unwrap_or_else(|| PackedMove::from_move(mov, self))
+    }
 
-    pub fn make_mask(&self, legal: bool, from_sq: Option<ChessSquare>) -> [bool; 64] {
-        let mut mask = [false; 64];
-        if let Some(from_sq) = from_sq {
-            assert!(!self.pseudolegal_moves.is_empty());
-            self.pseudolegal_moves.iter().for_each(|&mov| {
-                if (!legal || self.is_legal(&mov)) && from_sq == mov.from {
-                    mask[mov.to.0 as usize] = true;
-                }
-            });
-        } else {
-            assert!(!self.pseudolegal_moves.is_empty());
-            self.pseudolegal_moves.iter().for_each(|&mov| {
-                if !legal || self.is_legal(&mov) {
-                    mask[mov.from.0 as usize] = true;
+    pub fn is_legal(&self, mov: &ChessMove) -> bool {
+        self.is_legal_packed(self.encode_move(mov))
+    }
+
+    pub fn is_legal_packed(&self, mov: PackedMove) -> bool {
+        if !self.variant.has_royal_king(self.side_to_move) {
+            return self.pseudolegal_moves.contains(&mov);
+        }
+
+        // the king may not castle out of or through check, with the castling rook lifted like in generate_legal
+        if mov.is_castling() {
+            let castling = self.chessboard.castling_of(mov, self.side_to_move);
+            let them = self.side_to_move.opposite();
+            let lifted = self.chessboard.all_pieces & !castling.king_from.bitboard() & !castling.rook_from.bitboard();
+            let mut path =
+                ChessBoard::BETWEEN[castling.king_from.0 as usize][castling.king_to.0 as usize].unwrap_or_default() | castling.king_from.bitboard();
+            while let Some(sq) = path.pop_lsb() {
+                if !self.chessboard.attackers_to(sq, them, lifted).is_empty() {
+                    return false;
                 }
-            });
+            }
         }
-        mask
-    }
 
-    pub fn is_legal(&self, mov: &ChessMove) -> bool {
         let mut temp_board = self.chessboard;
-        temp_board.apply_move(mov, self.side_to_move, self.en_passant);
+        temp_board.apply_move(mov, self.side_to_move);
 
         let king_bb = temp_board.get_piece_bitboard(self.side_to_move, PieceType::King);
         let king_sq = king_bb.msb_square().unwrap();
@@ -277,51 +544,188 @@ impl ChessPosition {
         !temp_board.is_square_attacked(king_sq, self.side_to_move.opposite())
     }
 
-    pub fn make_move(&mut self, mov: &ChessMove) {
-        let moving_piece = self.chessboard.get_piece_at(mov.from).unwrap_or_else(|| panic!());
-        let captured_piece = self.chessboard.get_piece_at(mov.to);
+    pub fn make_move(&mut self, mov: &ChessMove) -> UndoInfo {
+        self.make_packed(self.encode_move(mov))
+    }
+
+    pub fn make_packed(&mut self, mov: PackedMove) -> UndoInfo {
+        let keys = ZobristKeys::get();
+        let side = self.side_to_move;
+        let (from, to) = (mov.from(), mov.to());
+        let moving_piece = match mov.dropped() {
+            Some(piece_type) => ChessPiece::new(side, piece_type),
+            None => self.chessboard.get_piece_at(from).unwrap_or_else(|| panic!()),
+        };
+        let castling = mov.is_castling().then(|| self.chessboard.castling_of(mov, side));
+        let captured = match mov.kind() {
+            MoveKind::EnPassant => {
+                Some((ChessPiece::new(side.opposite(), PieceType::Pawn), ChessSquare::from_coords(to.file(), from.rank()).unwrap()))
+            }
+            MoveKind::Capture | MoveKind::PromotionCapture(_) => self.chessboard.get_piece_at(to).map(|piece| (piece, to)),
+            _ => None,
+        };
+
+        let undo = UndoInfo {
+            captured_piece: captured.map(|(piece, _)| piece),
+            castling_rights: self.castling_rights,
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+            zobrist_hash: self.zobrist_hash,
+            pawn_hash: self.pawn_hash,
+            material_hash: self.material_hash,
+            castling,
+            checks: self.checks,
+            pockets: self.pockets,
+            promoted: self.promoted,
+            mov,
+        };
 
         let mut rights_to_remove = CastlingRights::empty();
         if moving_piece.piece_type == PieceType::King {
-            match self.side_to_move {
-                Color::White => rights_to_remove |= CastlingRights::WHITE_KINGSIDE | CastlingRights::WHITE_QUEENSIDE,
-                Color::Black => rights_to_remove |= CastlingRights::BLACK_KINGSIDE | CastlingRights::BLACK_QUEENSIDE,
-            }
+            let (kingside, queenside) = CastlingRights::for_color(side);
+            rights_to_remove |= kingside | queenside;
         }
+        rights_to_remove |= self.castling_rights.touching(from);
+        rights_to_remove |= self.castling_rights.touching(to);
 
-        let get_rights = |sq: ChessSquare| -> CastlingRights {
-            match sq {
-                ChessSquare::H1 => CastlingRights::WHITE_KINGSIDE,
-                ChessSquare::A1 => CastlingRights::WHITE_QUEENSIDE,
-                ChessSquare::H8 => CastlingRights::BLACK_KINGSIDE,
-                ChessSquare::A8 => CastlingRights::BLACK_QUEENSIDE,
-                _ => CastlingRights::empty(),
+        // take the old castling rights and en passant file out of the hash, the new ones go back in below
+        let mut hash = self.zobrist_hash ^ keys.castling_key(self.castling_rights) ^ keys.side_to_move;
+        if let Some(sq) = self.en_passant {
+            hash ^= keys.en_passant[sq.file() as usize];
+        }
+        self.castling_rights.remove(rights_to_remove);
+        hash ^= keys.castling_key(self.castling_rights);
+
+        if let Some(castling) = castling {
+            let rook = ChessPiece::new(side, PieceType::Rook);
+            hash ^= keys.piece(moving_piece, castling.king_from) ^ keys.piece(moving_piece, castling.king_to);
+            hash ^= keys.piece(rook, castling.rook_from) ^ keys.piece(rook, castling.rook_to);
+        } else if mov.dropped().is_some() {
+            hash ^= keys.piece(moving_piece, to);
+            if moving_piece.piece_type == PieceType::Pawn {
+                self.pawn_hash ^= keys.piece(moving_piece, to);
             }
-        };
+            let count = self.chessboard.get_piece_bitboard(side, moving_piece.piece_type).count();
+            self.material_hash ^= keys.material(moving_piece, count);
+        } else {
+            let placed_piece = ChessPiece::new(side, mov.promotion().unwrap_or(moving_piece.piece_type));
+            hash ^= keys.piece(moving_piece, from) ^ keys.piece(placed_piece, to);
 
-        rights_to_remove |= get_rights(mov.from);
-        rights_to_remove |= get_rights(mov.to);
-        self.castling_rights.remove(rights_to_remove);
+            if moving_piece.piece_type == PieceType::Pawn {
+                self.pawn_hash ^= keys.piece(moving_piece, from);
+                if placed_piece.piece_type == PieceType::Pawn {
+                    self.pawn_hash ^= keys.piece(placed_piece, to);
+                } else {
+                    let pawns = self.chessboard.get_piece_bitboard(side, PieceType::Pawn).count();
+                    let promoted = self.chessboard.get_piece_bitboard(side, placed_piece.piece_type).count();
+                    self.material_hash ^= keys.material(moving_piece, pawns - 1) ^ keys.material(placed_piece, promoted);
+                }
+            }
 
-        self.chessboard.apply_move(mov, self.side_to_move, self.en_passant);
+            if let Some((piece, sq)) = captured {
+                hash ^= keys.piece(piece, sq);
+                if piece.piece_type == PieceType::Pawn {
+                    self.pawn_hash ^= keys.piece(piece, sq);
+                }
+                let count = self.chessboard.get_piece_bitboard(piece.color, piece.piece_type).count();
+                self.material_hash ^= keys.material(piece, count - 1);
+            }
+        }
+
+        // captures change sides, promoted pieces as the pawns they were
+        if self.variant == Variant::Crazyhouse {
+            if let Some(piece_type) = mov.dropped() {
+                let pocket = &mut self.pockets[side as usize][piece_type as usize];
+                *pocket -= 1;
+                hash ^= keys.pocket(side, piece_type, *pocket);
+            }
+            if let Some((piece, sq)) = captured
+                && piece.piece_type != PieceType::King
+            {
+                let piece_type = if self.promoted.is_set(sq) { PieceType::Pawn } else { piece.piece_type };
+                let pocket = &mut self.pockets[side as usize][piece_type as usize];
+                hash ^= keys.pocket(side, piece_type, *pocket);
+                *pocket += 1;
+                self.promoted.clear(sq);
+            }
+            if self.promoted.is_set(from) || mov.promotion().is_some() {
+                self.promoted.clear(from);
+                self.promoted.set(to);
+            }
+        }
+
+        self.chessboard.apply_move(mov, self.side_to_move);
 
         self.en_passant = None;
-        if moving_piece.piece_type == PieceType::Pawn && (mov.from.rank() as i8 - mov.to.rank() as i8).abs() == 2 {
-            let skipped_rank = (mov.from.rank() + mov.to.rank()) / 2;
-            self.en_passant = ChessSquare::from_coords(mov.from.file(), skipped_rank);
+        if mov.kind() == MoveKind::DoublePush {
+            let skipped_rank = (from.rank() + to.rank()) / 2;
+            self.en_passant = ChessSquare::from_coords(from.file(), skipped_rank);
+            hash ^= keys.en_passant[from.file() as usize];
         }
 
-        if moving_piece.piece_type == PieceType::Pawn || captured_piece.is_some() {
+        if moving_piece.piece_type == PieceType::Pawn || captured.is_some() {
             self.halfmove_clock = 0;
         } else {
             self.halfmove_clock += 1;
         }
 
+        if side == Color::Black {
+            self.fullmove_counter += 1;
+        }
         self.side_to_move = self.side_to_move.opposite();
 
+        if self.variant == Variant::ThreeCheck
+            && let Some(king_sq) = self.chessboard.get_piece_bitboard(self.side_to_move, PieceType::King).lsb_square()
+            && self.chessboard.is_square_attacked(king_sq, side)
+        {
+            let given = &mut self.checks[side as usize];
+            hash ^= keys.checks_key(side, *given) ^ keys.checks_key(side, *given + 1);
+            *given += 1;
+        }
+        self.zobrist_hash = hash;
+
+        debug_assert_eq!(self.zobrist_hash, self.calculate_hash(), "incremental hash diverged after {}", mov.to_uci());
+        debug_assert_eq!(self.pawn_hash, self.calculate_pawn_hash(), "incremental pawn hash diverged after {}", mov.to_uci());
+        debug_assert_eq!(self.material_hash, self.calculate_material_hash(), "incremental material hash diverged after {}", mov.to_uci());
+
         self.generate_pseudolegal();
+        undo
+    }
+
+    // the move itself is kept in the undo info, the argument is only checked against it
+    pub fn unmake_move(&mut self, mov: &ChessMove, undo: &UndoInfo) {
+        debug_assert_eq!(*mov, ChessMove::from(undo.mov), "unmaking a move that wasn't made");
+        self.unmake_packed(undo);
+    }
+
+    pub fn unmake_packed(&mut self, undo: &UndoInfo) {
+        self.side_to_move = self.side_to_move.opposite();
+        if self.side_to_move == Color::Black {
+            self.fullmove_counter -= 1;
+        }
+
+        self.chessboard.unapply_move(undo.mov, self.side_to_move, undo.captured_piece, undo.castling);
+
+        self.castling_rights = undo.castling_rights;
+        self.en_passant = undo.en_passant;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.zobrist_hash = undo.zobrist_hash;
+        self.pawn_hash = undo.pawn_hash;
+        self.material_hash = undo.material_hash;
+        self.checks = undo.checks;
+        self.pockets = undo.pockets;
+        self.promoted = undo.promoted;
+
+        debug_assert_eq!(self.zobrist_hash, self.calculate_hash(), "hash diverged after unmaking {}", undo.mov.to_uci());
+
+        self.generate_pseudolegal();
+    }
 
+    // recomputes every key from scratch, for positions built outside make_move
+    pub fn refresh_hashes(&mut self) {
         self.zobrist_hash = self.calculate_hash();
+        self.pawn_hash = self.calculate_pawn_hash();
+        self.material_hash = self.calculate_material_hash();
     }
 
     pub fn calculate_hash(&self) -> u64 {
@@ -337,7 +741,7 @@ impl ChessPosition {
             }
         }
 
-        hash ^= keys.castling[self.castling_rights.0 as usize];
+        hash ^= keys.castling_key(self.castling_rights);
 
         if let Some(sq) = self.en_passant {
             hash ^= keys.en_passant[sq.file() as usize];
@@ -347,103 +751,46 @@ impl ChessPosition {
             hash ^= keys.side_to_move;
         }
 
+        for color in [Color::White, Color::Black] {
+            hash ^= keys.checks_key(color, self.checks[color as usize]);
+            for piece_type in [PieceType::Pawn, PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen] {
+                for nth in 0..self.pockets[color as usize][piece_type as usize] {
+                    hash ^= keys.pocket(color, piece_type, nth);
+                }
+            }
+        }
+
         hash
     }
 
-    pub fn check_game_state(&self, legal: bool) -> Outcome {
-        // pseudolegal checks
-        if self.halfmove_clock >= 80 {
-            return Outcome::Finished(None);
-        }
-        if self.chessboard.get_piece_bitboard(Color::White, PieceType::King).is_empty() {
-            return Outcome::Finished(Some(Color::Black));
-        }
-        if self.chessboard.get_piece_bitboard(Color::Black, PieceType::King).is_empty() {
-            return Outcome::Finished(Some(Color::White));
-        }
-
-        // if !legal {
-        //     return Outcome::Unfinished;
-        // }
-        //
-        // Legal Checks
-        let mut king_bb = self.chessboard.get_piece_bitboard(self.side_to_move, PieceType::King);
-        let king_sq = king_bb.pop_lsb().unwrap();
+    pub fn calculate_pawn_hash(&self) -> u64 {
+        let mut hash = 0;
+        let keys = ZobristKeys::get();
 
-        if legal && !self.pseudolegal_moves.iter().any(|&mov| self.is_legal(&mov)) {
-            if self.chessboard.is_square_attacked(king_sq, self.side_to_move.opposite()) {
-                return Outcome::Finished(Some(self.side_to_move.opposite()));
-            } else {
-                return Outcome::Finished(None);
+        for color in [Color::White, Color::Black] {
+            let mut bb = self.chessboard.get_piece_bitboard(color, PieceType::Pawn);
+            while let Some(sq) = bb.pop_lsb() {
+                hash ^= keys.piece(ChessPiece::new(color, PieceType::Pawn), sq);
             }
         }
 
-        // insufficient material
-        let all_pieces = self.chessboard.all_pieces;
-        let count = all_pieces.count();
-
-        if count == 2 {
-            return Outcome::Finished(None);
-        }
-        let mut white_bishops = self.chessboard.get_piece_bitboard(Color::White, PieceType::Bishop);
-        let white_knights = self.chessboard.get_piece_bitboard(Color::White, PieceType::Knight);
-        let mut black_bishops = self.chessboard.get_piece_bitboard(Color::Black, PieceType::Bishop);
-        let black_knights = self.chessboard.get_piece_bitboard(Color::Black, PieceType::Knight);
-
-        let white_minors = white_bishops | white_knights;
-        let black_minors = black_bishops | black_knights;
-
-        if count == 3 && (!white_minors.is_empty() || !black_minors.is_empty()) {
-            return Outcome::Finished(None);
-        }
+        hash
+    }
 
-        if count == 4 {
-            // K + N vs K + N or K + N + N vs K
-            if white_bishops.is_empty() && black_bishops.is_empty() {
-                return Outcome::Finished(None);
-            }
+    // depends only on how many of each piece are on the board, not where
+    pub fn calculate_material_hash(&self) -> u64 {
+        let mut hash = 0;
+        let keys = ZobristKeys::get();
 
-            if white_bishops.count() == 1 && black_bishops.count() == 1 {
-                let w_sq = white_bishops.pop_lsb().unwrap();
-                let b_sq = black_bishops.pop_lsb().unwrap();
-                if w_sq.colour() == b_sq.colour() {
-                    return Outcome::Finished(None);
+        for color in [Color::White, Color::Black] {
+            for piece_type in [PieceType::Pawn, PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen, PieceType::King] {
+                let piece = ChessPiece::new(color, piece_type);
+                for nth in 0..self.chessboard.get_piece_bitboard(color, piece_type).count() {
+                    hash ^= keys.material(piece, nth);
                 }
             }
-
-            if black_bishops.count() == 2
-                && let (Some(sq1), Some(sq2)) = (black_bishops.pop_msb(), black_bishops.pop_msb())
-                && sq1.colour() == sq2.colour()
-            {
-                return Outcome::Finished(None);
-            }
-            if white_bishops.count() == 2
-                && let (Some(sq1), Some(sq2)) = (white_bishops.pop_msb(), white_bishops.pop_msb())
-                && sq1.colour() == sq2.colour()
-            {
-                return Outcome::Finished(None);
-            }
         }
 
-        Outcome::Unfinished
-    }
-
-    pub fn expand_if_prom(&self, mov: ChessMove) -> Option<[ChessMove; 4]> {
-        let prom_rank = match self.side_to_move {
-            Color::White => 7,
-            Color::Black => 0,
-        };
-        if let Some(piece) = self.chessboard.get_piece_at(mov.from)
-            && matches!(piece.piece_type, PieceType::Pawn)
-            && mov.to.rank() == prom_rank
-        {
-            return Some([
-                mov.with_prom(PieceType::Knight),
-                mov.with_prom(PieceType::Bishop),
-                mov.with_prom(PieceType::Rook),
-                mov.with_prom(PieceType::Queen),
-            ]);
-        }
-        None
+        hash
     }
 }
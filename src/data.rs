@@ -4,14 +4,57 @@ use burn::{
     data::dataloader::batcher::Batcher,
     tensor::{Bool, Tensor, TensorData, backend::Backend},
 };
-use rand::{rngs::SmallRng, seq::IndexedRandom};
+use rand::{RngExt, rngs::SmallRng, seq::IndexedRandom};
 
-use crate::{ChessPosition, ChessSquare, Color};
+use crate::{ChessPosition, ChessSquare, Color, PackedMove, PackedPosition, PieceType};
+
+// castling rights (4 1-hot), 50 move counter, the Three-check checks given by each side, the
+// Crazyhouse pockets of both sides and the pocket piece picked for a drop (1-hot)
+pub const META_SIZE: usize = 22;
+// the squares and a virtual square per piece that can be dropped, pawn to queen
+pub const POLICY_SIZE: usize = 64 + 5;
+
+// What the first pass of the search picks, a piece on the board or one in the pocket to drop.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Selection {
+    Square(ChessSquare),
+    Pocket(PieceType),
+}
+
+impl Selection {
+    // what has to be picked before the move can be played
+    pub fn of(mov: PackedMove) -> Self {
+        match mov.dropped() {
+            Some(piece_type) => Selection::Pocket(piece_type),
+            None => Selection::Square(mov.from()),
+        }
+    }
+
+    // where it sits in policies and masks, squares as white sees them
+    pub fn index(self) -> usize {
+        match self {
+            Selection::Square(sq) => sq.0 as usize,
+            Selection::Pocket(piece_type) => 64 + piece_type as usize,
+        }
+    }
+
+    pub fn from_index(index: usize) -> Option<Self> {
+        match index {
+            0..64 => ChessSquare::new(index as u8).map(Selection::Square),
+            _ => PieceType::from_idx(index - 64).filter(|&piece_type| piece_type != PieceType::King).map(Selection::Pocket),
+        }
+    }
+}
+
+// black's policies and masks are seen from its side, with the ranks mirrored and the pockets as they are
+pub fn flip_ranks<T: Copy>(values: [T; POLICY_SIZE]) -> [T; POLICY_SIZE] {
+    std::array::from_fn(|i| if i < 64 { values[i ^ 56] } else { values[i] })
+}
 
 #[derive(Clone, Copy, Debug)]
 pub struct NetworkInputs {
     pub boards: [f32; 64 * 14],
-    pub meta:   [f32; 5],
+    pub meta:   [f32; META_SIZE],
 }
 
 impl fmt::Display for NetworkInputs {
@@ -50,7 +93,7 @@ impl fmt::Display for NetworkInputs {
 
 #[derive(Clone, Copy, Debug)]
 pub struct NetworkLabels {
-    pub policy: [f32; 64],
+    pub policy: [f32; POLICY_SIZE],
     pub value:  [f32; 3],
 }
 
@@ -66,6 +109,7 @@ impl fmt::Display for NetworkLabels {
             }
             output.push('\n');
         }
+        output.push_str(&format!("pockets: {:?}\n", &self.policy[64..]));
         output.push_str(&format!("{:?}", self.value));
         write!(f, "{}", output)
     }
@@ -92,13 +136,13 @@ impl fmt::Display for NetworkLabels {
 
 impl Default for NetworkInputs {
     fn default() -> Self {
-        Self { boards: [0.0; 64 * 14], meta: [0.0; 5] }
+        Self { boards: [0.0; 64 * 14], meta: [0.0; META_SIZE] }
     }
 }
 
 impl Default for NetworkLabels {
     fn default() -> Self {
-        Self { policy: [0.0; 64], value: [0.0; 3] }
+        Self { policy: [0.0; POLICY_SIZE], value: [0.0; 3] }
     }
 }
 
@@ -107,7 +151,7 @@ impl NetworkInputs {
         NetworkInputs::from_position(position, None)
     }
 
-    pub fn from_position(position: &ChessPosition, selected_sq: Option<&ChessSquare>) -> Self {
+    pub fn from_position(position: &ChessPosition, selected: Option<Selection>) -> Self {
         let (chess_board, castling_rights, ep_sq) = if position.side_to_move == Color::White {
             (position.chessboard, position.castling_rights, position.en_passant)
         } else {
@@ -133,7 +177,7 @@ impl NetworkInputs {
             data[768 + square.0 as usize] = 1.0;
         }
 
-        if let Some(square) = selected_sq {
+        if let Some(Selection::Square(square)) = selected {
             data[832
                 + if position.side_to_move == Color::Black {
                     square.square_opposite().0 as usize
@@ -142,21 +186,27 @@ impl NetworkInputs {
                 }] = 1.0;
         }
 
-        let mut meta = [0f32; 5];
-        meta.iter_mut().enumerate().for_each(|(i, meta)| *meta = (castling_rights.0 >> i & 1).into());
+        let mut meta = [0f32; META_SIZE];
+        meta[..4].iter_mut().enumerate().for_each(|(i, meta)| *meta = (castling_rights.bits >> i & 1).into());
         meta[4] = position.halfmove_clock as f32 / 100.0;
+        // the side to move first, like the boards
+        let us = position.side_to_move as usize;
+        meta[5] = position.checks[us] as f32 / 3.0;
+        meta[6] = position.checks[1 - us] as f32 / 3.0;
+        for (i, count) in position.pockets[us].iter().chain(&position.pockets[1 - us]).enumerate() {
+            meta[7 + i] = *count as f32 / 8.0;
+        }
+        if let Some(Selection::Pocket(piece_type)) = selected {
+            meta[17 + piece_type as usize] = 1.0;
+        }
 
         Self { boards: data, meta }
     }
 }
 
 impl NetworkLabels {
-    pub fn as_squares(&self) -> [(ChessSquare, f32); 64] {
-        std::array::from_fn(|i| {
-            let sq = ChessSquare::new(i as u8).unwrap();
-            let val = self.policy[i];
-            (sq, val)
-        })
+    pub fn as_selections(&self) -> [(Selection, f32); POLICY_SIZE] {
+        std::array::from_fn(|i| (Selection::from_index(i).unwrap(), self.policy[i]))
     }
 }
 
@@ -167,7 +217,34 @@ pub struct ChessBatcher {}
 pub struct TrainingSample {
     pub inputs:  NetworkInputs,
     pub targets: NetworkLabels,
-    pub mask:    [bool; 64],
+    pub mask:    [bool; POLICY_SIZE],
+}
+
+impl TrainingSample {
+    // The sample of the position with files a and h swapped. Only valid without castling
+    // rights, castling is the one rule that tells the two wings apart. Pockets stay where they are.
+    pub fn mirrored(&self) -> Option<TrainingSample> {
+        if self.inputs.meta[..4].iter().any(|&right| right != 0.0) {
+            return None;
+        }
+
+        let mut inputs = self.inputs;
+        for (plane, mirrored) in self.inputs.boards.chunks_exact(64).zip(inputs.boards.chunks_exact_mut(64)) {
+            mirror_files(plane, mirrored);
+        }
+        let mut targets = self.targets;
+        mirror_files(&self.targets.policy[..64], &mut targets.policy[..64]);
+        let mut mask = self.mask;
+        mirror_files(&self.mask[..64], &mut mask[..64]);
+
+        Some(TrainingSample { inputs, targets, mask })
+    }
+}
+
+fn mirror_files<T: Copy>(squares: &[T], mirrored: &mut [T]) {
+    for (sq, value) in squares.iter().enumerate() {
+        mirrored[sq ^ 7] = *value;
+    }
 }
 
 impl fmt::Display for TrainingSample {
@@ -183,8 +260,8 @@ impl fmt::Display for TrainingSample {
 #[derive(Clone, Debug)]
 pub struct ChessBatch<B: Backend> {
     pub boards: Tensor<B, 3>,         // Batch x 64 x 14
-    pub metas: Tensor<B, 2>,          // Batch x 5
-    pub policy_targets: Tensor<B, 2>, // Batch x 64
+    pub metas: Tensor<B, 2>,          // Batch x META_SIZE
+    pub policy_targets: Tensor<B, 2>, // Batch x POLICY_SIZE
     pub value_targets: Tensor<B, 2>,  // Batch x 1
     pub loss_ratio: f32,
     pub masks: Tensor<B, 2, Bool>,
@@ -195,10 +272,10 @@ impl<B: Backend> Batcher<B, &TrainingSample, ChessBatch<B>> for ChessBatcher {
         let n = items.len();
 
         let mut boards = Vec::with_capacity(n * 64 * 14);
-        let mut metas = Vec::with_capacity(n * 5);
-        let mut targets = Vec::with_capacity(n * 64);
+        let mut metas = Vec::with_capacity(n * META_SIZE);
+        let mut targets = Vec::with_capacity(n * POLICY_SIZE);
         let mut values = Vec::with_capacity(n * 3);
-        let mut masks = Vec::with_capacity(n * 64);
+        let mut masks = Vec::with_capacity(n * POLICY_SIZE);
 
         for item in items {
             boards.extend_from_slice(&item.inputs.boards);
@@ -209,10 +286,10 @@ impl<B: Backend> Batcher<B, &TrainingSample, ChessBatch<B>> for ChessBatcher {
         }
 
         let board_data = TensorData::new(boards, [n, 64, 14]);
-        let metas_data = TensorData::new(metas, [n, 5]);
-        let pol_target = TensorData::new(targets, [n, 64]);
+        let metas_data = TensorData::new(metas, [n, META_SIZE]);
+        let pol_target = TensorData::new(targets, [n, POLICY_SIZE]);
         let val_target = TensorData::new(values, [n, 3]);
-        let mask = TensorData::new(masks, [n, 64]);
+        let mask = TensorData::new(masks, [n, POLICY_SIZE]);
 
         let boards = Tensor::from_data(board_data, device);
         let metas = Tensor::from_data(metas_data, device);
@@ -226,18 +303,59 @@ impl<B: Backend> Batcher<B, &TrainingSample, ChessBatch<B>> for ChessBatcher {
     }
 }
 
+// A training sample as the replay buffer keeps it, a tenth of the size: the position packed
+// with the piece picked for it, its network inputs are rebuilt when the sample is drawn.
+#[derive(Clone, Debug)]
+pub struct ReplaySample {
+    pub position:  ReplayPosition,
+    pub selection: Option<Selection>,
+    pub targets:   NetworkLabels,
+    pub mask:      [bool; POLICY_SIZE],
+}
+
+// positions that don't pack, like Horde's with more than 32 pieces, keep their inputs
+#[derive(Clone, Debug)]
+pub enum ReplayPosition {
+    Packed(PackedPosition),
+    Inputs(Box<NetworkInputs>),
+}
+
+impl ReplaySample {
+    pub fn new(position: &ChessPosition, selection: Option<Selection>, targets: NetworkLabels, mask: [bool; POLICY_SIZE]) -> Self {
+        let position = match PackedPosition::new(position) {
+            Ok(packed) => ReplayPosition::Packed(packed),
+            Err(_) => ReplayPosition::Inputs(Box::new(NetworkInputs::from_position(position, selection))),
+        };
+        Self { position, selection, targets, mask }
+    }
+
+    pub fn to_sample(&self) -> TrainingSample {
+        let inputs = match &self.position {
+            ReplayPosition::Packed(packed) => packed.network_inputs(self.selection).expect("packed from a valid position"),
+            ReplayPosition::Inputs(inputs) => **inputs,
+        };
+        TrainingSample { inputs, targets: self.targets, mask: self.mask }
+    }
+}
+
 pub struct ReplayBuffer {
     pub capacity: usize,
     pub pointer:  usize,
-    pub buffer:   Vec<TrainingSample>,
+    pub buffer:   Vec<ReplaySample>,
+    pub mirror:   bool, // sample the a-h mirror of positions without castling rights half the time
 }
 
 impl ReplayBuffer {
     pub fn new(capacity: usize) -> Self {
-        Self { capacity, pointer: 0, buffer: Vec::with_capacity(capacity) }
+        Self { capacity, pointer: 0, buffer: Vec::with_capacity(capacity), mirror: false }
+    }
+
+    pub fn with_mirror(mut self, mirror: bool) -> Self {
+        self.mirror = mirror;
+        self
     }
 
-    pub fn push(&mut self, sample: TrainingSample) {
+    pub fn push(&mut self, sample: ReplaySample) {
         if self.buffer.len() < self.capacity {
             self.buffer.push(sample);
         } else {
@@ -249,8 +367,19 @@ impl ReplayBuffer {
     pub fn sample_batch<B: Backend>(&self, batch_size: usize, rng: &mut SmallRng, device: &B::Device) -> ChessBatch<B> {
         assert!(self.buffer.len() >= batch_size);
 
-        let samples: Vec<&TrainingSample> = self.buffer.sample(rng, batch_size).collect();
+        let samples: Vec<TrainingSample> = self.buffer.sample(rng, batch_size).map(ReplaySample::to_sample).collect();
         let batcher = ChessBatcher {};
-        batcher.batch(samples, device)
+        if !self.mirror {
+            return batcher.batch(samples.iter().collect(), device);
+        }
+
+        let augmented: Vec<TrainingSample> = samples
+            .into_iter()
+            .map(|sample| match sample.mirrored() {
+                Some(mirrored) if rng.random_bool(0.5) => mirrored,
+                _ => sample,
+            })
+            .collect();
+        batcher.batch(augmented.iter().collect(), device)
     }
 }
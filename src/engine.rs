@@ -14,7 +14,7 @@ use burn::{
 };
 use log::{info, trace};
 use rand::TryRng;
-use rand::seq::{IndexedRandom, IteratorRandom};
+use rand::seq::IndexedRandom;
 use rand::{SeedableRng, rngs::SmallRng};
 use rayon::iter::IntoParallelRefMutIterator;
 use rayon::prelude::*;
@@ -23,11 +23,13 @@ use std::io::{self, Write};
 use std::path::PathBuf;
 use std::time::Instant;
 
-use crate::{ChessBatcher, Color, Stockfish, TrainingSample};
+use crate::{
+    BookWriter, ChessBatcher, Color, DrawRules, PackError, PackedGame, PolyglotBook, Ruleset, Stockfish, TrainingSample, Variant, XorShift64,
+};
 use crate::{
     ChessGame, ChessTransformer, Mcts, MctsConfig, ReplayBuffer,
-    chess_game::Outcome,
-    data::{ChessBatch, NetworkInputs, NetworkLabels},
+    chess_game::{Outcome, Termination},
+    data::{ChessBatch, META_SIZE, NetworkInputs, NetworkLabels, POLICY_SIZE},
     expand_batch,
     model::ChessTransformerConfig,
 };
@@ -36,7 +38,8 @@ use crate::{
 pub struct TrainingConfig {
     pub model: ChessTransformerConfig,
     pub masked: bool,
-    pub legal: bool,
+    pub ruleset: Ruleset,
+    pub draw_rules: DrawRules,
     pub annealing: bool,
     pub scheduler: NoamLrSchedulerConfig,
     pub optimizer: AdamWConfig,
@@ -48,6 +51,41 @@ pub struct TrainingConfig {
     pub batch_size: usize,
     #[config(default = 1234)]
     pub seed: u64,
+    #[config(default = false)]
+    pub chess960: bool,
+    // plies played from the global opening book before self-play takes over
+    #[config(default = 0)]
+    pub book_plies: usize,
+    // root visit counts of this many plies of every game are written to book.bin next to the model
+    #[config(default = 0)]
+    pub export_book_plies: usize,
+    // train on the a-h mirror of positions without castling rights half the time
+    #[config(default = false)]
+    pub mirror: bool,
+    // self-play games start from the variant's start position, Chess960 only goes with standard chess
+    #[config(default = "Variant::Standard")]
+    pub variant: Variant,
+}
+
+// self-play starts from a random Chess960 position when enabled, the variant's start otherwise,
+// followed by book moves while the book knows the position. Books only hold standard chess.
+fn start_game(config: &TrainingConfig, rng: &mut XorShift64) -> ChessGame {
+    let mut game = if config.chess960 {
+        ChessGame::chess960((rng.next() % 960) as usize)
+    } else {
+        ChessGame::from_variant(config.variant)
+    };
+    if config.variant == Variant::Standard
+        && let Some(book) = PolyglotBook::global()
+    {
+        for _ in 0..config.book_plies {
+            let Some(mov) = book.weighted_move(&game.position, rng) else {
+                break;
+            };
+            game.make_move(&mov);
+        }
+    }
+    game
 }
 
 pub fn model_make_outputs<B: Backend>(
@@ -61,13 +99,13 @@ pub fn model_make_outputs<B: Backend>(
     let (boards, metas) = inputs_to_tensor(inputs, device);
     let (mut policies, mut values) = model.forward(boards, metas);
 
-    let mask_data = TensorData::new(masks, [batch_size, 64]);
+    let mask_data = TensorData::new(masks, [batch_size, POLICY_SIZE]);
     let mask = Tensor::<B, 2, Bool>::from_data(mask_data, device);
     policies = policies.clone().mask_fill(mask.bool_not(), -1e9);
 
     policies = softmax(policies, 1);
     values = softmax(values, 1);
-    // batch_size x 64
+    // batch_size x POLICY_SIZE
     let policies: Tensor<B, 1> = policies.flatten(0, 1);
     let policies = policies.into_data().to_vec::<f32>().unwrap();
 
@@ -78,7 +116,7 @@ pub fn model_make_outputs<B: Backend>(
     let mut out: Vec<NetworkLabels> = Vec::with_capacity(batch_size);
 
     for i in 0..batch_size {
-        let policy: [f32; 64] = policies[(i * 64)..(i * 64 + 64)].try_into().unwrap();
+        let policy: [f32; POLICY_SIZE] = policies[(i * POLICY_SIZE)..((i + 1) * POLICY_SIZE)].try_into().unwrap();
         let value: [f32; 3] = values[(i * 3)..(i * 3 + 3)].try_into().unwrap();
         out.push(NetworkLabels { policy, value });
     }
@@ -90,7 +128,7 @@ pub fn inputs_to_tensor<B: Backend>(buffer: &Vec<NetworkInputs>, device: &B::Dev
     let n = buffer.len();
 
     let mut boards = Vec::with_capacity(n * 64 * 14);
-    let mut metas = Vec::with_capacity(n * 5);
+    let mut metas = Vec::with_capacity(n * META_SIZE);
 
     for item in buffer {
         boards.extend_from_slice(&item.boards);
@@ -101,7 +139,7 @@ pub fn inputs_to_tensor<B: Backend>(buffer: &Vec<NetworkInputs>, device: &B::Dev
     let board_data = TensorData::new(boards, shape);
     let t1 = Tensor::from_data(board_data, device);
 
-    let shape = [n, 5];
+    let shape = [n, META_SIZE];
     let meta_data = TensorData::new(metas, shape);
     let t2 = Tensor::from_data(meta_data, device);
 
@@ -151,11 +189,15 @@ pub fn play<B: AutodiffBackend>(path_arg: &PathBuf, mcts_config: &MctsConfig, tr
     }
     B::seed(device, training_config.seed);
 
-    let mut replay_buffer = ReplayBuffer::new(524288);
-    let mut games = vec![ChessGame::default(); training_config.batch_size];
+    let mut replay_buffer = ReplayBuffer::new(524288).with_mirror(training_config.mirror);
+    let mut start_rng = XorShift64::new(training_config.seed);
+    let mut games: Vec<ChessGame> = (0..training_config.batch_size).map(|_| start_game(training_config, &mut start_rng)).collect();
     let mut mctss: Vec<Mcts> = games.iter().map(|game| Mcts::from_game(game, 16384, *mcts_config, rng.try_next_u64().unwrap())).collect();
     let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+    let mut book_writer = BookWriter::new(training_config.export_book_plies);
 
+    // finished self-play games, one packed record after another
+    let games_path = artifact_dir.join("games.bin");
     let csv_path = format!("{}/metrics.csv", artifact_dir.to_str().unwrap_or("./tmp"));
     let mut csv_file = OpenOptions::new().create(true).append(true).open(&csv_path).unwrap();
     if std::fs::metadata(&csv_path).unwrap().len() == 0 {
@@ -175,26 +217,33 @@ pub fn play<B: AutodiffBackend>(path_arg: &PathBuf, mcts_config: &MctsConfig, tr
         let mut illegal_move_weight: f64 = 0.0;
 
         for _ in 0..training_config.steps_per_iter {
-            let (total_length, win, draw, new_games): (f32, f32, f32, u32) = games
+            let finished: Vec<(f32, f32, f32, Result<PackedGame, PackError>)> = games
                 .par_iter_mut()
                 .zip(mctss.par_iter_mut())
-                .map(|(game, mcts)| {
-                    if let Outcome::Finished(color) = game.check_game_state(training_config.legal) {
-                        let length = game.game_history.len() as f32;
-                        let (win, draw) = if color.is_none() { (0.0, 1.0) } else { (1.0, 0.0) };
-                        let new_game = 1;
-                        *game = ChessGame::default();
-                        mcts.refresh(game);
-                        return (length, win, draw, new_game);
-                    }
-                    (0.0, 0.0, 0.0, 0)
+                .filter_map(|(game, mcts)| {
+                    let outcome = game.check_game_state(training_config.ruleset, &training_config.draw_rules);
+                    let Outcome::Finished(color, termination) = outcome else {
+                        return None;
+                    };
+                    let length = game.history.len() as f32;
+                    info!("Game over after {} plies by {}", game.move_list.len(), termination);
+                    let (win, draw) = if color.is_none() { (0.0, 1.0) } else { (1.0, 0.0) };
+                    let packed = PackedGame::new(game).map(|packed| PackedGame { outcome, ..packed });
+                    *game = start_game(training_config, &mut mcts.rng);
+                    mcts.refresh(game);
+                    Some((length, win, draw, packed))
                 })
-                .reduce(|| (0.0, 0.0, 0.0, 0), |a, b| (a.0 + b.0, a.1 + b.1, a.2 + b.2, a.3 + b.3));
+                .collect();
 
-            games_started += new_games;
-            average_game_length += total_length;
-            wins += win;
-            draws += draw;
+            for (length, win, draw, packed) in finished {
+                games_started += 1;
+                average_game_length += length;
+                wins += win;
+                draws += draw;
+                if let Err(err) = packed.map_err(io::Error::other).and_then(|packed| packed.append(&games_path)) {
+                    eprintln!("failed to log game: {}", err);
+                }
+            }
 
             for _count in 0..mcts_config.num_simulations {
                 mctss.par_iter_mut().for_each(|mcts| {
@@ -211,27 +260,39 @@ pub fn play<B: AutodiffBackend>(path_arg: &PathBuf, mcts_config: &MctsConfig, tr
                 .zip(games.par_iter_mut())
                 .map(|(mcts, game)| {
                     let sample = mcts.make_targets(training_config.masked);
+                    let visits = if game.move_list.len() < training_config.export_book_plies && training_config.variant == Variant::Standard {
+                        mcts.root_move_visits().map(|visits| (game.position.clone(), visits))
+                    } else {
+                        None
+                    };
                     if let Some(mov) = mcts.get_move_to_play() {
-                        game.make_move(&mov);
+                        game.make_packed(mov);
                         info!("\n{}", game.position);
-                        trace!("\nSelected move: {}", &mov.to_uci());
+                        trace!("\nSelected move: {}", mov.to_uci());
                     };
                     // scale draw threshold down after 60 moves
-                    let draw_threshold = if game.game_history.len() > 60 { 0.75 } else { 0.95 };
-                    if sample.1[1] > draw_threshold || game.game_history.len() > 400 {
+                    let draw_threshold = if game.history.len() > 60 { 0.75 } else { 0.95 };
+                    if sample.1[1] > draw_threshold || game.history.len() > 400 {
                         // sample.1 is root value after search, just restart.
-                        game.position.halfmove_clock = 200;
+                        game.outcome = Outcome::Finished(None, Termination::Adjudication);
+                    } else if training_config.ruleset.uses_tablebases()
+                        && let Some(wdl) = game.position.tablebase_wdl()
+                    {
+                        game.outcome = wdl.outcome(game.position.side_to_move);
                     }
                     mcts.add_dirichlet_noise(mcts.root);
-                    sample
+                    (sample, visits)
                 })
                 .collect();
 
-            for (sample, _) in new_samples {
+            for ((sample, _), visits) in new_samples {
                 if let Some(sample) = sample {
-                    trace!("{}", sample);
+                    trace!("{}", sample.to_sample());
                     replay_buffer.push(sample);
                 }
+                if let Some((position, visits)) = visits {
+                    book_writer.add_visits(&position, &visits);
+                }
             }
 
             trace!("Replay Buffer size: {}", replay_buffer.buffer.len());
@@ -269,31 +330,37 @@ pub fn play<B: AutodiffBackend>(path_arg: &PathBuf, mcts_config: &MctsConfig, tr
         let mut total_batch_acpl = 0.0;
         let mut valid_games = 0;
 
+        // Stockfish only evaluates standard chess
         for game in games.iter() {
-            if game.move_list.is_empty() || matches!(game.check_game_state(training_config.legal), Outcome::Finished(_)){
+            if game.move_list.is_empty()
+                || training_config.variant != Variant::Standard
+                || matches!(game.check_game_state(training_config.ruleset, &training_config.draw_rules), Outcome::Finished(..))
+            {
                 continue;
             }
 
             let mut game_acpl_sum = 0;
             let mut moves_in_game = 0;
 
-            let mut prev_cp = Stockfish::with_global(|sf| sf.get_eval(&game.game_history[0]));
+            let mut prev_cp = Stockfish::with_global(|sf| sf.get_eval(&game.start_position));
 
-            for (i, mov) in game.move_list.iter().enumerate() {
-                let position_before = &game.game_history[i];
-                let position_after = &game.game_history[i + 1];
+            // the history only keeps hashes, the positions are replayed from the start
+            let mut position_after = game.start_position.clone();
+            for mov in game.move_list.iter() {
+                let position_before = position_after.clone();
+                position_after.make_move(mov);
 
                 let cp_loss = if !position_before.is_legal(mov) {
                     info!("acpl loss: 1000");
                     1000
                 } else {
-                    let current_cp = Stockfish::with_global(|sf| sf.get_eval(position_after));
+                    let current_cp = Stockfish::with_global(|sf| sf.get_eval(&position_after));
 
-                    let loss = (prev_cp as u32+ current_cp as u32).clamp(0, 1000);
+                    let loss = (prev_cp as u32 + current_cp as u32).clamp(0, 1000);
 
                     prev_cp = current_cp;
                     info!("acpl loss: {}", loss);
-                    loss 
+                    loss
                 };
 
                 game_acpl_sum += cp_loss;
@@ -331,7 +398,7 @@ pub fn play<B: AutodiffBackend>(path_arg: &PathBuf, mcts_config: &MctsConfig, tr
             let model_path = format!("{}/model", artifact_dir.to_str().unwrap());
             let optim_path = format!("{}/optim", artifact_dir.to_str().unwrap());
 
-            info!("Saving model snapshot at: {}", &model_path);
+            info!("Saving model snapshot at: {}", model_path);
 
             if let Err(err) = model.clone().save_file(model_path, &recorder) {
                 eprintln!("failed to save model: {}", err);
@@ -340,6 +407,13 @@ pub fn play<B: AutodiffBackend>(path_arg: &PathBuf, mcts_config: &MctsConfig, tr
             if let Err(err) = recorder.record(optimizer.to_record(), optim_path.into()) {
                 eprintln!("failed to save optimiser: {}", err);
             }
+
+            if !book_writer.is_empty() {
+                let book_path = artifact_dir.join("book.bin");
+                if let Err(err) = book_writer.write(&book_path) {
+                    eprintln!("failed to save book: {}", err);
+                }
+            }
         }
 
         iterations += 1;
@@ -417,9 +491,9 @@ pub fn pretrain<B: AutodiffBackend>(
         };
 
         let inputs = NetworkInputs::from_position(&game.position, None);
-        let targets = NetworkLabels { policy: [0.0; 64], value: eval };
+        let targets = NetworkLabels { policy: [0.0; POLICY_SIZE], value: eval };
 
-        let mask = [false; 64];
+        let mask = [false; POLICY_SIZE];
 
         samples.push(TrainingSample { inputs, targets, mask });
     }
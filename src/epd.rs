@@ -0,0 +1,344 @@
+use core::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use burn::prelude::Backend;
+use log::info;
+use serde::Serialize;
+
+use crate::{ChessGame, ChessMove, ChessPosition, ChessTransformer, FenError, MctsConfig, SanError, SearchBudget, TrainingConfig, parse_fen, search};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EpdErrorKind {
+    Io(String),
+    Fen(FenError),
+    UnterminatedString,
+    InvalidClock(String),
+    InvalidMove { opcode: String, error: SanError },
+}
+
+// line is 1-based, 0 when the file could not be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EpdError {
+    pub line: usize,
+    pub kind: EpdErrorKind,
+}
+
+impl fmt::Display for EpdErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EpdErrorKind::Io(e) => write!(f, "io error: {}", e),
+            EpdErrorKind::Fen(e) => write!(f, "invalid position: {}", e),
+            EpdErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            EpdErrorKind::InvalidClock(found) => write!(f, "invalid move counter '{}'", found),
+            EpdErrorKind::InvalidMove { opcode, error } => write!(f, "{} operand: {}", opcode, error),
+        }
+    }
+}
+
+impl fmt::Display for EpdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl std::error::Error for EpdError {}
+
+// The four position fields of a FEN followed by operations, each an opcode with its operands
+// and a closing semicolon. Operands keep their quotes stripped and appear in file order.
+#[derive(Debug, Clone)]
+pub struct EpdRecord {
+    pub position:   ChessPosition,
+    pub operations: Vec<(String, Vec<String>)>,
+}
+
+impl EpdRecord {
+    pub fn parse(line: &str) -> Result<Self, EpdErrorKind> {
+        let mut rest = line.trim();
+        let mut fields = Vec::with_capacity(4);
+        for _ in 0..4 {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            fields.push(&rest[..end]);
+            rest = rest[end..].trim_start();
+        }
+        let operations = parse_operations(rest)?;
+
+        // the clocks are operations in EPD, hmvc and fmvn
+        let clock = |opcode: &str, default: &str| -> Result<String, EpdErrorKind> {
+            match operations.iter().find(|(op, _)| op == opcode).and_then(|(_, operands)| operands.first()) {
+                Some(value) if value.parse::<u32>().is_err() => Err(EpdErrorKind::InvalidClock(value.clone())),
+                Some(value) => Ok(value.clone()),
+                None => Ok(default.to_string()),
+            }
+        };
+        let fen = format!("{} {} {}", fields.join(" "), clock("hmvc", "0")?, clock("fmvn", "1")?);
+        let position = parse_fen(&fen).map_err(EpdErrorKind::Fen)?;
+
+        let record = Self { position, operations };
+        for opcode in ["bm", "am", "pm"] {
+            record.moves(opcode)?;
+        }
+        Ok(record)
+    }
+
+    pub fn operands(&self, opcode: &str) -> Option<&[String]> {
+        self.operations.iter().find(|(op, _)| op == opcode).map(|(_, operands)| operands.as_slice())
+    }
+
+    pub fn operand(&self, opcode: &str) -> Option<&str> {
+        self.operands(opcode)?.first().map(String::as_str)
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.operand("id")
+    }
+
+    // the c0 to c9 comments
+    pub fn comment(&self, index: usize) -> Option<&str> {
+        self.operand(&format!("c{}", index))
+    }
+
+    // the SAN operands of a move opcode as legal moves
+    pub fn moves(&self, opcode: &str) -> Result<Vec<ChessMove>, EpdErrorKind> {
+        let operands = self.operands(opcode).unwrap_or_default();
+        operands
+            .iter()
+            .map(|san| ChessMove::from_san(san, &self.position).map_err(|error| EpdErrorKind::InvalidMove { opcode: opcode.to_string(), error }))
+            .collect()
+    }
+
+    pub fn best_moves(&self) -> Vec<ChessMove> {
+        self.moves("bm").unwrap_or_default()
+    }
+
+    pub fn avoid_moves(&self) -> Vec<ChessMove> {
+        self.moves("am").unwrap_or_default()
+    }
+
+    // Whether a move solves the record: it must be one of the bm moves and none of the am
+    // moves. None for records that have neither.
+    pub fn solved_by(&self, mov: Option<&ChessMove>) -> Option<bool> {
+        let (best, avoid) = (self.best_moves(), self.avoid_moves());
+        if best.is_empty() && avoid.is_empty() {
+            return None;
+        }
+        let Some(mov) = mov else {
+            return Some(false);
+        };
+        Some((best.is_empty() || best.contains(mov)) && !avoid.contains(mov))
+    }
+
+    pub fn to_epd(&self) -> String {
+        let fen = self.position.to_fen();
+        let mut epd: String = fen.split(' ').take(4).collect::<Vec<_>>().join(" ");
+        for (opcode, operands) in &self.operations {
+            epd.push(' ');
+            epd.push_str(opcode);
+            for operand in operands {
+                if operand.is_empty() || operand.contains([' ', ';', '"']) {
+                    epd.push_str(&format!(" \"{}\"", operand.replace('"', "")));
+                } else {
+                    epd.push(' ');
+                    epd.push_str(operand);
+                }
+            }
+            epd.push(';');
+        }
+        epd
+    }
+}
+
+fn parse_operations(text: &str) -> Result<Vec<(String, Vec<String>)>, EpdErrorKind> {
+    let mut operations = Vec::new();
+    let mut tokens: Vec<String> = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    loop {
+        match chars.next() {
+            None | Some(';') => {
+                if !tokens.is_empty() {
+                    let opcode = tokens.remove(0);
+                    operations.push((opcode, std::mem::take(&mut tokens)));
+                }
+                if chars.peek().is_none() {
+                    return Ok(operations);
+                }
+            }
+            Some(c) if c.is_whitespace() => {}
+            Some('"') => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => string.push(c),
+                        None => return Err(EpdErrorKind::UnterminatedString),
+                    }
+                }
+                tokens.push(string);
+            }
+            Some(c) => {
+                let mut token = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == ';' {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+                tokens.push(token);
+            }
+        }
+    }
+}
+
+// A file of EPD records, named after the file. Blank lines and lines starting with # are skipped.
+#[derive(Debug, Clone)]
+pub struct EpdSuite {
+    pub name:    String,
+    pub records: Vec<EpdRecord>,
+}
+
+impl EpdSuite {
+    pub fn parse(name: &str, text: &str) -> Result<Self, EpdError> {
+        let mut records = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            records.push(EpdRecord::parse(line).map_err(|kind| EpdError { line: i + 1, kind })?);
+        }
+        Ok(Self { name: name.to_string(), records })
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, EpdError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| EpdError { line: 0, kind: EpdErrorKind::Io(e.to_string()) })?;
+        let name = path.file_stem().map_or("suite".into(), |stem| stem.to_string_lossy());
+        Self::parse(&name, &text)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EpdResult {
+    pub id:     String,
+    pub fen:    String,
+    pub played: Option<String>,
+    pub best:   Vec<String>,
+    pub avoid:  Vec<String>,
+    pub solved: Option<bool>, // None when the record has no bm or am
+    pub nodes:  usize,
+    pub millis: u64,
+}
+
+impl fmt::Display for EpdResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self.solved {
+            Some(true) => "ok",
+            Some(false) => "FAILED",
+            None => "unscored",
+        };
+        let played = self.played.as_deref().unwrap_or("none");
+        write!(f, "{}: played {}", self.id, played)?;
+        if !self.best.is_empty() {
+            write!(f, ", bm {}", self.best.join(" "))?;
+        }
+        if !self.avoid.is_empty() {
+            write!(f, ", am {}", self.avoid.join(" "))?;
+        }
+        write!(f, " ({} nodes, {} ms) ... {}", self.nodes, self.millis, status)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SuiteReport {
+    pub suite:   String,
+    pub results: Vec<EpdResult>,
+}
+
+impl SuiteReport {
+    pub fn solved(&self) -> usize {
+        self.results.iter().filter(|result| result.solved == Some(true)).count()
+    }
+
+    pub fn scored(&self) -> usize {
+        self.results.iter().filter(|result| result.solved.is_some()).count()
+    }
+
+    pub fn to_json(reports: &[SuiteReport]) -> String {
+        serde_json::to_string_pretty(reports).expect("reports serialize")
+    }
+
+    // one row per position
+    pub fn to_csv(reports: &[SuiteReport]) -> String {
+        let mut csv = String::from("suite,id,fen,played,best,avoid,solved,nodes,millis\n");
+        for report in reports {
+            for result in &report.results {
+                let solved = result.solved.map_or(String::new(), |solved| solved.to_string());
+                let row = [
+                    report.suite.clone(),
+                    result.id.clone(),
+                    result.fen.clone(),
+                    result.played.clone().unwrap_or_default(),
+                    result.best.join(" "),
+                    result.avoid.join(" "),
+                    solved,
+                    result.nodes.to_string(),
+                    result.millis.to_string(),
+                ];
+                csv.push_str(&row.map(|field| csv_field(&field)).join(","));
+                csv.push('\n');
+            }
+        }
+        csv
+    }
+}
+
+impl fmt::Display for SuiteReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scored = self.scored();
+        let percent = if scored == 0 { 0.0 } else { 100.0 * self.solved() as f64 / scored as f64 };
+        write!(f, "{}: {}/{} solved ({:.1}%)", self.suite, self.solved(), scored, percent)
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// Searches every position of a suite and checks the move played against its bm and am moves.
+pub fn run_epd_suite<B: Backend>(
+    suite: &EpdSuite,
+    model: &ChessTransformer<B>,
+    mcts_config: MctsConfig,
+    config: &TrainingConfig,
+    budget: SearchBudget,
+    device: &B::Device,
+) -> SuiteReport {
+    let mut results = Vec::with_capacity(suite.records.len());
+    for (i, record) in suite.records.iter().enumerate() {
+        let before = Instant::now();
+        let game = ChessGame::from_position(record.position.clone());
+        let (played, nodes) = search(&game, model, mcts_config, config, budget, device);
+        let san = |moves: Vec<ChessMove>| moves.iter().map(|mov| mov.to_san(&record.position)).collect();
+
+        let result = EpdResult {
+            id: record.id().map_or_else(|| format!("{} #{}", suite.name, i + 1), str::to_string),
+            fen: record.position.to_fen(),
+            played: played.map(|mov| mov.to_san(&record.position)),
+            best: san(record.best_moves()),
+            avoid: san(record.avoid_moves()),
+            solved: record.solved_by(played.as_ref()),
+            nodes,
+            millis: before.elapsed().as_millis() as u64,
+        };
+        info!("{}", result);
+        results.push(result);
+    }
+    SuiteReport { suite: suite.name.clone(), results }
+}
@@ -0,0 +1,309 @@
+use core::fmt;
+
+use crate::builder::{plausible_en_passant, validate_material};
+use crate::{Bitboard, CastlingRights, ChessBoard, ChessPiece, ChessPosition, ChessSquare, Color, PieceType, PositionBuilder, Variant};
+
+// Every index is a byte offset into the original FEN string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FenError {
+    MissingField(&'static str),
+    TooManyFields { index: usize },
+    InvalidPiece { index: usize, found: char },
+    RankTooLong { index: usize, rank: u8 },
+    RankTooShort { index: usize, rank: u8 },
+    WrongRankCount { index: usize, ranks: usize },
+    InvalidSideToMove { index: usize, found: String },
+    InvalidCastling { index: usize, found: char },
+    DuplicateCastling { index: usize, found: char },
+    CastlingWithoutPieces { index: usize, found: char },
+    InvalidEnPassant { index: usize, found: String },
+    ImplausibleEnPassant { index: usize, square: ChessSquare },
+    InvalidClock { index: usize, found: String },
+    InvalidChecks { index: usize, found: String },
+    KingCount { color: Color, count: u32 },
+    TooManyPieces { color: Color, count: u32 },
+    TooManyPawns { color: Color, count: u32 },
+    PawnOnBackRank { square: ChessSquare },
+    OpponentInCheck { color: Color },
+    CastlingRightWithoutPieces { square: ChessSquare },
+    EnPassantWithoutPawn { square: ChessSquare },
+    TooManyPocketPieces { count: u32 },
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FenError::MissingField(field) => write!(f, "FEN missing {}", field),
+            FenError::TooManyFields { index } => write!(f, "unexpected extra field at {}", index),
+            FenError::InvalidPiece { index, found } => write!(f, "invalid piece '{}' at {}", found, index),
+            FenError::RankTooLong { index, rank } => write!(f, "rank {} has more than 8 squares (at {})", rank + 1, index),
+            FenError::RankTooShort { index, rank } => write!(f, "rank {} has fewer than 8 squares (at {})", rank + 1, index),
+            FenError::WrongRankCount { index, ranks } => write!(f, "board has {} ranks instead of 8 (at {})", ranks, index),
+            FenError::InvalidSideToMove { index, found } => write!(f, "invalid side to move '{}' at {}", found, index),
+            FenError::InvalidCastling { index, found } => write!(f, "invalid castling right '{}' at {}", found, index),
+            FenError::DuplicateCastling { index, found } => write!(f, "duplicate castling right '{}' at {}", found, index),
+            FenError::CastlingWithoutPieces { index, found } => {
+                write!(f, "castling right '{}' at {} has no king or rook on its home square", found, index)
+            }
+            FenError::InvalidEnPassant { index, found } => write!(f, "invalid en passant square '{}' at {}", found, index),
+            FenError::ImplausibleEnPassant { index, square } => {
+                write!(f, "en passant square {} at {} does not follow a double pawn push", square, index)
+            }
+            FenError::InvalidClock { index, found } => write!(f, "invalid move counter '{}' at {}", found, index),
+            FenError::InvalidChecks { index, found } => write!(f, "invalid remaining checks '{}' at {}", found, index),
+            FenError::KingCount { color, count } => write!(f, "{} has {} kings", color, count),
+            FenError::TooManyPieces { color, count } => write!(f, "{} has {} pieces", color, count),
+            FenError::TooManyPawns { color, count } => write!(f, "{} has {} pawns", color, count),
+            FenError::PawnOnBackRank { square } => write!(f, "pawn on back rank square {}", square),
+            FenError::OpponentInCheck { color } => write!(f, "{} is in check with the other side to move", color),
+            FenError::CastlingRightWithoutPieces { square } => write!(f, "castling right with rook {} has no king or rook in place", square),
+            FenError::EnPassantWithoutPawn { square } => write!(f, "en passant square {} does not follow a double pawn push", square),
+            FenError::TooManyPocketPieces { count } => write!(f, "{} pieces on the board and in the pockets", count),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+// whitespace separated fields with their byte offsets
+fn fields(fen: &str) -> impl Iterator<Item = (usize, &str)> {
+    fen.split_ascii_whitespace().map(move |field| (field.as_ptr() as usize - fen.as_ptr() as usize, field))
+}
+
+pub fn parse_board(board_str: &str, offset: usize) -> Result<ChessBoard, FenError> {
+    parse_placement(board_str, offset, None)
+}
+
+// the board with Crazyhouse's ~ after promoted pieces when promoted is given
+fn parse_placement(board_str: &str, offset: usize, mut promoted: Option<&mut Bitboard>) -> Result<ChessBoard, FenError> {
+    let mut chessboard = ChessBoard::empty();
+    let ranks: Vec<&str> = board_str.split('/').collect();
+    if ranks.len() != 8 {
+        return Err(FenError::WrongRankCount { index: offset, ranks: ranks.len() });
+    }
+
+    let mut index = offset;
+    for (i, rank_str) in ranks.iter().enumerate() {
+        let rank = 7 - i as u8;
+        let mut file: u8 = 0;
+
+        for c in rank_str.chars() {
+            match c {
+                '1'..='8' => file += c as u8 - b'0',
+                '~' if file > 0 && promoted.is_some() => {
+                    let square = ChessSquare::from_coords(file - 1, rank).unwrap();
+                    if chessboard.get_piece_at(square).is_none() {
+                        return Err(FenError::InvalidPiece { index, found: c });
+                    }
+                    promoted.as_deref_mut().unwrap().set(square);
+                }
+                _ => {
+                    let piece_type = PieceType::from_char(c).ok_or(FenError::InvalidPiece { index, found: c })?;
+                    let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+                    let square = ChessSquare::from_coords(file, rank).ok_or(FenError::RankTooLong { index, rank })?;
+                    chessboard.add_piece(ChessPiece::new(color, piece_type), square);
+                    file += 1;
+                }
+            }
+            if file > 8 {
+                return Err(FenError::RankTooLong { index, rank });
+            }
+            index += c.len_utf8();
+        }
+
+        if file < 8 {
+            return Err(FenError::RankTooShort { index, rank });
+        }
+        // the '/'
+        index += 1;
+    }
+
+    Ok(chessboard)
+}
+
+// KQkq pick the outermost rook on that wing (X-FEN), rook file letters name the rook directly
+// (Shredder-FEN, and X-FEN when the outermost rook is not the castling one).
+pub(crate) fn parse_castling(castling_str: &str, offset: usize, board: &ChessBoard) -> Result<CastlingRights, FenError> {
+    let mut rights = CastlingRights::empty();
+    if castling_str == "-" {
+        return Ok(rights);
+    }
+
+    for (i, c) in castling_str.char_indices() {
+        let index = offset + i;
+        let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+        let rank = if color == Color::White { 0 } else { 7 };
+        let missing = FenError::CastlingWithoutPieces { index, found: c };
+
+        let king_sq = board.get_piece_bitboard(color, PieceType::King).lsb_square().ok_or(missing.clone())?;
+        if king_sq.rank() != rank {
+            return Err(missing);
+        }
+        let rook_files: Vec<u8> =
+            (0..8).filter(|&file| board.get_piece_bitboard(color, PieceType::Rook).is_set(ChessSquare::from_coords(file, rank).unwrap())).collect();
+
+        let rook_file = match c.to_ascii_lowercase() {
+            'k' => rook_files.iter().rev().find(|&&file| file > king_sq.file()).copied(),
+            'q' => rook_files.iter().find(|&&file| file < king_sq.file()).copied(),
+            'a'..='h' => {
+                let file = c.to_ascii_lowercase() as u8 - b'a';
+                rook_files.contains(&file).then_some(file)
+            }
+            _ => return Err(FenError::InvalidCastling { index, found: c }),
+        }
+        .ok_or(missing)?;
+
+        let (kingside, queenside) = CastlingRights::for_color(color);
+        let right = if rook_file > king_sq.file() { kingside } else { queenside };
+        if rights.has(right) {
+            return Err(FenError::DuplicateCastling { index, found: c });
+        }
+        rights |= CastlingRights::with_rook_file(right, rook_file);
+    }
+
+    Ok(rights)
+}
+
+fn parse_en_passant(ep_str: &str, offset: usize, board: &ChessBoard, side_to_move: Color) -> Result<Option<ChessSquare>, FenError> {
+    if ep_str == "-" {
+        return Ok(None);
+    }
+    let square = ChessSquare::from_name(ep_str).ok_or_else(|| FenError::InvalidEnPassant { index: offset, found: ep_str.to_string() })?;
+
+    if !plausible_en_passant(board, side_to_move, square) {
+        return Err(FenError::ImplausibleEnPassant { index: offset, square });
+    }
+    Ok(Some(square))
+}
+
+fn parse_clock(field: Option<(usize, &str)>, default: u32) -> Result<u32, FenError> {
+    match field {
+        None => Ok(default),
+        Some((index, s)) => s.parse().map_err(|_| FenError::InvalidClock { index, found: s.to_string() }),
+    }
+}
+
+// Crazyhouse pockets follow the board in brackets, rnbqkbnr/.../RNBQKBNR[Nq], or as a ninth rank
+fn split_pocket(board_str: &str) -> (&str, Option<&str>) {
+    if let Some(board) = board_str.strip_suffix(']')
+        && let Some((board, pocket)) = board.split_once('[')
+    {
+        return (board, Some(pocket));
+    }
+    match board_str.rsplit_once('/') {
+        Some((board, pocket)) if board.matches('/').count() == 7 => (board, Some(pocket)),
+        _ => (board_str, None),
+    }
+}
+
+fn parse_pocket(pocket_str: &str, offset: usize) -> Result<[[u8; 5]; 2], FenError> {
+    let mut pockets = [[0; 5]; 2];
+    for (i, c) in pocket_str.char_indices() {
+        let piece_type = PieceType::from_char(c).filter(|&piece_type| piece_type != PieceType::King);
+        let Some(piece_type) = piece_type else {
+            return Err(FenError::InvalidPiece { index: offset + i, found: c });
+        };
+        let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+        pockets[color as usize][piece_type as usize] += 1;
+    }
+    Ok(pockets)
+}
+
+// the checks white and black still need in Three-check, 3+3 at the start
+fn parse_checks(field: (usize, &str)) -> Result<[u8; 2], FenError> {
+    let (index, s) = field;
+    let invalid = || FenError::InvalidChecks { index, found: s.to_string() };
+    let (white, black) = s.split_once('+').ok_or_else(invalid)?;
+    let given = |remaining: &str| remaining.parse::<u8>().ok().filter(|&remaining| remaining <= 3).map(|remaining| 3 - remaining);
+    Ok([given(white).ok_or_else(invalid)?, given(black).ok_or_else(invalid)?])
+}
+
+// The board, side, castling and en passant fields are required, the two clocks default to 0 and 1.
+pub fn parse_fen(fen: &str) -> Result<ChessPosition, FenError> {
+    parse_variant_fen(fen, Variant::Standard)
+}
+
+// A FEN of a variant, which decides how much material is valid. Three-check adds the checks
+// each side still needs after the en passant square, as in 2+3, and counts none given without it.
+// Crazyhouse adds the pockets to the board and marks promoted pieces with ~, as in Q~.
+pub fn parse_variant_fen(fen: &str, variant: Variant) -> Result<ChessPosition, FenError> {
+    let mut parts = fields(fen).peekable();
+    let (board_idx, board_str) = parts.next().ok_or(FenError::MissingField("board"))?;
+    let (side_idx, side_str) = parts.next().ok_or(FenError::MissingField("side to move"))?;
+    let (castling_idx, castling_str) = parts.next().ok_or(FenError::MissingField("castling rights"))?;
+    let (ep_idx, ep_str) = parts.next().ok_or(FenError::MissingField("en passant square"))?;
+    let checks = match parts.next_if(|(_, field)| variant == Variant::ThreeCheck && field.contains('+')) {
+        Some(field) => parse_checks(field)?,
+        None => [0, 0],
+    };
+    let halfmove_clock = parse_clock(parts.next(), 0)?;
+    let fullmove_counter = parse_clock(parts.next(), 1)?;
+    if let Some((index, _)) = parts.next() {
+        return Err(FenError::TooManyFields { index });
+    }
+
+    let mut promoted = Bitboard::EMPTY;
+    let mut pockets = [[0; 5]; 2];
+    let chessboard = if variant == Variant::Crazyhouse {
+        let (board_str, pocket_str) = split_pocket(board_str);
+        if let Some(pocket_str) = pocket_str {
+            pockets = parse_pocket(pocket_str, board_idx + board_str.len() + 1)?;
+        }
+        parse_placement(board_str, board_idx, Some(&mut promoted))?
+    } else {
+        parse_board(board_str, board_idx)?
+    };
+    validate_material(&chessboard, variant)?;
+
+    let side_to_move = match side_str {
+        "w" => Color::White,
+        "b" => Color::Black,
+        _ => return Err(FenError::InvalidSideToMove { index: side_idx, found: side_str.to_string() }),
+    };
+
+    let castling_rights = parse_castling(castling_str, castling_idx, &chessboard)?;
+    let en_passant = parse_en_passant(ep_str, ep_idx, &chessboard, side_to_move)?;
+
+    PositionBuilder::from_board(chessboard)
+        .variant(variant)
+        .checks(checks)
+        .pockets(pockets)
+        .promoted(promoted)
+        .side_to_move(side_to_move)
+        .castling_rights(castling_rights)
+        .en_passant(en_passant)
+        .halfmove_clock(halfmove_clock)
+        .fullmove_counter(fullmove_counter)
+        .build_allowing_check()
+}
+
+// Start position by its standard (Scharnagl) number, 518 is the classical setup.
+pub fn chess960_fen(index: usize) -> String {
+    assert!(index < 960, "Chess960 start positions are numbered 0 to 959");
+    let mut back_rank = [None; 8];
+    let mut n = index;
+
+    back_rank[(n % 4) * 2 + 1] = Some('b');
+    n /= 4;
+    back_rank[(n % 4) * 2] = Some('b');
+    n /= 4;
+
+    let mut place = |piece: char, nth_empty: usize| {
+        let file = (0..8).filter(|&file| back_rank[file].is_none()).nth(nth_empty).unwrap();
+        back_rank[file] = Some(piece);
+    };
+    place('q', n % 6);
+    n /= 6;
+
+    const KNIGHTS: [(usize, usize); 10] = [(0, 1), (0, 2), (0, 3), (0, 4), (1, 2), (1, 3), (1, 4), (2, 3), (2, 4), (3, 4)];
+    let (first, second) = KNIGHTS[n];
+    // the second knight goes in after the first has taken its square
+    place('n', first);
+    place('n', second - 1);
+    for piece in ['r', 'k', 'r'] {
+        place(piece, 0);
+    }
+
+    let black: String = back_rank.iter().map(|piece| piece.unwrap()).collect();
+    format!("{}/pppppppp/8/8/8/8/PPPPPPPP/{} w KQkq - 0 1", black, black.to_ascii_uppercase())
+}
@@ -0,0 +1,293 @@
+use core::fmt;
+
+use crate::{
+    ChessGame, ChessMove, ChessPosition, Color, Divide, DrawRules, FenError, Outcome, PieceType, Ruleset, Termination, XorShift64, divide, parse_fen,
+};
+
+// A position as a start FEN and the moves played from it, with the perft depth compared there.
+// Display gives the UCI commands that set it up in another engine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzCase {
+    pub fen:      String,
+    pub chess960: bool,
+    pub moves:    Vec<ChessMove>,
+    pub depth:    u32,
+}
+
+impl FuzzCase {
+    pub fn position(&self) -> Result<ChessPosition, FenError> {
+        let mut position = parse_fen(&self.fen)?;
+        position.chess960 |= self.chess960;
+        for mov in &self.moves {
+            position.make_move(mov);
+        }
+        Ok(position)
+    }
+
+    // the same position started skip moves later
+    fn rebased(&self, skip: usize) -> Result<Self, FenError> {
+        let mut position = parse_fen(&self.fen)?;
+        position.chess960 |= self.chess960;
+        for mov in &self.moves[..skip] {
+            position.make_move(mov);
+        }
+        Ok(Self { fen: position.to_fen(), chess960: position.chess960, moves: self.moves[skip..].to_vec(), depth: self.depth })
+    }
+}
+
+impl fmt::Display for FuzzCase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.chess960 {
+            writeln!(f, "setoption name UCI_Chess960 value true")?;
+        }
+        write!(f, "position fen {}", self.fen)?;
+        if !self.moves.is_empty() {
+            write!(f, " moves")?;
+            for mov in &self.moves {
+                write!(f, " {}", mov.to_uci())?;
+            }
+        }
+        write!(f, "\ngo perft {}", self.depth)
+    }
+}
+
+// a root move whose node count differs, None where one side doesn't have the move at all
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MoveDiff {
+    pub mov: ChessMove,
+    pub ours: Option<u64>,
+    pub reference: Option<u64>,
+}
+
+impl fmt::Display for MoveDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let count = |nodes: Option<u64>| nodes.map_or("missing".to_string(), |nodes| nodes.to_string());
+        write!(f, "{}: ours {}, reference {}", self.mov.to_uci(), count(self.ours), count(self.reference))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FuzzFailureKind {
+    Perft(Vec<MoveDiff>),
+    KingCapture(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzFailure {
+    pub case: FuzzCase,
+    pub kind: FuzzFailureKind,
+}
+
+impl fmt::Display for FuzzFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            FuzzFailureKind::Perft(diffs) => {
+                writeln!(f, "move generation differs from the reference in")?;
+                writeln!(f, "{}", self.case)?;
+                for diff in diffs {
+                    writeln!(f, "{}", diff)?;
+                }
+                Ok(())
+            }
+            FuzzFailureKind::KingCapture(reason) => write!(f, "{} in\n{}", reason, self.case),
+        }
+    }
+}
+
+impl std::error::Error for FuzzFailure {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FuzzConfig {
+    pub games: usize,
+    pub max_plies: usize,
+    pub sample_every: usize, // plies between positions compared with the reference
+    pub depth: u32,          // perft depth compared on top of the legal move set
+    pub ruleset: Ruleset,
+    pub chess960: bool,
+    pub seed: u64,
+}
+
+impl Default for FuzzConfig {
+    fn default() -> Self {
+        Self { games: 100, max_plies: 300, sample_every: 4, depth: 2, ruleset: Ruleset::Standard, chess960: false, seed: 1 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FuzzStats {
+    pub games:     usize,
+    pub plies:     usize,
+    pub positions: usize, // compared with the reference
+}
+
+impl fmt::Display for FuzzStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} games, {} plies, {} positions compared", self.games, self.plies, self.positions)
+    }
+}
+
+// Plays random games and compares the legal moves and a perft divide of sampled positions with
+// a reference, normally Stockfish's `go perft`. King-capture games are also checked at every ply
+// for how they end. The first failure is shrunk to the shallowest depth and fewest moves that
+// still fail.
+pub fn fuzz_movegen<R>(config: &FuzzConfig, mut reference: R) -> Result<FuzzStats, FuzzFailure>
+where
+    R: FnMut(&ChessPosition, u32) -> Divide,
+{
+    let mut rng = XorShift64::new(config.seed);
+    let mut stats = FuzzStats::default();
+    let depths = if config.depth > 1 { vec![1, config.depth] } else { vec![1] };
+
+    for _ in 0..config.games {
+        let mut game = if config.chess960 {
+            ChessGame::chess960((rng.next() % 960) as usize)
+        } else {
+            ChessGame::default()
+        };
+        let case = |game: &ChessGame, depth: u32| FuzzCase {
+            fen: game.start_position.to_fen(),
+            chess960: game.start_position.chess960,
+            moves: game.move_list.clone(),
+            depth,
+        };
+
+        loop {
+            if config.ruleset == Ruleset::KingCapture && king_capture_error(&game.position).is_some() {
+                return Err(shrink(case(&game, 1), |case| {
+                    let position = case.position().ok()?;
+                    king_capture_error(&position).map(FuzzFailureKind::KingCapture)
+                }));
+            }
+
+            if game.move_list.len().is_multiple_of(config.sample_every.max(1)) && reference_can_read(&game.position) {
+                for &depth in &depths {
+                    if !perft_diffs(&game.position, depth, &mut reference).is_empty() {
+                        return Err(shrink(case(&game, depth), |case| {
+                            let position = case.position().ok().filter(reference_can_read)?;
+                            let diffs = perft_diffs(&position, case.depth, &mut reference);
+                            (!diffs.is_empty()).then_some(FuzzFailureKind::Perft(diffs))
+                        }));
+                    }
+                }
+                stats.positions += 1;
+            }
+
+            if game.move_list.len() >= config.max_plies || game.check_game_state(config.ruleset, &DrawRules::AUTOMATIC) != Outcome::Unfinished {
+                break;
+            }
+            let moves = config.ruleset.packed_moves(&game.position);
+            game.make_packed(moves[(rng.next() % moves.len() as u64) as usize]);
+        }
+
+        stats.games += 1;
+        stats.plies += game.move_list.len();
+    }
+    Ok(stats)
+}
+
+// a divide from our side, legal move generation at depth 1 and make/unmake below that
+fn our_divide(position: &ChessPosition, depth: u32) -> Divide {
+    if depth == 1 {
+        Divide { moves: position.generate_legal().iter().map(|&mov| (mov, 1)).collect() }
+    } else {
+        divide(position, depth, false)
+    }
+}
+
+fn perft_diffs<R>(position: &ChessPosition, depth: u32, reference: &mut R) -> Vec<MoveDiff>
+where
+    R: FnMut(&ChessPosition, u32) -> Divide,
+{
+    let (ours, theirs) = (our_divide(position, depth), reference(position, depth));
+    let count = |divide: &Divide, mov: &ChessMove| divide.moves.iter().find(|(other, _)| other == mov).map(|&(_, nodes)| nodes);
+
+    let mut diffs = Vec::new();
+    for (mov, _) in ours.moves.iter().chain(&theirs.moves) {
+        let diff = MoveDiff { mov: *mov, ours: count(&ours, mov), reference: count(&theirs, mov) };
+        if diff.ours != diff.reference && !diffs.contains(&diff) {
+            diffs.push(diff);
+        }
+    }
+    diffs.sort_by_key(|diff| diff.mov.to_uci());
+    diffs
+}
+
+// other engines only take positions with both kings where the side that just moved isn't in check
+fn reference_can_read(position: &ChessPosition) -> bool {
+    let board = &position.chessboard;
+    let them = position.side_to_move.opposite();
+    board.get_piece_bitboard(position.side_to_move, PieceType::King).lsb_square().is_some()
+        && board
+            .get_piece_bitboard(them, PieceType::King)
+            .lsb_square()
+            .is_some_and(|king_sq| !board.is_square_attacked(king_sq, position.side_to_move))
+}
+
+// A king-capture game is over exactly when a king is gone, and a king left attacked can always
+// be taken, which ends the game for the side that took it.
+fn king_capture_error(position: &ChessPosition) -> Option<&'static str> {
+    let board = &position.chessboard;
+    let us = position.side_to_move;
+    let king = |color: Color| board.get_piece_bitboard(color, PieceType::King).lsb_square();
+    let outcome = Ruleset::KingCapture.outcome(position, 1, &DrawRules::AUTOMATIC);
+    let king_captured = |winner: Color| outcome == Outcome::Finished(Some(winner), Termination::KingCaptured);
+
+    match (king(us), king(us.opposite())) {
+        (None, _) if !king_captured(us.opposite()) => Some("a captured king doesn't end the game"),
+        (_, None) if !king_captured(us) => Some("a captured king doesn't end the game"),
+        (Some(_), Some(their_king)) => {
+            if matches!(outcome, Outcome::Finished(_, Termination::KingCaptured)) {
+                return Some("the game ends by king capture with both kings on the board");
+            }
+            if !board.is_square_attacked(their_king, us) {
+                return None;
+            }
+            let Some(&capture) = position.pseudolegal_moves.iter().find(|mov| mov.to() == their_king && mov.is_capture()) else {
+                return Some("a king left attacked can't be captured");
+            };
+            let mut after = position.clone();
+            after.make_packed(capture);
+            let outcome = Ruleset::KingCapture.outcome(&after, 1, &DrawRules::AUTOMATIC);
+            (outcome != Outcome::Finished(Some(us), Termination::KingCaptured)).then_some("capturing the king doesn't win the game")
+        }
+        _ => None,
+    }
+}
+
+// Cuts a failing case down while check still fails it: the shallowest depth, then down the tree
+// along a root move whose count is off, then started as late in its moves as possible.
+fn shrink(mut case: FuzzCase, mut check: impl FnMut(&FuzzCase) -> Option<FuzzFailureKind>) -> FuzzFailure {
+    let mut kind = check(&case).expect("shrinking a case that passes");
+
+    for depth in 1..case.depth {
+        let candidate = FuzzCase { depth, ..case.clone() };
+        if let Some(failure) = check(&candidate) {
+            (case, kind) = (candidate, failure);
+            break;
+        }
+    }
+
+    while case.depth > 1
+        && let FuzzFailureKind::Perft(diffs) = &kind
+        && let Some(diff) = diffs.iter().find(|diff| diff.ours.is_some() && diff.reference.is_some())
+    {
+        let mut candidate = case.clone();
+        candidate.moves.push(diff.mov);
+        candidate.depth -= 1;
+        match check(&candidate) {
+            Some(failure) => (case, kind) = (candidate, failure),
+            None => break,
+        }
+    }
+
+    for skip in (1..=case.moves.len()).rev() {
+        if let Ok(candidate) = case.rebased(skip)
+            && let Some(failure) = check(&candidate)
+        {
+            (case, kind) = (candidate, failure);
+            break;
+        }
+    }
+
+    FuzzFailure { case, kind }
+}
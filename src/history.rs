@@ -0,0 +1,59 @@
+use crate::ChessPosition;
+
+// The zobrist hash and halfmove clock of every position of a game, oldest first. A position can
+// only repeat one from after the last capture or pawn move, where the clock was reset, and only
+// one with the same side to move, so a repetition check looks at every second entry of the
+// last halfmove clock entries and no further.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashHistory {
+    entries: Vec<(u64, u32)>,
+}
+
+impl HashHistory {
+    pub fn new(position: &ChessPosition) -> Self {
+        Self { entries: vec![(position.zobrist_hash, position.halfmove_clock)] }
+    }
+
+    pub fn push(&mut self, position: &ChessPosition) {
+        self.entries.push((position.zobrist_hash, position.halfmove_clock));
+    }
+
+    pub fn pop(&mut self) -> Option<u64> {
+        self.entries.pop().map(|(hash, _)| hash)
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.entries.truncate(len);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn hashes(&self) -> impl Iterator<Item = u64> + '_ {
+        self.entries.iter().map(|&(hash, _)| hash)
+    }
+
+    // how often the last position has occurred, itself included
+    pub fn repetitions(&self) -> usize {
+        match self.entries.split_last() {
+            Some((&(hash, halfmove_clock), earlier)) => 1 + earlier_matches(earlier, hash, halfmove_clock),
+            None => 0,
+        }
+    }
+
+    // the same for a position one ply after the last one, without pushing it
+    pub fn repetitions_of(&self, position: &ChessPosition) -> usize {
+        1 + earlier_matches(&self.entries, position.zobrist_hash, position.halfmove_clock)
+    }
+}
+
+// earlier ends one ply before the position, the same side was to move two, four... plies back
+fn earlier_matches(earlier: &[(u64, u32)], hash: u64, halfmove_clock: u32) -> usize {
+    let window = (halfmove_clock as usize).min(earlier.len());
+    earlier[earlier.len() - window..].iter().rev().skip(1).step_by(2).filter(|&&(earlier_hash, _)| earlier_hash == hash).count()
+}
@@ -18,6 +18,7 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>.
 #![recursion_limit = "256"]
 
 pub mod bitboard;
+pub mod builder;
 pub mod castling;
 pub mod chess_board;
 pub mod chess_game;
@@ -27,23 +28,53 @@ pub mod chess_position;
 pub mod chess_square;
 pub mod data;
 pub mod engine;
+pub mod epd;
+pub mod fen;
+pub mod fuzz;
+pub mod history;
+pub mod magic;
 pub mod mcts;
 pub mod model;
-pub mod zobrist;
+pub mod packed;
+pub mod perft;
+pub mod pgn;
+pub mod polyglot;
+pub mod ruleset;
+pub mod san;
+pub mod see;
 pub mod stockfish;
+pub mod svg;
+pub mod syzygy;
+pub mod variant;
+pub mod zobrist;
 
 pub use bitboard::Bitboard;
+pub use builder::PositionBuilder;
 pub use burn;
 pub use castling::CastlingRights;
-pub use chess_board::ChessBoard;
-pub use chess_game::ChessGame;
-pub use chess_move::ChessMove;
+pub use chess_board::{Castling, ChessBoard, Pin};
+pub use chess_game::{ChessGame, Outcome, Termination};
+pub use chess_move::{ChessMove, MoveKind, MoveList, PackedMove};
 pub use chess_piece::{ChessPiece, Color, PieceType};
-pub use chess_position::ChessPosition;
+pub use chess_position::{ChessPosition, MAX_MOVES, UndoInfo};
 pub use chess_square::ChessSquare;
 pub use data::*;
 pub use engine::*;
+pub use epd::{EpdError, EpdErrorKind, EpdRecord, EpdResult, EpdSuite, SuiteReport, run_epd_suite};
+pub use fen::{FenError, chess960_fen, parse_fen, parse_variant_fen};
+pub use fuzz::{FuzzCase, FuzzConfig, FuzzFailure, FuzzFailureKind, FuzzStats, MoveDiff, fuzz_movegen};
+pub use history::HashHistory;
+pub use magic::{SliderAttacks, SliderIndexing};
 pub use mcts::*;
 pub use model::ChessTransformer;
-pub use zobrist::{XorShift64, ZobristKeys};
+pub use packed::{PackError, PackedGame, PackedPosition};
+pub use perft::*;
+pub use pgn::{PgnError, PgnErrorKind, PgnGame, PgnNode, PgnReader, parse_pgn};
+pub use polyglot::{BookEntry, BookWriter, PolyglotBook};
+pub use ruleset::{DrawRules, Ruleset};
+pub use san::SanError;
 pub use stockfish::*;
+pub use svg::{Arrow, BoardSvg};
+pub use syzygy::{Tablebase, Wdl};
+pub use variant::Variant;
+pub use zobrist::{PolyglotKeys, XorShift64, ZobristKeys};
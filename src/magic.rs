@@ -0,0 +1,307 @@
+use std::sync::OnceLock;
+
+use crate::{Bitboard, ChessBoard, ChessSquare};
+
+// found by trial and error over sparse XorShift64 candidates, none collide destructively
+pub const BISHOP_MAGICS: [u64; 64] = [
+    0x8008029802002200,
+    0x4291040808802804,
+    0x0008180040800300,
+    0x00088A0202AA1050,
+    0x000410A800000000,
+    0x0009100804040009,
+    0x0801140121080011,
+    0xA040808400824000,
+    0x000008A004040048,
+    0x0600200440808114,
+    0x2020410401204403,
+    0x000404106200C001,
+    0x0100011040800026,
+    0x00080088200A0820,
+    0x0008004804642080,
+    0x4000004402981800,
+    0x0710002220020088,
+    0x2010808202020402,
+    0x8010080844002820,
+    0x800C000124028000,
+    0x0002000422010040,
+    0x6438402200422000,
+    0x0010A1004C0C2000,
+    0x000A00E109010190,
+    0x08022010400414C0,
+    0x8428022220240101,
+    0x0008088004040010,
+    0x0008080000220020,
+    0x0421010000104000,
+    0x219102082500A000,
+    0x0018008042120150,
+    0x02108020A09C0402,
+    0x301C202000890208,
+    0xA004022000080100,
+    0x100C024100881200,
+    0x8000080800460A00,
+    0x1004010804440040,
+    0x420C920080041000,
+    0x05018C0114440100,
+    0x00040100308A0080,
+    0x0020821042801000,
+    0x0202026120001C02,
+    0x0002001044000800,
+    0x20AA844200800801,
+    0x0000012011001200,
+    0x0860209008808042,
+    0x0008100080A80200,
+    0x0808020050420201,
+    0x00051C0104C00000,
+    0x0000840108820022,
+    0x000A461842080004,
+    0x2400400914880002,
+    0x00040040102481B4,
+    0x2104A14202020060,
+    0x0004081041020060,
+    0x00A0840082005100,
+    0x0000412210101482,
+    0x0108504208042210,
+    0x000020044C040405,
+    0x4140050206051401,
+    0x0122008051820200,
+    0x0082800428109100,
+    0x9104042454440401,
+    0x141E200C00820848,
+];
+
+pub const ROOK_MAGICS: [u64; 64] = [
+    0x0280038860400010,
+    0x098020004000B080,
+    0x2100110008402002,
+    0x0880080081041000,
+    0x0200020020041008,
+    0x2300040008010012,
+    0x0C00283004008201,
+    0x0180010000407A80,
+    0x0168800080400020,
+    0x0010400040201000,
+    0x1001002001001048,
+    0x1001002408100100,
+    0x0801000408010012,
+    0x4001000209000400,
+    0x08A20004C8020001,
+    0x2002801145002280,
+    0x0080860021004200,
+    0x001000C009402002,
+    0x00B0002004002800,
+    0x100A808010020800,
+    0x8101010008000410,
+    0x0244008002000480,
+    0x0000040010810208,
+    0x2000020000448534,
+    0x4104400480008033,
+    0x0000810100204000,
+    0x0440430900200010,
+    0x4600240900100100,
+    0x0060080080040080,
+    0x0001000300080400,
+    0x0004084400011002,
+    0x0023040200008041,
+    0x0580050043002080,
+    0x0400804002802008,
+    0x0001002001004010,
+    0x1000200901001000,
+    0x4410800801800C00,
+    0xA012003806001004,
+    0x0020100104008802,
+    0x0004808402000041,
+    0x0010400170898000,
+    0x0080500020004004,
+    0x1040408012020020,
+    0x8010040008004040,
+    0x2001080100110004,
+    0x0000020004008080,
+    0x0021010810040002,
+    0x0800008C43020024,
+    0x0000800021005100,
+    0x0070201040008080,
+    0x0000D04282006A00,
+    0x0010014400080240,
+    0x0001080110050100,
+    0x0012000810240600,
+    0x0402000801040200,
+    0x028100108A004100,
+    0x0050800300102045,
+    0x8208210040120882,
+    0x8010600101183441,
+    0x020B000910006045,
+    0x0241001002480005,
+    0x0081000400880241,
+    0x0000009008024124,
+    0x0048122980410402,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SliderIndexing {
+    Magic,
+    Pext,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct SliderEntry {
+    mask:   u64, // relevant occupancy, edges excluded
+    magic:  u64,
+    shift:  u32,
+    offset: usize,
+}
+
+impl SliderEntry {
+    #[inline(always)]
+    fn index(&self, occupancy: Bitboard, indexing: SliderIndexing) -> usize {
+        let key = match indexing {
+            SliderIndexing::Magic => ((occupancy.0 & self.mask).wrapping_mul(self.magic) >> self.shift) as usize,
+            SliderIndexing::Pext => pext(occupancy.0, self.mask) as usize,
+        };
+        self.offset + key
+    }
+}
+
+// Sliding piece attacks looked up by occupancy. The same table layout serves both magic
+// multiplication and BMI2 PEXT indexing, the choice is made once when the table is built.
+pub struct SliderAttacks {
+    pub indexing: SliderIndexing,
+    bishop: [SliderEntry; 64],
+    rook: [SliderEntry; 64],
+    table: Vec<Bitboard>,
+}
+
+impl SliderAttacks {
+    pub fn new(indexing: SliderIndexing) -> Self {
+        let indexing = if indexing == SliderIndexing::Pext && !pext_supported() {
+            SliderIndexing::Magic
+        } else {
+            indexing
+        };
+
+        let mut table = Vec::new();
+        let bishop = std::array::from_fn(|sq| Self::build_square(&mut table, indexing, ChessBoard::BISHOP_ATTACKS[sq], BISHOP_MAGICS[sq]));
+        let rook = std::array::from_fn(|sq| Self::build_square(&mut table, indexing, ChessBoard::ROOK_ATTACKS[sq], ROOK_MAGICS[sq]));
+
+        Self { indexing, bishop, rook, table }
+    }
+
+    // pext when the crate is built with the `pext` feature and the cpu has bmi2, magics otherwise
+    pub fn get() -> &'static Self {
+        static INSTANCE: OnceLock<SliderAttacks> = OnceLock::new();
+        INSTANCE.get_or_init(|| {
+            let indexing = if cfg!(feature = "pext") {
+                SliderIndexing::Pext
+            } else {
+                SliderIndexing::Magic
+            };
+            Self::new(indexing)
+        })
+    }
+
+    #[inline(always)]
+    pub fn bishop(&self, sq: ChessSquare, occupancy: Bitboard) -> Bitboard {
+        self.table[self.bishop[sq.0 as usize].index(occupancy, self.indexing)]
+    }
+
+    #[inline(always)]
+    pub fn rook(&self, sq: ChessSquare, occupancy: Bitboard) -> Bitboard {
+        self.table[self.rook[sq.0 as usize].index(occupancy, self.indexing)]
+    }
+
+    fn build_square(table: &mut Vec<Bitboard>, indexing: SliderIndexing, rays: [Bitboard; 4], magic: u64) -> SliderEntry {
+        let mask = relevant_mask(rays);
+        let bits = mask.count_ones();
+
+        let offset = table.len();
+        table.resize(offset + (1usize << bits), Bitboard::EMPTY);
+
+        let entry = SliderEntry { mask, magic, shift: 64 - bits, offset };
+
+        // walk every subset of the mask
+        let mut subset = 0u64;
+        loop {
+            let attacks = ray_attacks(rays, Bitboard(subset));
+            let slot = &mut table[entry.index(Bitboard(subset), indexing)];
+            // checked in release builds too, a bad magic would silently corrupt slider attacks
+            assert!(slot.is_empty() || *slot == attacks, "destructive magic collision for magic {:#x}", magic);
+            *slot = attacks;
+
+            subset = subset.wrapping_sub(mask) & mask;
+            if subset == 0 {
+                break;
+            }
+        }
+
+        entry
+    }
+}
+
+// The furthest square of each ray never changes the attack set, so it is left out of the key.
+// Directions 0 and 1 run towards a1 (furthest square is the lsb), 2 and 3 towards h8 (msb).
+fn relevant_mask(rays: [Bitboard; 4]) -> u64 {
+    let mut mask = 0;
+    for (i, ray) in rays.iter().enumerate() {
+        let mut ray = *ray;
+        if i < 2 {
+            ray.pop_lsb();
+        } else {
+            ray.pop_msb();
+        }
+        mask |= ray.0;
+    }
+    mask
+}
+
+// Slow reference attacks, walks each ray up to and including the first blocker.
+pub fn ray_attacks(rays: [Bitboard; 4], occupancy: Bitboard) -> Bitboard {
+    let mut attacks = Bitboard::EMPTY;
+    for (i, ray) in rays.iter().enumerate() {
+        let mut blockers = ray & occupancy;
+        let blocker = if i < 2 { blockers.pop_msb() } else { blockers.pop_lsb() };
+        match blocker {
+            Some(sq) => attacks |= ray & !ray_beyond(*ray, sq, i < 2),
+            None => attacks |= *ray,
+        }
+    }
+    attacks
+}
+
+// squares of the ray strictly past sq
+fn ray_beyond(ray: Bitboard, sq: ChessSquare, towards_a1: bool) -> Bitboard {
+    if towards_a1 {
+        Bitboard(ray.0 & ((1u64 << sq.0) - 1))
+    } else {
+        Bitboard(ray.0 & !(2u64 << sq.0).wrapping_sub(1))
+    }
+}
+
+fn pext_supported() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        std::arch::is_x86_feature_detected!("bmi2")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}
+
+#[inline(always)]
+fn pext(value: u64, mask: u64) -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        // SAFETY: SliderAttacks::new only selects pext indexing after detecting bmi2
+        unsafe { pext_bmi2(value, mask) }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        unreachable!("pext indexing selected without bmi2 ({value:#x}, {mask:#x})")
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "bmi2")]
+unsafe fn pext_bmi2(value: u64, mask: u64) -> u64 {
+    core::arch::x86_64::_pext_u64(value, mask)
+}
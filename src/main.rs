@@ -18,23 +18,25 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>.
 #![recursion_limit = "256"]
 use crate::{ChessGame, ChessTransformer, Mcts, MctsConfig, expand_batch};
 use burn::{lr_scheduler::noam::NoamLrSchedulerConfig, module::Module, optim::AdamWConfig};
-use log::info;
 use std::io::{self, Write};
 use std::path::PathBuf;
+use std::time::Instant;
 
 use burn::backend::Autodiff;
 use burn::record::{FullPrecisionSettings, NamedMpkFileRecorder, Recorder};
 use chess_engine::model::ChessTransformerConfig;
 use chess_engine::*;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     #[arg(short, long, default_value_t = 256)]
     batch_size: usize,
-    #[arg(short, long)]
-    legal: bool,
+    #[arg(short, long, value_enum, default_value_t)]
+    ruleset: Ruleset,
     #[arg(short, long)]
     masked: bool,
     #[arg(short, long, default_value_t = 1.25)]
@@ -51,8 +53,103 @@ struct Args {
     annealing: bool,
     #[arg(short, long, default_value_t = 1.0)]
     temperature: f32,
-    #[arg(short, long, value_name = "DIR")]
-    path: PathBuf,
+    #[arg(short, long, value_name = "DIR", required = true)]
+    path: Option<PathBuf>,
+    /// Start self-play games from random Chess960 positions
+    #[arg(long)]
+    chess960: bool,
+    /// Rule variant of the self-play games, with its own start position and game ends
+    #[arg(long, value_enum, default_value_t, conflicts_with = "chess960")]
+    variant: Variant,
+    /// Don't claim threefold repetition or 50-move draws, play on to fivefold or 75 moves
+    #[arg(long)]
+    no_draw_claims: bool,
+    /// Directory of Syzygy tables used to adjudicate and score endgames under the standard ruleset
+    #[arg(long, value_name = "DIR")]
+    syzygy: Option<PathBuf>,
+    /// Polyglot book to open self-play games and answer inference positions from
+    #[arg(long, value_name = "FILE")]
+    book: Option<PathBuf>,
+    /// How many plies of each self-play game to take from the book
+    #[arg(long, default_value_t = 8)]
+    book_plies: usize,
+    /// Write the root visit counts of this many plies of every self-play game to book.bin
+    #[arg(long, default_value_t = 0)]
+    export_book_plies: usize,
+    /// Augment training with the a-h mirror of positions that have no castling rights left
+    #[arg(long)]
+    mirror: bool,
+    /// Write SVG boards of the inference search to this directory
+    #[arg(long, value_name = "DIR")]
+    svg: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Count leaf nodes of the legal move tree, or run the reference suite
+    Perft {
+        #[arg(short, long, default_value = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")]
+        fen:      String,
+        #[arg(short, long, default_value_t = 5)]
+        depth:    u32,
+        /// Print the node count below each root move
+        #[arg(long)]
+        divide:   bool,
+        /// Split root moves across threads
+        #[arg(long)]
+        parallel: bool,
+        /// Run the built-in reference positions up to this many nodes per depth
+        #[arg(long, value_name = "MAX_NODES")]
+        suite:    Option<u64>,
+    },
+    /// Build a Polyglot book from a PGN collection, plain or zstd compressed
+    Book {
+        #[arg(value_name = "PGN")]
+        pgn:    PathBuf,
+        #[arg(value_name = "BIN")]
+        output: PathBuf,
+        /// Only take the first this many plies of every game
+        #[arg(long, default_value_t = 24)]
+        plies:  usize,
+    },
+    /// Compare move generation in random games with the bundled Stockfish's perft
+    Fuzz {
+        #[arg(long, default_value_t = 100)]
+        games: usize,
+        /// Perft depth compared at each sampled position, the legal moves are always compared
+        #[arg(short, long, default_value_t = 2)]
+        depth: u32,
+        /// Plies between sampled positions
+        #[arg(long, default_value_t = 4)]
+        every: usize,
+        #[arg(long, default_value_t = 300)]
+        max_plies: usize,
+        /// Play pseudolegal king-capture games and check how they end as well
+        #[arg(long)]
+        king_capture: bool,
+        /// Start from random Chess960 positions
+        #[arg(long)]
+        chess960: bool,
+        #[arg(long, default_value_t = 1)]
+        seed: u64,
+    },
+    /// Score the model loaded from --path on EPD suites by their bm and am moves
+    Epd {
+        #[arg(value_name = "EPD", required = true)]
+        suites: Vec<PathBuf>,
+        /// Simulations per position, --num-simulations by default
+        #[arg(long)]
+        nodes:  Option<usize>,
+        /// Search each position for this many milliseconds instead of a node count
+        #[arg(long, conflicts_with = "nodes")]
+        millis: Option<u64>,
+        /// Write every result to this JSON file
+        #[arg(long, value_name = "FILE")]
+        json:   Option<PathBuf>,
+        /// Write every result to this CSV file
+        #[arg(long, value_name = "FILE")]
+        csv:    Option<PathBuf>,
+    },
 }
 
 pub struct TrainingMetrics {
@@ -70,12 +167,123 @@ pub fn display_moves(moves: &Vec<ChessMove>) {
     }
 }
 
+fn run_perft(fen: &str, depth: u32, show_divide: bool, parallel: bool, suite: Option<u64>) {
+    if let Some(max_nodes) = suite {
+        let results = run_suite(max_nodes, parallel);
+        results.iter().for_each(|result| println!("{}", result));
+        let failed = results.iter().filter(|result| !result.passed()).count();
+        println!("\n{} passed, {} failed", results.len() - failed, failed);
+        if failed > 0 {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let game = match ChessGame::from_fen(fen) {
+        Ok(game) => game,
+        Err(e) => {
+            eprintln!("Failed to parse fen: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let before = Instant::now();
+    let nodes = if show_divide {
+        let div = divide(&game.position, depth, parallel);
+        println!("{}", div);
+        div.total()
+    } else if parallel {
+        perft_parallel(&game.position, depth)
+    } else {
+        perft(&game.position, depth)
+    };
+    let elapsed = before.elapsed().as_secs_f64();
+
+    if !show_divide {
+        println!("Nodes searched: {}", nodes);
+    }
+    println!("Time: {:.3}s ({:.0} nps)", elapsed, nodes as f64 / elapsed.max(1e-9));
+}
+
+fn run_fuzz(config: &FuzzConfig) {
+    let before = Instant::now();
+    match fuzz_movegen(config, |position, depth| Stockfish::with_global(|sf| sf.perft(position, depth))) {
+        Ok(stats) => println!("{} in {:.1}s, no differences", stats, before.elapsed().as_secs_f64()),
+        Err(failure) => {
+            eprintln!("{}", failure);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn build_book(pgn: &PathBuf, output: &PathBuf, plies: usize) {
+    let reader = PgnReader::open(pgn).unwrap_or_else(|err| panic!("could not open {}: {}", pgn.display(), err));
+    let mut writer = BookWriter::new(plies);
+    match writer.add_pgn(reader) {
+        Ok(games) => println!("Read {} games, {} book moves", games, writer.len()),
+        Err(err) => eprintln!("Stopped at a broken game: {}", err),
+    }
+    writer.write(output).unwrap_or_else(|err| panic!("could not write {}: {}", output.display(), err));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_epd<B: burn::prelude::Backend>(
+    model: &ChessTransformer<B>,
+    mcts_config: MctsConfig,
+    training_config: &TrainingConfig,
+    suites: &[PathBuf],
+    budget: SearchBudget,
+    json: Option<&PathBuf>,
+    csv: Option<&PathBuf>,
+    device: &B::Device,
+) {
+    let mut reports = Vec::new();
+    for path in suites {
+        let suite = match EpdSuite::open(path) {
+            Ok(suite) => suite,
+            Err(err) => {
+                eprintln!("Skipping {}: {}", path.display(), err);
+                continue;
+            }
+        };
+        let report = run_epd_suite(&suite, model, mcts_config, training_config, budget, device);
+        report.results.iter().for_each(|result| println!("{}", result));
+        println!("{}\n", report);
+        reports.push(report);
+    }
+
+    if let Some(path) = json {
+        std::fs::write(path, SuiteReport::to_json(&reports)).unwrap_or_else(|err| eprintln!("could not write {}: {}", path.display(), err));
+    }
+    if let Some(path) = csv {
+        std::fs::write(path, SuiteReport::to_csv(&reports)).unwrap_or_else(|err| eprintln!("could not write {}: {}", path.display(), err));
+    }
+}
+
 fn main() {
     env_logger::init();
 
     let args = Args::parse();
 
-    let mut path = args.path.clone();
+    match args.command {
+        Some(Command::Perft { fen, depth, divide, parallel, suite }) => {
+            run_perft(&fen, depth, divide, parallel, suite);
+            return;
+        }
+        Some(Command::Book { pgn, output, plies }) => {
+            build_book(&pgn, &output, plies);
+            return;
+        }
+        Some(Command::Fuzz { games, depth, every, max_plies, king_capture, chess960, seed }) => {
+            let ruleset = if king_capture { Ruleset::KingCapture } else { Ruleset::Standard };
+            let config = FuzzConfig { games, max_plies, sample_every: every, depth, ruleset, chess960, seed };
+            run_fuzz(&config);
+            return;
+        }
+        Some(Command::Epd { .. }) | None => {}
+    }
+
+    let mut path = args.path.clone().expect("path is required to train or load a model");
     if path.starts_with("~")
         && let Ok(home) = std::env::var("HOME")
     {
@@ -94,7 +302,30 @@ fn main() {
 
     let device = Default::default();
 
-    let mcts_config = MctsConfig { num_simulations: args.num_simulations, c_puct: args.c_puct, temperature: args.temperature, legal: args.legal };
+    if let Some(dir) = &args.syzygy {
+        match Tablebase::init_global(dir) {
+            Ok(tablebase) => println!("Found {} Syzygy tables, up to {} pieces", tablebase.num_tables(), tablebase.max_pieces()),
+            Err(err) => eprintln!("Could not read Syzygy tables from {}: {}", dir.display(), err),
+        }
+    }
+
+    let book_plies = match &args.book {
+        Some(file) => match PolyglotBook::init_global(file) {
+            Ok(book) => {
+                println!("Loaded opening book with {} entries", book.len());
+                args.book_plies
+            }
+            Err(err) => {
+                eprintln!("Could not read opening book {}: {}", file.display(), err);
+                0
+            }
+        },
+        None => 0,
+    };
+
+    let draw_rules = if args.no_draw_claims { DrawRules::AUTOMATIC } else { DrawRules::FIDE };
+    let mcts_config =
+        MctsConfig { num_simulations: args.num_simulations, c_puct: args.c_puct, temperature: args.temperature, ruleset: args.ruleset, draw_rules };
 
     let size = 8;
     let n_heads = size;
@@ -109,7 +340,8 @@ fn main() {
     let training_config = TrainingConfig {
         model: model_config,
         masked: args.masked,
-        legal: args.legal,
+        ruleset: args.ruleset,
+        draw_rules,
         annealing: args.annealing,
         scheduler: scheduler_config,
         optimizer: optimizer_config,
@@ -117,8 +349,27 @@ fn main() {
         steps_per_iter: args.iter_count,
         batch_size: args.batch_size,
         seed: args.seed,
+        chess960: args.chess960,
+        book_plies,
+        export_book_plies: args.export_book_plies,
+        mirror: args.mirror,
+        variant: args.variant,
     };
 
+    if let Some(Command::Epd { suites, nodes, millis, json, csv }) = &args.command {
+        let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::default();
+        let record = recorder.load(artifact_dir.clone(), &device).expect("Failed to load .mpk model record");
+        let model: ChessTransformer<MyInferenceBackend> = training_config.model.init(&device);
+        let model = model.load_record(record);
+
+        let budget = match millis {
+            Some(millis) => SearchBudget::Time(std::time::Duration::from_millis(*millis)),
+            None => SearchBudget::Nodes(nodes.unwrap_or(args.num_simulations)),
+        };
+        run_epd(&model, mcts_config, &training_config, suites, budget, json.as_ref(), csv.as_ref(), &device);
+        return;
+    }
+
     loop {
         println!("What do you want to do?\n1 - Train a model!\n2 - Inference");
         io::stdout().flush().unwrap();
@@ -144,6 +395,13 @@ fn main() {
                     ChessGame::default()
                 });
 
+                if let Some(mov) = PolyglotBook::global().and_then(|book| book.weighted_move(&game.position, &mut XorShift64::default())) {
+                    println!("\nBook move: {}", mov.to_uci());
+                    game.make_move(&mov);
+                    println!("{}", game.position.to_fen());
+                    continue;
+                }
+
                 let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::default();
                 let record = recorder.load(artifact_dir.clone(), &device).expect("Failed to load .mpk model record");
                 let model: ChessTransformer<MyInferenceBackend> = training_config.model.init(&device);
@@ -152,7 +410,6 @@ fn main() {
                 let mut inf_config = training_config.clone();
                 inf_config.batch_size = 1;
                 inf_config.masked = true;
-                inf_config.legal = mcts_config.legal;
 
                 let mut mcts = Mcts::from_game(&game, 65536, mcts_config, 1234);
 
@@ -168,6 +425,12 @@ fn main() {
                 let best_from_edge = mcts.edge_arena.buffer[start..end].iter().max_by_key(|e| e.visits).expect("No edges");
                 let from_sq = best_from_edge.square;
                 let piece_move_node_idx = best_from_edge.child_node_idx.expect("Edge not expanded");
+                if let Some(dir) = &args.svg
+                    && let (Some(sample), _) = mcts.make_targets(true)
+                {
+                    let board = BoardSvg::from_mcts(&mcts, 5).policy(&sample.targets);
+                    board.write(dir.join("from.svg")).unwrap_or_else(|err| eprintln!("could not write svg: {}", err));
+                }
 
                 mcts.root = piece_move_node_idx;
 
@@ -181,12 +444,16 @@ fn main() {
                 let move_root = &mcts.node_arena.buffer[mcts.root];
                 let (start2, end2) = move_root.get_data().child_edge_range.expect("Move root not expanded");
                 let best_to_edge = mcts.edge_arena.buffer[start2..end2].iter().max_by_key(|e| e.visits).expect("No to-edges");
-                let to_sq = best_to_edge.square;
-                let promotion = best_to_edge.promotion_piece;
+                let mov = best_to_edge.mov.expect("to-edges carry their move");
+                if let Some(dir) = &args.svg
+                    && let (Some(sample), _) = mcts.make_targets(true)
+                {
+                    let board = BoardSvg::from_mcts(&mcts, 5).to_policy(from_sq, &sample.targets);
+                    board.write(dir.join("to.svg")).unwrap_or_else(|err| eprintln!("could not write svg: {}", err));
+                }
 
-                let mov = ChessMove::new(from_sq, to_sq, promotion);
                 println!("\nI picked: {}", mov.to_uci());
-                game.make_move(&mov);
+                game.make_packed(mov);
                 println!("{}", game.position.to_fen());
             }
             _ => println!("Invalid - select {{1|2}}"),
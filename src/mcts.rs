@@ -7,12 +7,14 @@ use rayon::{
     iter::{IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator},
     slice::ParallelSliceMut,
 };
+use std::time::{Duration, Instant};
 
 use burn::prelude::Backend;
 
 use crate::{
-    ChessGame, ChessMove, ChessPosition, ChessSquare, ChessTransformer, Color, NetworkInputs, NetworkLabels, PieceType, TrainingConfig,
-    TrainingSample, XorShift64, chess_game::Outcome, model_make_outputs,
+    ChessGame, ChessMove, ChessPosition, ChessSquare, ChessTransformer, Color, DrawRules, HashHistory, MAX_MOVES, NetworkInputs, NetworkLabels,
+    POLICY_SIZE, PackedMove, PieceType, ReplaySample, Ruleset, Selection, TrainingConfig, XorShift64, chess_game::Outcome, flip_ranks,
+    model_make_outputs,
 };
 
 #[derive(Default, Debug, Copy, Clone)]
@@ -32,10 +34,12 @@ impl NodeData {
 
 // --------------------
 
+// The search picks a move in two steps, a piece on the board or in the pocket, then where it goes.
 #[derive(Debug, Clone)]
 pub enum MctsNode {
     PieceSelect { data: NodeData },
     PieceMove { data: NodeData, from_sq: ChessSquare },
+    PieceDrop { data: NodeData, piece: PieceType },
 }
 
 impl fmt::Display for MctsNode {
@@ -56,6 +60,16 @@ impl fmt::Display for MctsNode {
                 data.is_terminal,
                 data.visits
             ),
+            MctsNode::PieceDrop { data, piece } => write!(
+                f,
+                "Select: drop: {} position_idx: {}, edges: {:?}, value: {:?}, terminal: {}, visits: {}",
+                piece.to_char(Color::White),
+                data.chess_position_idx,
+                data.child_edge_range,
+                data.value,
+                data.is_terminal,
+                data.visits
+            ),
         }
     }
 }
@@ -71,6 +85,7 @@ impl MctsNode {
         match self {
             Self::PieceSelect { data } => data,
             Self::PieceMove { data, .. } => data,
+            Self::PieceDrop { data, .. } => data,
         }
     }
 
@@ -78,6 +93,16 @@ impl MctsNode {
         match self {
             Self::PieceSelect { data } => data,
             Self::PieceMove { data, .. } => data,
+            Self::PieceDrop { data, .. } => data,
+        }
+    }
+
+    // what the first step picked, None while picking it
+    pub fn selection(&self) -> Option<Selection> {
+        match self {
+            Self::PieceSelect { .. } => None,
+            Self::PieceMove { from_sq, .. } => Some(Selection::Square(*from_sq)),
+            Self::PieceDrop { piece, .. } => Some(Selection::Pocket(*piece)),
         }
     }
 }
@@ -93,7 +118,8 @@ pub struct MctsEdge {
     pub mean_value: [f32; 3],          // total val / visits
     pub child_node_idx: Option<usize>, // None if not explored
     pub parent_node_idx: usize,
-    pub promotion_piece: Option<PieceType>,
+    pub mov: Option<PackedMove>,       // the move played by an edge below a picked piece
+    pub drop_piece: Option<PieceType>, // a piece select edge into the pocket, square is unused
 }
 
 impl fmt::Display for MctsEdge {
@@ -116,15 +142,29 @@ impl MctsEdge {
             mean_value: [0.0; 3],
             child_node_idx: None,
             parent_node_idx,
-            promotion_piece: None,
+            mov: None,
+            drop_piece: None,
         }
     }
 
-    pub fn with_prom(mut self, piece: PieceType) -> Self {
-        self.promotion_piece = Some(piece);
+    pub fn with_move(mut self, mov: PackedMove) -> Self {
+        self.mov = Some(mov);
         self
     }
 
+    pub fn with_drop(mut self, piece: PieceType) -> Self {
+        self.drop_piece = Some(piece);
+        self
+    }
+
+    // the policy entry the edge was scored from
+    pub fn selection(&self) -> Selection {
+        match self.drop_piece {
+            Some(piece) => Selection::Pocket(piece),
+            None => Selection::Square(self.square),
+        }
+    }
+
     pub fn to_value(&self) -> f32 {
         let (w, d, l) = (self.mean_value[0], self.mean_value[1], self.mean_value[2]);
         (w - l) / (w + d + l)
@@ -194,7 +234,8 @@ pub struct MctsConfig {
     pub num_simulations: usize,
     pub c_puct: f32,
     pub temperature: f32,
-    pub legal: bool,
+    pub ruleset: Ruleset,
+    pub draw_rules: DrawRules,
 }
 
 pub struct Mcts {
@@ -204,8 +245,9 @@ pub struct Mcts {
     pub position_arena: Arena<ChessPosition>,
     pub path: Vec<usize>, // idx of edges
     pub rng: XorShift64,
-    pub root: usize,           // node idx
-    pub past_hashes: Vec<u64>, // when make move
+    pub root: usize,          // node idx
+    pub history: HashHistory, // the game up to the root, then the positions along the current path
+    pub root_plies: usize,    // history entries up to and including the root
     pub dead_nodes: Vec<usize>,
 }
 
@@ -221,7 +263,7 @@ impl Mcts {
 
         let rng = XorShift64::new(rng);
 
-        let past_hashes: Vec<_> = game.game_history.iter().map(|game| game.zobrist_hash).collect();
+        let history = game.history.clone();
         let dead_nodes = Vec::new();
 
         Self {
@@ -232,7 +274,8 @@ impl Mcts {
             rng,
             path: Vec::new(),
             root: 0,
-            past_hashes,
+            root_plies: history.len(),
+            history,
             dead_nodes,
         }
     }
@@ -255,7 +298,8 @@ impl Mcts {
         self.node_arena.push(node);
 
         self.position_arena.push(game.position.clone());
-        self.past_hashes = game.game_history.iter().map(|game| game.zobrist_hash).collect();
+        self.history = game.history.clone();
+        self.root_plies = self.history.len();
     }
 
     pub fn select_puct_edge(&mut self, node_idx: usize) -> Option<usize> {
@@ -284,16 +328,8 @@ impl Mcts {
 
     pub fn get_network_input(&self, node_idx: usize) -> NetworkInputs {
         let node = &self.node_arena.buffer[node_idx];
-        match node {
-            MctsNode::PieceSelect { data } => {
-                let position = &self.position_arena.buffer[data.chess_position_idx];
-                NetworkInputs::from_position(position, None)
-            }
-            MctsNode::PieceMove { data, from_sq } => {
-                let position = &self.position_arena.buffer[data.chess_position_idx];
-                NetworkInputs::from_position(position, Some(from_sq))
-            }
-        }
+        let position = &self.position_arena.buffer[node.get_data().chess_position_idx];
+        NetworkInputs::from_position(position, node.selection())
     }
 
     pub fn backprop(&mut self, value: [f32; 3], color: Color) {
@@ -344,73 +380,62 @@ impl Mcts {
         info!("edges cleared: {} ({} total)", self.edge_arena.freelist.len(), self.edge_arena.buffer.len());
     }
 
+    // the history has to end at the parent's position, traverse_get_terminal keeps it that way
     fn add_leaf(&mut self, edge_idx: usize) -> Option<usize> {
-        let mut path_hashes = Vec::new();
-        let root_pos_idx = self.node_arena.buffer[self.root].get_data().chess_position_idx;
-        path_hashes.push(self.position_arena.buffer[root_pos_idx].zobrist_hash);
-        self.path.iter().for_each(|&idx| {
-            let edge = &self.edge_arena.buffer[idx];
-            let Some(node_idx) = edge.child_node_idx else {
-                return;
-            };
-            let node = &self.node_arena.buffer[node_idx];
-            if matches!(node, MctsNode::PieceSelect { data: _ }) {
-                let pos = &self.position_arena.buffer[node.get_data().chess_position_idx];
-                path_hashes.push(pos.zobrist_hash);
-            }
-        });
-
         let edge = &mut self.edge_arena.buffer[edge_idx];
         if edge.child_node_idx.is_some() {
             return None;
         }
 
         let parent_node = &self.node_arena.buffer[edge.parent_node_idx];
-        match parent_node {
+        let mov = match parent_node {
             MctsNode::PieceSelect { data } => {
-                let new_node = MctsNode::PieceMove { data: NodeData::new(data.chess_position_idx), from_sq: edge.square };
+                let data = NodeData::new(data.chess_position_idx);
+                let new_node = match edge.drop_piece {
+                    Some(piece) => MctsNode::PieceDrop { data, piece },
+                    None => MctsNode::PieceMove { data, from_sq: edge.square },
+                };
                 let node_idx = self.node_arena.push(new_node);
                 edge.child_node_idx = Some(node_idx);
-                Some(node_idx)
+                return Some(node_idx);
             }
-            MctsNode::PieceMove { from_sq, .. } => {
-                let mov = ChessMove::new(*from_sq, edge.square, edge.promotion_piece);
+            MctsNode::PieceMove { .. } | MctsNode::PieceDrop { .. } => edge.mov.expect("move edges carry their move"),
+        };
 
-                let mut position = self.position_arena.buffer[parent_node.get_data().chess_position_idx].clone();
-                position.make_move(&mov);
+        let mut position = self.position_arena.buffer[parent_node.get_data().chess_position_idx].clone();
+        position.make_packed(mov);
 
-                let repeats = self.past_hashes.iter().filter(|&hash| hash == &position.zobrist_hash).count()
-                    + path_hashes.iter().filter(|&hash| hash == &position.zobrist_hash).count();
+        let repetitions = self.history.repetitions_of(&position);
 
-                let side_to_move = position.side_to_move;
-                let outcome = if repeats >= 2 {
-                    Outcome::Finished(None)
-                } else {
-                    position.check_game_state(self.config.legal)
-                };
+        let side_to_move = position.side_to_move;
+        let mut outcome = self.config.ruleset.outcome(&position, repetitions, &self.config.draw_rules);
+        if outcome == Outcome::Unfinished
+            && self.config.ruleset.uses_tablebases()
+            && let Some(wdl) = position.tablebase_wdl()
+        {
+            outcome = wdl.outcome(side_to_move);
+        }
 
-                let idx = self.position_arena.push(position);
+        let idx = self.position_arena.push(position);
 
-                let mut new_node = MctsNode::PieceSelect { data: NodeData::new(idx) };
+        let mut new_node = MctsNode::PieceSelect { data: NodeData::new(idx) };
 
-                if let Outcome::Finished(winner) = outcome {
-                    let value = match (winner, side_to_move) {
-                        (Some(Color::White), Color::White) => [1.0, 0.0, 0.0],
-                        (Some(Color::White), Color::Black) => [0.0, 0.0, 1.0],
-                        (Some(Color::Black), Color::White) => [0.0, 0.0, 1.0],
-                        (Some(Color::Black), Color::Black) => [1.0, 0.0, 0.0],
-                        (None, _) => [0.0, 1.0, 0.0],
-                    };
-                    new_node.get_data_mut().value = Some(value);
-                    new_node.get_data_mut().is_terminal = true;
-                }
+        if let Outcome::Finished(winner, _) = outcome {
+            let value = match (winner, side_to_move) {
+                (Some(Color::White), Color::White) => [1.0, 0.0, 0.0],
+                (Some(Color::White), Color::Black) => [0.0, 0.0, 1.0],
+                (Some(Color::Black), Color::White) => [0.0, 0.0, 1.0],
+                (Some(Color::Black), Color::Black) => [1.0, 0.0, 0.0],
+                (None, _) => [0.0, 1.0, 0.0],
+            };
+            new_node.get_data_mut().value = Some(value);
+            new_node.get_data_mut().is_terminal = true;
+        }
 
-                let node_idx = self.node_arena.push(new_node);
-                edge.child_node_idx = Some(node_idx);
+        let node_idx = self.node_arena.push(new_node);
+        edge.child_node_idx = Some(node_idx);
 
-                Some(node_idx)
-            }
-        }
+        Some(node_idx)
     }
 
     pub fn node_to_expand(&self) -> Option<usize> {
@@ -425,43 +450,25 @@ impl Mcts {
         self.position_arena.buffer[self.node_arena.buffer[node_idx].get_data().chess_position_idx].clone()
     }
 
-    pub fn make_targets(&mut self, masked: bool) -> (Option<TrainingSample>, [f32; 3]) {
+    pub fn make_targets(&mut self, masked: bool) -> (Option<ReplaySample>, [f32; 3]) {
         let node = &self.node_arena.buffer[self.root];
         let Some((start, end)) = node.get_data().child_edge_range else {
             return (None, [0.0; 3]);
         };
 
         let position = &self.position_arena.buffer[node.get_data().chess_position_idx];
-        let mut mask = [true; 64];
-        let inputs = match node {
-            MctsNode::PieceSelect { .. } => {
-                if masked {
-                    mask = position.make_mask(self.config.legal, None);
-                }
-                NetworkInputs::from_position(position, None)
-            }
-            MctsNode::PieceMove { from_sq, .. } => {
-                if masked {
-                    mask = position.make_mask(self.config.legal, Some(*from_sq));
-                }
-                NetworkInputs::from_position(position, Some(from_sq))
-            }
-        };
-
+        let mut mask = [true; POLICY_SIZE];
+        if masked {
+            mask = self.config.ruleset.mask(position, node.selection());
+        }
         if position.side_to_move == Color::Black {
-            let mut flipped_mask = [false; 64];
-
-            for i in 0..8 {
-                let other = 7 - i;
-                flipped_mask[(i * 8)..(i * 8 + 8)].copy_from_slice(&mask[(other * 8)..(other * 8 + 8)]);
-            }
-            mask = flipped_mask;
+            mask = flip_ranks(mask);
         }
 
         let total_visits = node.get_data().visits;
 
-        let mut target_policy = [0.0; 64];
-        self.edge_arena.buffer[start..end].iter().for_each(|e| target_policy[e.square.0 as usize] += e.visits as f32 / total_visits as f32);
+        let mut target_policy = [0.0; POLICY_SIZE];
+        self.edge_arena.buffer[start..end].iter().for_each(|e| target_policy[e.selection().index()] += e.visits as f32 / total_visits as f32);
 
         let mut root_value = [0.0; 3];
         for edge in &self.edge_arena.buffer[start..end] {
@@ -472,24 +479,48 @@ impl Mcts {
         }
 
         if position.side_to_move == Color::Black {
-            let mut flipped_policy = [0.0; 64];
+            target_policy = flip_ranks(target_policy);
+        }
 
-            for i in 0..8 {
-                let other = 7 - i;
-                flipped_policy[(i * 8)..(i * 8 + 8)].copy_from_slice(&target_policy[(other * 8)..(other * 8 + 8)]);
-            }
+        // the search value is only an estimate where the tablebase knows the result
+        let value = match position.tablebase_wdl() {
+            Some(wdl) if self.config.ruleset.uses_tablebases() => wdl.value(),
+            _ => root_value,
+        };
+        let targets = NetworkLabels { policy: target_policy, value };
+        let sample = ReplaySample::new(position, node.selection(), targets, mask);
+        debug!("---- training sample ----\n{}", sample.to_sample());
+        (Some(sample), root_value)
+    }
 
-            target_policy = flipped_policy;
+    // Visit counts of the full moves searched below a root that picks the piece to move. None
+    // when the root already has its piece picked.
+    pub fn root_move_visits(&self) -> Option<Vec<(ChessMove, u32)>> {
+        let root = &self.node_arena.buffer[self.root];
+        if !matches!(root, MctsNode::PieceSelect { .. }) {
+            return None;
         }
+        let (start, end) = root.get_data().child_edge_range?;
 
-        let targets = NetworkLabels { policy: target_policy, value: root_value };
-        debug!("---- training sample ----\n{}", TrainingSample { inputs, targets, mask });
-        (Some(TrainingSample { inputs, targets, mask }), root_value)
+        let mut visits = Vec::new();
+        for from_edge in &self.edge_arena.buffer[start..end] {
+            let Some((move_start, move_end)) = from_edge.child_node_idx.and_then(|idx| self.node_arena.buffer[idx].get_data().child_edge_range)
+            else {
+                continue;
+            };
+            for to_edge in &self.edge_arena.buffer[move_start..move_end] {
+                if let Some(mov) = to_edge.mov {
+                    visits.push((mov.into(), to_edge.visits));
+                }
+            }
+        }
+        Some(visits)
     }
 
     pub fn traverse_get_terminal(&mut self) -> bool {
         let mut current_node_idx = self.root;
         self.path.clear();
+        self.history.truncate(self.root_plies);
 
         loop {
             let Some(child_edge_idx) = self.select_puct_edge(current_node_idx) else {
@@ -510,6 +541,9 @@ impl Mcts {
 
             // if edge has child, move to it, else make it
             if let Some(next_node_idx) = next_edge.child_node_idx {
+                if let MctsNode::PieceSelect { data } = &self.node_arena.buffer[next_node_idx] {
+                    self.history.push(&self.position_arena.buffer[data.chess_position_idx]);
+                }
                 current_node_idx = next_node_idx;
             } else {
                 _ = self.add_leaf(child_edge_idx).expect("Node already expanded");
@@ -519,13 +553,9 @@ impl Mcts {
         false
     }
 
-    pub fn get_mask(&self, node_idx: usize) -> [bool; 64] {
+    pub fn get_mask(&self, node_idx: usize) -> [bool; POLICY_SIZE] {
         let node = &self.node_arena.buffer[node_idx];
-
-        match node {
-            MctsNode::PieceSelect { data } => self.position_arena.buffer[data.chess_position_idx].make_mask(self.config.legal, None),
-            MctsNode::PieceMove { data, from_sq } => self.position_arena.buffer[data.chess_position_idx].make_mask(self.config.legal, Some(*from_sq)),
-        }
+        self.config.ruleset.mask(&self.position_arena.buffer[node.get_data().chess_position_idx], node.selection())
     }
 
     pub fn add_dirichlet_noise(&mut self, node_idx: usize) {
@@ -540,7 +570,7 @@ impl Mcts {
         let gamma = Gamma::new(alpha, 1.0).unwrap();
         let mut rng: SmallRng = rand::make_rng();
 
-        let noise: ArrayVec<f32, 32> = self.edge_arena.buffer[start..end].iter().map(|_| gamma.sample(&mut rng) as f32).collect();
+        let noise: ArrayVec<f32, POLICY_SIZE> = self.edge_arena.buffer[start..end].iter().map(|_| gamma.sample(&mut rng) as f32).collect();
 
         let total_noise: f32 = noise.iter().sum();
 
@@ -550,13 +580,13 @@ impl Mcts {
         });
     }
 
-    pub fn get_move_to_play(&mut self) -> Option<ChessMove> {
+    pub fn get_move_to_play(&mut self) -> Option<PackedMove> {
         if self.node_arena.buffer[self.root].get_data().is_terminal {
             return None;
         }
         let (start, end) = self.node_arena.buffer[self.root].get_data().child_edge_range.unwrap();
 
-        let ply_count = self.past_hashes.len();
+        let ply_count = self.root_plies;
         let piece_count = self.position_arena.buffer[self.node_arena.buffer[self.root].get_data().chess_position_idx].chessboard.all_pieces.count();
         let temperature = self.config.temperature * ((1.0 / (ply_count + 1) as f32 + (piece_count - 2) as f32 / 30.0) / 2.0);
 
@@ -596,19 +626,14 @@ impl Mcts {
 
         let selected_edge = &self.edge_arena.buffer[selected_edge_idx];
 
-        match self.node_arena.buffer[old_root] {
-            MctsNode::PieceMove { from_sq, .. } => {
-                if let Some(piece_type) = selected_edge.promotion_piece {
-                    let hash = self.position_arena.buffer[self.node_arena.buffer[self.root].get_data().chess_position_idx].zobrist_hash;
-                    self.past_hashes.push(hash);
-                    return Some(ChessMove::new(from_sq, selected_edge.square, Some(piece_type)));
-                }
-                let hash = self.position_arena.buffer[self.node_arena.buffer[self.root].get_data().chess_position_idx].zobrist_hash;
-                self.past_hashes.push(hash);
-                Some(ChessMove::new(from_sq, selected_edge.square, None))
-            }
-            _ => None,
-        }
+        let mov = match self.node_arena.buffer[old_root] {
+            MctsNode::PieceMove { .. } | MctsNode::PieceDrop { .. } => selected_edge.mov.expect("move edges carry their move"),
+            MctsNode::PieceSelect { .. } => return None,
+        };
+        self.history.truncate(self.root_plies);
+        self.history.push(&self.position_arena.buffer[self.node_arena.buffer[self.root].get_data().chess_position_idx]);
+        self.root_plies = self.history.len();
+        Some(mov)
     }
 }
 
@@ -628,45 +653,21 @@ pub fn expand_batch<B: Backend>(mctss: &mut [Mcts], model: ChessTransformer<B>,
             let node = &game.node_arena.buffer[node_idx];
 
             if node.get_data().is_terminal {
-                return [false; 64];
+                return [false; POLICY_SIZE];
             }
             unique += 1;
 
-            let flip_mask = |mask: [bool; 64]| {
-                let mut flipped_mask = [false; 64];
-
-                for i in 0..8 {
-                    let other = 7 - i;
-                    flipped_mask[(i * 8)..(i * 8 + 8)].copy_from_slice(&mask[(other * 8)..(other * 8 + 8)]);
-                }
-                flipped_mask
-            };
-
             let position_idx = node.get_data().chess_position_idx;
             let position = &game.position_arena.buffer[position_idx];
-            match node {
-                MctsNode::PieceSelect { .. } => {
-                    let mut mask = position.make_mask(config.legal, None);
-                    if position.side_to_move == Color::Black {
-                        mask = flip_mask(mask);
-                    };
-                    mask
-                }
-                MctsNode::PieceMove { from_sq, .. } => {
-                    let mut mask = position.make_mask(config.legal, Some(*from_sq));
-                    if position.side_to_move == Color::Black {
-                        mask = flip_mask(mask);
-                    }
-                    mask
-                }
-            }
+            let mask = game.config.ruleset.mask(position, node.selection());
+            if position.side_to_move == Color::Black { flip_ranks(mask) } else { mask }
         })
         .collect();
 
     // generate outputs
-    let mut mask_in: Vec<bool> = vec![true; config.batch_size * 64];
+    let mut mask_in: Vec<bool> = vec![true; config.batch_size * POLICY_SIZE];
     if config.masked {
-        mask_in.par_chunks_mut(64).zip(masks.par_iter()).for_each(|(dest_chunk, src_mask)| {
+        mask_in.par_chunks_mut(POLICY_SIZE).zip(masks.par_iter()).for_each(|(dest_chunk, src_mask)| {
             dest_chunk.copy_from_slice(src_mask);
         });
     }
@@ -680,15 +681,20 @@ pub fn expand_batch<B: Backend>(mctss: &mut [Mcts], model: ChessTransformer<B>,
         .map(|((game, output), mask)| {
             let node_idx = game.node_to_expand().expect("path is None");
             let position = &game.get_position(node_idx);
-            let (mut policy, value) = (output.as_squares(), output.value);
+            let (mut policy, value) = (output.as_selections(), output.value);
             let node_to_expand = &game.node_arena.buffer[node_idx];
             if node_to_expand.get_data().is_terminal {
                 return 0.0;
             }
             assert!(node_to_expand.get_data().child_edge_range.is_none());
 
-            let rate: f64 = mask.iter().zip(policy.iter()).map(|(legal, policy)| if !legal { policy.1 as f64 } else { 0.0 }).sum();
+            // the moves of the picked piece, its edges play them without looking at the board again
+            let moves: ArrayVec<PackedMove, MAX_MOVES> = match node_to_expand.selection() {
+                Some(selection) => game.config.ruleset.packed_moves(position).into_iter().filter(|&mov| Selection::of(mov) == selection).collect(),
+                None => ArrayVec::new(),
+            };
 
+            let rate: f64 = mask.iter().zip(policy.iter()).map(|(legal, policy)| if !legal { policy.1 as f64 } else { 0.0 }).sum();
 
             // debug!("---- chess position ----\n{}\n---- chess position -----", position);
             debug!("\n---- network output ----\n{}", output);
@@ -702,33 +708,31 @@ pub fn expand_batch<B: Backend>(mctss: &mut [Mcts], model: ChessTransformer<B>,
                 .into_par_iter()
                 .zip(policy.into_par_iter())
                 .map(|(legal, policy)| {
-                    let (mut sq, score) = (policy.0, policy.1);
+                    let (selection, score) = (policy.0, policy.1);
                     let mut edges = Vec::new();
 
                     if legal {
+                        let sq = match selection {
+                            Selection::Square(sq) if position.side_to_move == Color::Black => sq.square_opposite(),
+                            Selection::Square(sq) => sq,
+                            Selection::Pocket(_) => ChessSquare::A1,
+                        };
                         match node_to_expand {
-                            MctsNode::PieceMove { from_sq, .. } => {
-                                if position.side_to_move == Color::Black {
-                                    sq = sq.square_opposite();
-                                };
-                                let mov = ChessMove::new(*from_sq, sq, None);
-                                if let Some(moves) = position.expand_if_prom(mov) {
-                                    for mov in moves {
-                                        let edge = MctsEdge::new(sq, score / 4.0, node_idx).with_prom(mov.promotion.unwrap());
-                                        trace!("adding edge: {}", edge);
-                                        edges.push(edge);
-                                    }
-                                } else {
-                                    let edge = MctsEdge::new(sq, score, node_idx);
+                            // promotions share the square's score
+                            MctsNode::PieceMove { .. } | MctsNode::PieceDrop { .. } => {
+                                let to_sq: ArrayVec<PackedMove, 5> = moves.iter().copied().filter(|mov| mov.to() == sq).collect();
+                                let share = score / to_sq.len() as f32;
+                                for mov in to_sq {
+                                    let edge = MctsEdge::new(sq, share, node_idx).with_move(mov);
                                     trace!("adding edge: {}", edge);
                                     edges.push(edge);
                                 }
                             }
                             MctsNode::PieceSelect { .. } => {
-                                if position.side_to_move == Color::Black {
-                                    sq = sq.square_opposite();
+                                let mut edge = MctsEdge::new(sq, score, node_idx);
+                                if let Selection::Pocket(piece) = selection {
+                                    edge = edge.with_drop(piece);
                                 }
-                                let edge = MctsEdge::new(sq, score, node_idx);
                                 trace!("adding edge: {}", edge);
                                 edges.push(edge);
                             }
@@ -755,3 +759,48 @@ pub fn expand_batch<B: Backend>(mctss: &mut [Mcts], model: ChessTransformer<B>,
         .sum();
     (unique, illegal_rate / config.batch_size as f64)
 }
+
+// How long an analysis search runs, in simulations or wall time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchBudget {
+    Nodes(usize),
+    Time(Duration),
+}
+
+// Searches a single game with the model and returns the most visited full move along with the
+// number of simulations run. None when the position has no moves.
+pub fn search<B: Backend>(
+    game: &ChessGame,
+    model: &ChessTransformer<B>,
+    mcts_config: MctsConfig,
+    config: &TrainingConfig,
+    budget: SearchBudget,
+    device: &B::Device,
+) -> (Option<ChessMove>, usize) {
+    if mcts_config.ruleset.moves(&game.position).is_empty() {
+        return (None, 0);
+    }
+
+    let mut config = config.clone();
+    config.batch_size = 1;
+    config.masked = true;
+
+    let mut mcts = Mcts::from_game(game, 65536, mcts_config, config.seed);
+    let start = Instant::now();
+    let mut simulations = 0;
+    loop {
+        let done = match budget {
+            SearchBudget::Nodes(nodes) => simulations >= nodes,
+            SearchBudget::Time(limit) => simulations > 0 && start.elapsed() >= limit,
+        };
+        if done {
+            break;
+        }
+        mcts.traverse_get_terminal();
+        expand_batch(std::slice::from_mut(&mut mcts), model.clone(), &config, device);
+        simulations += 1;
+    }
+
+    let best = mcts.root_move_visits().and_then(|visits| visits.into_iter().max_by_key(|(_, visits)| *visits)).map(|(mov, _)| mov);
+    (best, simulations)
+}
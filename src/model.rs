@@ -8,16 +8,17 @@ use burn::{
     train::{ClassificationOutput, InferenceStep, TrainOutput, TrainStep},
 };
 
-use crate::data::ChessBatch;
+use crate::data::{ChessBatch, META_SIZE, POLICY_SIZE};
 
-// 2 pass encoder: select from sq, populate plane 14, select to square
+// 2 pass encoder: select from sq or pocket piece, populate plane 14 or the meta, select to square
 #[derive(Module, Debug)]
 pub struct ChessTransformer<B: Backend> {
     piece_encoder: Linear<B>, // 64 x 14 (12 piece plane + en pasant plane + selected sq plane)
-    meta_encoder: Linear<B>,  // This is just castling rights and 50 move counter (4 1-hot + 1 scalar)
+    meta_encoder: Linear<B>,  // castling rights, 50 move counter and Three-check checks, see META_SIZE
     coordinates: Tensor<B, 2, Int>,
     pos_embedding_x: Embedding<B>,
     pos_embedding_y: Embedding<B>,
+    pocket_embedding: Embedding<B>, // a token per piece that can be dropped, their policy is the pick from the pocket
     transformer: TransformerEncoder<B>,
     policy: Linear<B>, // Just pick one square
     value: Linear<B>,
@@ -37,10 +38,11 @@ impl ChessTransformerConfig {
         let coordinates = Tensor::arange(0..8, device).unsqueeze_dim(0);
         ChessTransformer {
             piece_encoder: LinearConfig::new(14, self.d_model).init(device),
-            meta_encoder: LinearConfig::new(5, self.d_model).init(device),
+            meta_encoder: LinearConfig::new(META_SIZE, self.d_model).init(device),
             coordinates,
             pos_embedding_x: EmbeddingConfig::new(8, self.d_model).init(device),
             pos_embedding_y: EmbeddingConfig::new(8, self.d_model).init(device),
+            pocket_embedding: EmbeddingConfig::new(POLICY_SIZE - 64, self.d_model).init(device),
             transformer: TransformerEncoderConfig::new(self.d_model, self.d_ff, self.n_heads, self.n_layers).with_dropout(0.0).init(device),
             policy: LinearConfig::new(self.d_model, 1).init(device),
             value: LinearConfig::new(self.d_model, 3).init(device),
@@ -83,18 +85,22 @@ impl<B: Backend> ChessTransformer<B> {
         let pos_flat = pos2d.reshape([1, 64, self.d_model]);
         x = x + pos_flat;
 
-        // batch_size x d_model -> batchsize x 64 x d_model
+        // 1 x 5 x d_model -> batch_size x 5 x d_model, the pockets' counts are in the meta token
+        let pocket_ids = self.coordinates.clone().slice([0..1, 0..POLICY_SIZE - 64]);
+        let pockets = self.pocket_embedding.forward(pocket_ids).repeat_dim(0, batch_size);
+
+        // batch_size x d_model -> batchsize x 1 x d_model
         let meta_x = self.meta_encoder.forward(meta).unsqueeze_dim(1);
-        let x = Tensor::cat(vec![x, meta_x], 1);
+        let x = Tensor::cat(vec![x, pockets, meta_x], 1);
 
         let x = self.transformer.forward(TransformerEncoderInput::new(x));
 
         // batch_size x 1 x d_model -> batch_size x 3
-        let value_latent = x.clone().slice([0..batch_size, 64..65]).squeeze_dim(1);
+        let value_latent = x.clone().slice([0..batch_size, POLICY_SIZE..POLICY_SIZE + 1]).squeeze_dim(1);
         let value = self.value.forward(value_latent);
 
-        // batch_size x 64 x d_model -> batch_size x 64
-        let board_latent = x.slice([0..batch_size, 0..64]);
+        // batch_size x POLICY_SIZE x d_model -> batch_size x POLICY_SIZE
+        let board_latent = x.slice([0..batch_size, 0..POLICY_SIZE]);
         let policy = self.policy.forward(board_latent).squeeze_dim(2);
 
         (policy, value)
@@ -0,0 +1,333 @@
+use core::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::chess_game::{Outcome, Termination};
+use crate::{
+    Bitboard, CastlingRights, ChessBoard, ChessGame, ChessPiece, ChessPosition, ChessSquare, Color, FenError, NetworkInputs, PackedMove, PieceType,
+    PositionBuilder, Selection, Variant,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PackError {
+    Truncated { needed: usize, found: usize },
+    TooManyPieces(u32),
+    PromotedPiece(u8),
+    InvalidPiece(u8),
+    InvalidSquare(u8),
+    InvalidVariant(u8),
+    Fen(FenError),
+    InvalidOutcome { winner: u8, termination: u8 },
+    IllegalMove { ply: usize, raw: u16 },
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PackError::Truncated { needed, found } => write!(f, "needed {} bytes, found {}", needed, found),
+            PackError::TooManyPieces(count) => write!(f, "{} pieces do not fit", count),
+            PackError::PromotedPiece(sq) => write!(f, "promoted piece on square {} does not fit", sq),
+            PackError::InvalidPiece(code) => write!(f, "invalid piece code {}", code),
+            PackError::InvalidSquare(sq) => write!(f, "invalid square {}", sq),
+            PackError::InvalidVariant(index) => write!(f, "invalid variant {}", index),
+            PackError::Fen(e) => write!(f, "invalid position: {}", e),
+            PackError::InvalidOutcome { winner, termination } => write!(f, "invalid outcome {}/{}", winner, termination),
+            PackError::IllegalMove { ply, raw } => write!(f, "ply {}: move {:#06x} is not legal", ply, raw),
+        }
+    }
+}
+
+impl std::error::Error for PackError {}
+
+// nibbles 0-11 are the pieces by colour and type, the two above mark rooks that still castle
+const WHITE_CASTLING_ROOK: u8 = 12;
+const BLACK_CASTLING_ROOK: u8 = 13;
+const NO_SQUARE: u8 = 64;
+// in the flags byte after side to move and Chess960, the variant takes the three bits above
+const VARIANT_SHIFT: u8 = 2;
+const VARIANT_START: u8 = 1 << 5;
+
+// A position in 32 bytes: the occupancy, one nibble per occupied square in square order, then
+// side to move and flags, en passant square, the halfmove clock, the fullmove counter, the
+// Three-check checks given and the number of Crazyhouse pocket pieces, whose nibbles follow the
+// board's. Castling rights are stored on their rooks so Chess960 files come for free. Crazyhouse
+// has no checks to count, so bytes 29 and 31 flag which of the first 16 knights, bishops, rooks
+// and queens on the board were promoted. Horde's start position doesn't fit and is stored as just
+// its variant, other Horde positions with more than 32 pieces don't fit at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PackedPosition(pub [u8; PackedPosition::SIZE]);
+
+impl PackedPosition {
+    pub const SIZE: usize = 32;
+
+    // Horde positions past the start position can hold more than 32 pieces, those don't fit
+    pub fn new(position: &ChessPosition) -> Result<Self, PackError> {
+        let mut bytes = [0; Self::SIZE];
+        let board = &position.chessboard;
+        let variant = Variant::ALL.iter().position(|&variant| variant == position.variant).unwrap() as u8;
+        if board.all_pieces.count() > 32 {
+            if position.zobrist_hash != position.variant.start_position().zobrist_hash {
+                return Err(PackError::TooManyPieces(board.all_pieces.count()));
+            }
+            bytes[24] = VARIANT_START | variant << VARIANT_SHIFT;
+            return Ok(Self(bytes));
+        }
+
+        bytes[0..8].copy_from_slice(&board.all_pieces.0.to_le_bytes());
+
+        let castling_rooks =
+            [CastlingRights::WHITE_KINGSIDE, CastlingRights::WHITE_QUEENSIDE, CastlingRights::BLACK_KINGSIDE, CastlingRights::BLACK_QUEENSIDE]
+                .into_iter()
+                .filter(|right| position.castling_rights.has(*right))
+                .fold(Bitboard::EMPTY, |rooks, right| rooks | position.castling_rights.rook_square(right).bitboard());
+
+        let mut occupied = board.all_pieces;
+        let mut i = 0;
+        while let Some(sq) = occupied.pop_lsb() {
+            let piece = board.get_piece_at(sq).expect("occupied square without a piece");
+            let code = match (castling_rooks.is_set(sq), piece.color) {
+                (true, Color::White) => WHITE_CASTLING_ROOK,
+                (true, Color::Black) => BLACK_CASTLING_ROOK,
+                (false, color) => 6 * color as u8 + piece.piece_type as u8,
+            };
+            bytes[8 + i / 2] |= code << (4 * (i % 2));
+            i += 1;
+        }
+
+        // there are never more than 32 pieces between the board and the pockets
+        for color in [Color::White, Color::Black] {
+            for piece_type in [PieceType::Pawn, PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen] {
+                for _ in 0..position.pockets[color as usize][piece_type as usize] {
+                    bytes[8 + i / 2] |= (6 * color as u8 + piece_type as u8) << (4 * (i % 2));
+                    i += 1;
+                }
+            }
+        }
+        bytes[30] = (i - board.all_pieces.count() as usize) as u8;
+
+        bytes[24] = position.side_to_move as u8 | (position.chess960 as u8) << 1 | variant << VARIANT_SHIFT;
+        bytes[25] = position.en_passant.map_or(NO_SQUARE, |sq| sq.0);
+        bytes[26] = position.halfmove_clock.min(u8::MAX as u32) as u8;
+        bytes[27..29].copy_from_slice(&(position.fullmove_counter.min(u16::MAX as u32) as u16).to_le_bytes());
+        if position.variant == Variant::Crazyhouse {
+            let flags = promotable(board)
+                .enumerate()
+                .filter(|&(_, sq)| position.promoted.is_set(sq))
+                .try_fold(0u16, |flags, (i, sq)| if i < 16 { Ok(flags | 1 << i) } else { Err(PackError::PromotedPiece(sq.0)) })?;
+            [bytes[29], bytes[31]] = flags.to_le_bytes();
+        } else {
+            bytes[29] = position.checks[0].min(15) | position.checks[1].min(15) << 4;
+        }
+        Ok(Self(bytes))
+    }
+
+    // checked like a FEN, so corrupt data can't produce a position the move generator trips over
+    pub fn unpack(&self) -> Result<ChessPosition, PackError> {
+        let bytes = &self.0;
+        let index = bytes[24] >> VARIANT_SHIFT & 7;
+        let variant = *Variant::ALL.get(index as usize).ok_or(PackError::InvalidVariant(index))?;
+        if bytes[24] & VARIANT_START != 0 {
+            return Ok(variant.start_position());
+        }
+
+        let mut occupied = Bitboard(u64::from_le_bytes(bytes[0..8].try_into().unwrap()));
+        if occupied.count() > 32 {
+            return Err(PackError::TooManyPieces(occupied.count()));
+        }
+
+        let mut chessboard = ChessBoard::empty();
+        let mut castling_rooks = Vec::new();
+        let mut i = 0;
+        while let Some(sq) = occupied.pop_lsb() {
+            let code = bytes[8 + i / 2] >> (4 * (i % 2)) & 0xF;
+            i += 1;
+            let piece = match code {
+                0..=5 => ChessPiece { color: Color::White, piece_type: PieceType::from_idx(code as usize).unwrap() },
+                6..=11 => ChessPiece { color: Color::Black, piece_type: PieceType::from_idx(code as usize - 6).unwrap() },
+                WHITE_CASTLING_ROOK => ChessPiece { color: Color::White, piece_type: PieceType::Rook },
+                BLACK_CASTLING_ROOK => ChessPiece { color: Color::Black, piece_type: PieceType::Rook },
+                _ => return Err(PackError::InvalidPiece(code)),
+            };
+            chessboard.add_piece(piece, sq);
+            if code >= WHITE_CASTLING_ROOK {
+                castling_rooks.push((piece.color, sq));
+            }
+        }
+
+        let in_pockets = bytes[30] as u32;
+        if i as u32 + in_pockets > 32 {
+            return Err(PackError::TooManyPieces(i as u32 + in_pockets));
+        }
+        let mut pockets = [[0; 5]; 2];
+        for i in i..i + in_pockets as usize {
+            let code = bytes[8 + i / 2] >> (4 * (i % 2)) & 0xF;
+            match (code / 6, PieceType::from_idx(code as usize % 6)) {
+                (color @ 0..=1, Some(piece_type)) if piece_type != PieceType::King => pockets[color as usize][piece_type as usize] += 1,
+                _ => return Err(PackError::InvalidPiece(code)),
+            }
+        }
+
+        let mut rights = CastlingRights::empty();
+        for (color, sq) in castling_rooks {
+            rights |= castling_right(&chessboard, color, sq)?;
+        }
+
+        let en_passant = match bytes[25] {
+            NO_SQUARE => None,
+            sq => Some(ChessSquare::new(sq).ok_or(PackError::InvalidSquare(sq))?),
+        };
+        let (checks, promoted) = match variant {
+            Variant::Crazyhouse => {
+                let flags = u16::from_le_bytes([bytes[29], bytes[31]]);
+                let promoted = promotable(&chessboard).take(16).enumerate().filter(|&(i, _)| flags >> i & 1 != 0);
+                ([0, 0], promoted.fold(Bitboard::EMPTY, |promoted, (_, sq)| promoted | sq.bitboard()))
+            }
+            _ => ([bytes[29] & 0xF, bytes[29] >> 4], Bitboard::EMPTY),
+        };
+        let mut position = PositionBuilder::from_board(chessboard)
+            .variant(variant)
+            .checks(checks)
+            .promoted(promoted)
+            .pockets(pockets)
+            .side_to_move(if bytes[24] & 1 == 0 { Color::White } else { Color::Black })
+            .castling_rights(rights)
+            .en_passant(en_passant)
+            .halfmove_clock(bytes[26] as u32)
+            .fullmove_counter(u16::from_le_bytes([bytes[27], bytes[28]]) as u32)
+            .build_allowing_check()
+            .map_err(PackError::Fen)?;
+        position.chess960 |= bytes[24] & 2 != 0;
+        Ok(position)
+    }
+
+    // the network inputs of the position, with a selected piece for the to-square pass
+    pub fn network_inputs(&self, selected: Option<Selection>) -> Result<NetworkInputs, PackError> {
+        Ok(NetworkInputs::from_position(&self.unpack()?, selected))
+    }
+}
+
+// the knights, bishops, rooks and queens on the board in square order, the pieces that can be promoted
+fn promotable(board: &ChessBoard) -> impl Iterator<Item = ChessSquare> {
+    let mut pieces =
+        [PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen].into_iter().fold(Bitboard::EMPTY, |pieces, piece_type| {
+            pieces | board.get_piece_bitboard(Color::White, piece_type) | board.get_piece_bitboard(Color::Black, piece_type)
+        });
+    core::iter::from_fn(move || pieces.pop_lsb())
+}
+
+// the right held by a castling rook on sq, kingside when it stands on the far side of its king
+fn castling_right(board: &ChessBoard, color: Color, sq: ChessSquare) -> Result<CastlingRights, PackError> {
+    let home_rank = if color == Color::White { 0 } else { 7 };
+    let king = board.get_piece_bitboard(color, PieceType::King).lsb_square().filter(|king| king.rank() == home_rank);
+    match king {
+        Some(king) if sq.rank() == home_rank => {
+            let (kingside, queenside) = CastlingRights::for_color(color);
+            Ok(CastlingRights::with_rook_file(if sq.file() > king.file() { kingside } else { queenside }, sq.file()))
+        }
+        _ => Err(PackError::InvalidSquare(sq.0)),
+    }
+}
+
+// A game as its start position, the outcome and its moves as raw PackedMoves, kinds included so
+// they replay without a lookup. Records are self-delimiting so a file of games is just their bytes
+// one after another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedGame {
+    pub start:   PackedPosition,
+    pub outcome: Outcome,
+    pub moves:   Vec<u16>,
+}
+
+impl PackedGame {
+    const HEADER: usize = PackedPosition::SIZE + 4;
+
+    pub fn new(game: &ChessGame) -> Result<Self, PackError> {
+        Ok(Self {
+            start:   PackedPosition::new(&game.start_position)?,
+            outcome: game.outcome,
+            moves:   game.undo_stack.iter().map(|undo| undo.mov.raw()).collect(),
+        })
+    }
+
+    // Replays the moves, each has to be one the start position's side could play. Games under
+    // king capture rules can hold moves that leave the king in check, so pseudolegal moves pass.
+    pub fn unpack(&self) -> Result<ChessGame, PackError> {
+        let mut game = ChessGame::from_position(self.start.unpack()?);
+        for (ply, &raw) in self.moves.iter().enumerate() {
+            let mov =
+                PackedMove::from_raw(raw).filter(|mov| game.position.pseudolegal_moves.contains(mov)).ok_or(PackError::IllegalMove { ply, raw })?;
+            game.make_packed(mov);
+        }
+        game.outcome = self.outcome;
+        Ok(game)
+    }
+
+    pub fn len(&self) -> usize {
+        Self::HEADER + 2 * self.moves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len());
+        bytes.extend_from_slice(&self.start.0);
+        let (winner, termination) = match self.outcome {
+            Outcome::Unfinished => (0, 0),
+            Outcome::Finished(winner, termination) => {
+                let winner = match winner {
+                    None => 1,
+                    Some(Color::White) => 2,
+                    Some(Color::Black) => 3,
+                };
+                (winner, Termination::ALL.iter().position(|&t| t == termination).unwrap() as u8)
+            }
+        };
+        bytes.extend_from_slice(&[winner, termination]);
+        bytes.extend_from_slice(&(self.moves.len() as u16).to_le_bytes());
+        self.moves.iter().for_each(|raw| bytes.extend_from_slice(&raw.to_le_bytes()));
+        bytes
+    }
+
+    // the game at the front of bytes and how many bytes it took
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), PackError> {
+        if bytes.len() < Self::HEADER {
+            return Err(PackError::Truncated { needed: Self::HEADER, found: bytes.len() });
+        }
+        let start = PackedPosition(bytes[..PackedPosition::SIZE].try_into().unwrap());
+        let header = &bytes[PackedPosition::SIZE..Self::HEADER];
+        let outcome = match (header[0], Termination::ALL.get(header[1] as usize)) {
+            (0, _) => Outcome::Unfinished,
+            (1, Some(&termination)) => Outcome::Finished(None, termination),
+            (2, Some(&termination)) => Outcome::Finished(Some(Color::White), termination),
+            (3, Some(&termination)) => Outcome::Finished(Some(Color::Black), termination),
+            _ => return Err(PackError::InvalidOutcome { winner: header[0], termination: header[1] }),
+        };
+
+        let count = u16::from_le_bytes([header[2], header[3]]) as usize;
+        let end = Self::HEADER + 2 * count;
+        if bytes.len() < end {
+            return Err(PackError::Truncated { needed: end, found: bytes.len() });
+        }
+        let moves = bytes[Self::HEADER..end].chunks_exact(2).map(|raw| u16::from_le_bytes([raw[0], raw[1]])).collect();
+        Ok((Self { start, outcome, moves }, end))
+    }
+
+    pub fn append(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        OpenOptions::new().create(true).append(true).open(path)?.write_all(&self.to_bytes())
+    }
+
+    pub fn read_all(path: impl AsRef<Path>) -> io::Result<Vec<PackedGame>> {
+        let bytes = fs::read(path)?;
+        let mut games = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let (game, len) = PackedGame::from_bytes(&bytes[offset..]).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            games.push(game);
+            offset += len;
+        }
+        Ok(games)
+    }
+}
@@ -0,0 +1,189 @@
+use core::fmt;
+use std::io::{self, BufRead};
+
+use arrayvec::ArrayVec;
+use rayon::prelude::*;
+
+use crate::{ChessGame, ChessMove, ChessPosition, MAX_MOVES, PackedMove};
+
+pub struct PerftCase {
+    pub name:  &'static str,
+    pub fen:   &'static str,
+    pub nodes: &'static [u64], // expected node count at depth 1, 2, 3...
+}
+
+// https://www.chessprogramming.org/Perft_Results
+pub const PERFT_SUITE: [PerftCase; 6] = [
+    PerftCase {
+        name:  "startpos",
+        fen:   "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        nodes: &[20, 400, 8902, 197281, 4865609, 119060324],
+    },
+    PerftCase {
+        name:  "kiwipete",
+        fen:   "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        nodes: &[48, 2039, 97862, 4085603, 193690690],
+    },
+    PerftCase { name: "position 3", fen: "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", nodes: &[14, 191, 2812, 43238, 674624, 11030083] },
+    PerftCase {
+        name:  "position 4",
+        fen:   "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        nodes: &[6, 264, 9467, 422333, 15833292],
+    },
+    PerftCase {
+        name:  "position 5",
+        fen:   "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        nodes: &[44, 1486, 62379, 2103487, 89941194],
+    },
+    PerftCase {
+        name:  "position 6",
+        fen:   "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+        nodes: &[46, 2079, 89890, 3894594, 164075551],
+    },
+];
+
+#[derive(Debug, Clone)]
+pub struct PerftResult {
+    pub name:     &'static str,
+    pub depth:    u32,
+    pub expected: u64,
+    pub actual:   u64,
+}
+
+impl PerftResult {
+    pub fn passed(&self) -> bool {
+        self.expected == self.actual
+    }
+}
+
+impl fmt::Display for PerftResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = if self.passed() { "ok" } else { "FAILED" };
+        write!(f, "{} depth {}: expected {}, got {} ... {}", self.name, self.depth, self.expected, self.actual, status)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Divide {
+    pub moves: Vec<(ChessMove, u64)>,
+}
+
+impl Divide {
+    pub fn total(&self) -> u64 {
+        self.moves.iter().map(|(_, nodes)| nodes).sum()
+    }
+
+    // Reads the layout below, as Stockfish prints it for `go perft`, up to the total. Other lines,
+    // like the banner or info strings, are skipped.
+    pub fn read(reader: &mut impl BufRead) -> io::Result<Self> {
+        let mut line = String::new();
+        let mut divide = Divide::default();
+
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 || line.starts_with("Nodes searched") {
+                return Ok(divide);
+            }
+            if let Some((uci, nodes)) = line.trim().split_once(": ")
+                && let (Ok(mov), Ok(nodes)) = (ChessMove::from_uci(uci), nodes.parse::<u64>())
+            {
+                divide.moves.push((mov, nodes));
+            }
+        }
+    }
+}
+
+// same layout as stockfish's `go perft` so the two can be diffed directly
+impl fmt::Display for Divide {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (mov, nodes) in &self.moves {
+            writeln!(f, "{}: {}", mov.to_uci(), nodes)?;
+        }
+        write!(f, "\nNodes searched: {}", self.total())
+    }
+}
+
+fn legal_moves(position: &ChessPosition) -> impl Iterator<Item = PackedMove> {
+    position.pseudolegal_moves.iter().copied().filter(|&mov| position.is_legal_packed(mov))
+}
+
+fn perft_inner(position: &mut ChessPosition, depth: u32) -> u64 {
+    if depth == 1 {
+        return legal_moves(position).count() as u64;
+    }
+
+    let moves: ArrayVec<PackedMove, MAX_MOVES> = legal_moves(position).collect();
+    moves
+        .iter()
+        .map(|&mov| {
+            let undo = position.make_packed(mov);
+            let nodes = perft_inner(position, depth - 1);
+            position.unmake_packed(&undo);
+            nodes
+        })
+        .sum()
+}
+
+pub fn perft(position: &ChessPosition, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let mut root = position.clone();
+    root.generate_pseudolegal();
+    perft_inner(&mut root, depth)
+}
+
+pub fn perft_parallel(position: &ChessPosition, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    divide(position, depth, true).total()
+}
+
+pub fn divide(position: &ChessPosition, depth: u32, parallel: bool) -> Divide {
+    if depth == 0 {
+        return Divide::default();
+    }
+    let mut root = position.clone();
+    root.generate_pseudolegal();
+
+    let count = |&mov: &PackedMove| {
+        if depth == 1 {
+            return (mov.into(), 1);
+        }
+        let mut child = root.clone();
+        child.make_packed(mov);
+        (mov.into(), perft_inner(&mut child, depth - 1))
+    };
+
+    let root_moves: Vec<PackedMove> = legal_moves(&root).collect();
+    let mut moves: Vec<(ChessMove, u64)> = if parallel {
+        root_moves.par_iter().map(count).collect()
+    } else {
+        root_moves.iter().map(count).collect()
+    };
+    moves.sort_by_key(|(mov, _)| mov.to_uci());
+
+    Divide { moves }
+}
+
+// runs every suite depth whose expected node count is at most max_nodes
+pub fn run_suite(max_nodes: u64, parallel: bool) -> Vec<PerftResult> {
+    let mut results = Vec::new();
+    for case in PERFT_SUITE.iter() {
+        let game = ChessGame::from_fen(case.fen).expect("invalid perft suite fen");
+        for (i, &expected) in case.nodes.iter().enumerate() {
+            if expected > max_nodes {
+                break;
+            }
+            let depth = i as u32 + 1;
+            let actual = if parallel {
+                perft_parallel(&game.position, depth)
+            } else {
+                perft(&game.position, depth)
+            };
+            results.push(PerftResult { name: case.name, depth, expected, actual });
+        }
+    }
+    results
+}
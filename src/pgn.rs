@@ -0,0 +1,569 @@
+use core::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use crate::chess_game::{Outcome, Termination};
+use crate::{ChessGame, ChessMove, ChessPosition, Color, DrawRules, FenError, Ruleset, SanError, Variant, parse_variant_fen};
+
+pub const SEVEN_TAG_ROSTER: [&str; 7] = ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+const LINE_WIDTH: usize = 80;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgnErrorKind {
+    Io(String),
+    InvalidTag(String),
+    UnterminatedString,
+    UnterminatedComment,
+    UnexpectedToken(String),
+    UnbalancedVariation,
+    Fen(FenError),
+    UnknownVariant(String),
+    San { ply: usize, error: SanError },
+}
+
+// game is the 1-based index of the game in the stream, line where its text starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgnError {
+    pub game: usize,
+    pub line: usize,
+    pub kind: PgnErrorKind,
+}
+
+impl fmt::Display for PgnErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PgnErrorKind::Io(e) => write!(f, "io error: {}", e),
+            PgnErrorKind::InvalidTag(tag) => write!(f, "invalid tag pair '{}'", tag),
+            PgnErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            PgnErrorKind::UnterminatedComment => write!(f, "unterminated comment"),
+            PgnErrorKind::UnexpectedToken(token) => write!(f, "unexpected token '{}'", token),
+            PgnErrorKind::UnbalancedVariation => write!(f, "unbalanced variation parentheses"),
+            PgnErrorKind::Fen(e) => write!(f, "invalid FEN tag: {}", e),
+            PgnErrorKind::UnknownVariant(name) => write!(f, "unknown variant '{}'", name),
+            PgnErrorKind::San { ply, error } => write!(f, "ply {}: {}", ply, error),
+        }
+    }
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "game {} (line {}): {}", self.game, self.line, self.kind)
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgnNode {
+    pub mov: ChessMove,
+    pub nags: Vec<u8>,
+    pub starting_comment: Option<String>, // only used on the first move of a variation
+    pub comment: Option<String>,
+    pub variations: Vec<Vec<PgnNode>>, // alternatives to this move
+}
+
+impl PgnNode {
+    pub fn new(mov: ChessMove) -> Self {
+        Self { mov, nags: Vec::new(), starting_comment: None, comment: None, variations: Vec::new() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PgnGame {
+    pub tags:    Vec<(String, String)>,
+    pub comment: Option<String>,
+    pub moves:   Vec<PgnNode>,
+    pub game:    ChessGame, // the mainline played out
+}
+
+impl PgnGame {
+    pub fn from_game(game: &ChessGame) -> Self {
+        let mut pgn = PgnGame {
+            tags:    Vec::new(),
+            comment: None,
+            moves:   game.move_list.iter().map(|mov| PgnNode::new(*mov)).collect(),
+            game:    game.clone(),
+        };
+        pgn.set_tag("Result", result_token(&game.outcome));
+        if let Outcome::Finished(_, termination) = game.outcome {
+            pgn.set_tag("Termination", termination.name());
+        }
+        if game.start_position.variant != Variant::Standard {
+            pgn.set_tag("Variant", game.start_position.variant.name());
+        }
+        pgn
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(key, _)| key == name) {
+            Some(tag) => tag.1 = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn start_position(&self) -> &ChessPosition {
+        &self.game.start_position
+    }
+
+    pub fn result(&self) -> &str {
+        match self.tag("Result") {
+            Some(result) => result,
+            None => result_token(&self.game.outcome),
+        }
+    }
+}
+
+fn result_token(outcome: &Outcome) -> &'static str {
+    match outcome {
+        Outcome::Finished(Some(Color::White), _) => "1-0",
+        Outcome::Finished(Some(Color::Black), _) => "0-1",
+        Outcome::Finished(None, _) => "1/2-1/2",
+        Outcome::Unfinished => "*",
+    }
+}
+
+// The reason comes from a Termination tag we wrote ourselves, or from the final position when the
+// rules ended the game there. Anything else, resignations and agreed draws included, was decided off the board.
+fn result_outcome(result: &str, termination: Option<&str>, game: &ChessGame) -> Outcome {
+    let winner = match result {
+        "1-0" => Some(Color::White),
+        "0-1" => Some(Color::Black),
+        "1/2-1/2" => None,
+        _ => return Outcome::Unfinished,
+    };
+    let termination =
+        termination.and_then(Termination::from_name).unwrap_or_else(|| match game.check_game_state(Ruleset::Standard, &DrawRules::FIDE) {
+            Outcome::Finished(rules_winner, termination) if rules_winner == winner => termination,
+            _ => Termination::Adjudication,
+        });
+    Outcome::Finished(winner, termination)
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn write_line(tokens: &mut Vec<String>, start: &ChessPosition, nodes: &[PgnNode]) {
+    let mut position = start.clone();
+    let mut need_number = true;
+
+    for node in nodes {
+        if let Some(comment) = &node.starting_comment {
+            tokens.push(format!("{{{}}}", comment.replace('}', ")")));
+            need_number = true;
+        }
+
+        if position.side_to_move == Color::White {
+            tokens.push(format!("{}.", position.fullmove_counter));
+        } else if need_number {
+            tokens.push(format!("{}...", position.fullmove_counter));
+        }
+        tokens.push(node.mov.to_san(&position));
+        tokens.extend(node.nags.iter().map(|nag| format!("${}", nag)));
+
+        need_number = false;
+        if let Some(comment) = &node.comment {
+            tokens.push(format!("{{{}}}", comment.replace('}', ")")));
+            need_number = true;
+        }
+        for variation in &node.variations {
+            tokens.push("(".to_string());
+            write_line(tokens, &position, variation);
+            tokens.push(")".to_string());
+            need_number = true;
+        }
+
+        position.make_move(&node.mov);
+    }
+}
+
+// export format: seven tag roster first, SetUp/FEN for starts other than the variant's, then the remaining tags
+impl fmt::Display for PgnGame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let result = self.result();
+        for name in SEVEN_TAG_ROSTER {
+            let value = match (name, self.tag(name)) {
+                ("Result", _) => result,
+                (_, Some(value)) => value,
+                ("Date", None) => "????.??.??",
+                (_, None) => "?",
+            };
+            writeln!(f, "[{} \"{}\"]", name, escape(value))?;
+        }
+
+        let start = self.start_position();
+        let start_fen = start.to_fen();
+        if start_fen != start.variant.start_fen() && self.tag("FEN").is_none() {
+            writeln!(f, "[SetUp \"1\"]")?;
+            writeln!(f, "[FEN \"{}\"]", start_fen)?;
+        }
+        for (name, value) in self.tags.iter().filter(|(name, _)| !SEVEN_TAG_ROSTER.contains(&name.as_str())) {
+            writeln!(f, "[{} \"{}\"]", name, escape(value))?;
+        }
+        writeln!(f)?;
+
+        let mut tokens = Vec::new();
+        if let Some(comment) = &self.comment {
+            tokens.push(format!("{{{}}}", comment.replace('}', ")")));
+        }
+        write_line(&mut tokens, start, &self.moves);
+        tokens.push(result.to_string());
+
+        let mut width = 0;
+        let mut previous = "";
+        for token in &tokens {
+            if width > 0 && width + 1 + token.len() > LINE_WIDTH {
+                writeln!(f)?;
+                width = 0;
+            } else if width > 0 && previous != "(" && token != ")" {
+                write!(f, " ")?;
+                width += 1;
+            }
+            write!(f, "{}", token)?;
+            width += token.len();
+            previous = token;
+        }
+        writeln!(f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    TagOpen,
+    TagClose,
+    Str(String),
+    Symbol(String),
+    Comment(String),
+    Nag(u8),
+    VariationOpen,
+    VariationClose,
+    Result(String),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, PgnErrorKind> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '[' => tokens.push(Token::TagOpen),
+            ']' => tokens.push(Token::TagClose),
+            '(' => tokens.push(Token::VariationOpen),
+            ')' => tokens.push(Token::VariationClose),
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => value.extend(chars.next()),
+                        Some('"') => break,
+                        Some(c) => value.push(c),
+                        None => return Err(PgnErrorKind::UnterminatedString),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            '{' => {
+                let mut comment = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => comment.push(c),
+                        None => return Err(PgnErrorKind::UnterminatedComment),
+                    }
+                }
+                tokens.push(Token::Comment(comment.split_whitespace().collect::<Vec<_>>().join(" ")));
+            }
+            ';' => {
+                let comment: String = chars.by_ref().take_while(|&c| c != '\n').collect();
+                tokens.push(Token::Comment(comment.trim().to_string()));
+            }
+            '$' => {
+                let mut digits = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                    digits.push(c);
+                    chars.next();
+                }
+                let nag = digits.parse().map_err(|_| PgnErrorKind::UnexpectedToken(format!("${}", digits)))?;
+                tokens.push(Token::Nag(nag));
+            }
+            _ => {
+                let mut symbol = String::from(c);
+                while let Some(&c) = chars.peek().filter(|c| !c.is_whitespace() && !"[]{}();$\"".contains(**c)) {
+                    symbol.push(c);
+                    chars.next();
+                }
+                push_symbol(&mut tokens, &symbol);
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+// splits "12.", "12...", "12.e4" into move number and move, annotation glyphs become NAGs
+fn push_symbol(tokens: &mut Vec<Token>, symbol: &str) {
+    if matches!(symbol, "1-0" | "0-1" | "1/2-1/2" | "*") {
+        tokens.push(Token::Result(symbol.to_string()));
+        return;
+    }
+
+    let symbol = match symbol.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) if i > 0 && symbol[i..].starts_with('.') => symbol[i..].trim_start_matches('.'),
+        None => "",
+        _ => symbol,
+    };
+    if symbol.is_empty() {
+        return;
+    }
+
+    let san = symbol.trim_end_matches(['!', '?']);
+    let nag = match &symbol[san.len()..] {
+        "!" => Some(1),
+        "?" => Some(2),
+        "!!" => Some(3),
+        "??" => Some(4),
+        "!?" => Some(5),
+        "?!" => Some(6),
+        _ => None,
+    };
+    tokens.push(Token::Symbol(san.to_string()));
+    tokens.extend(nag.map(Token::Nag));
+}
+
+struct MovetextParser {
+    tokens: Vec<Token>,
+    index:  usize,
+    ply:    usize,
+    result: Option<String>,
+}
+
+impl MovetextParser {
+    // parses one line of play up to its closing parenthesis (or the result / end for the mainline),
+    // returns the moves and any comment that was not followed by a move
+    fn parse_line(&mut self, mut position: ChessPosition, depth: usize) -> Result<(Vec<PgnNode>, Option<String>), PgnErrorKind> {
+        let mut nodes: Vec<PgnNode> = Vec::new();
+        let mut before: Option<ChessPosition> = None;
+        let mut pending_comment: Option<String> = None;
+
+        while let Some(token) = self.tokens.get(self.index).cloned() {
+            self.index += 1;
+            match token {
+                Token::Symbol(san) => {
+                    self.ply += 1;
+                    let mov = ChessMove::from_san(&san, &position).map_err(|error| PgnErrorKind::San { ply: self.ply, error })?;
+                    let mut node = PgnNode::new(mov);
+                    node.starting_comment = pending_comment.take();
+                    nodes.push(node);
+
+                    before = Some(position.clone());
+                    position.make_move(&mov);
+                }
+                Token::Nag(nag) => match nodes.last_mut() {
+                    Some(node) => node.nags.push(nag),
+                    None => return Err(PgnErrorKind::UnexpectedToken(format!("${}", nag))),
+                },
+                Token::Comment(comment) => {
+                    let target = match nodes.last_mut() {
+                        Some(node) => &mut node.comment,
+                        None => &mut pending_comment,
+                    };
+                    match target {
+                        Some(existing) => {
+                            existing.push(' ');
+                            existing.push_str(&comment);
+                        }
+                        None => *target = Some(comment),
+                    }
+                }
+                Token::VariationOpen => {
+                    let start = before.clone().ok_or(PgnErrorKind::UnexpectedToken("(".to_string()))?;
+                    let ply = self.ply;
+                    self.ply -= 1;
+                    let (mut variation, leftover) = self.parse_line(start, depth + 1)?;
+                    self.ply = ply;
+                    if let Some(first) = variation.first_mut() {
+                        first.starting_comment = first.starting_comment.take().or(leftover);
+                    }
+                    if !variation.is_empty() {
+                        nodes.last_mut().unwrap().variations.push(variation);
+                    }
+                }
+                Token::VariationClose if depth > 0 => return Ok((nodes, pending_comment)),
+                Token::VariationClose => return Err(PgnErrorKind::UnbalancedVariation),
+                Token::Result(result) if depth == 0 => {
+                    self.result = Some(result);
+                    break;
+                }
+                Token::Result(_) => return Err(PgnErrorKind::UnbalancedVariation),
+                Token::TagOpen => return Err(PgnErrorKind::UnexpectedToken("[".to_string())),
+                Token::TagClose => return Err(PgnErrorKind::UnexpectedToken("]".to_string())),
+                Token::Str(s) => return Err(PgnErrorKind::UnexpectedToken(format!("\"{}\"", s))),
+            }
+        }
+
+        if depth > 0 {
+            return Err(PgnErrorKind::UnbalancedVariation);
+        }
+        Ok((nodes, pending_comment))
+    }
+}
+
+fn parse_game(text: &str) -> Result<PgnGame, PgnErrorKind> {
+    let tokens = tokenize(text)?;
+    let mut index = 0;
+    let mut tags = Vec::new();
+
+    while tokens.get(index) == Some(&Token::TagOpen) {
+        match (tokens.get(index + 1), tokens.get(index + 2), tokens.get(index + 3)) {
+            (Some(Token::Symbol(name)), Some(Token::Str(value)), Some(Token::TagClose)) => tags.push((name.clone(), value.clone())),
+            (name, value, _) => return Err(PgnErrorKind::InvalidTag(format!("{:?} {:?}", name, value))),
+        }
+        index += 4;
+    }
+
+    // the Variant tag decides how the FEN is read, Lichess calls standard games from a FEN "From Position"
+    let variant = match tags.iter().find(|(name, _)| name == "Variant") {
+        Some((_, name)) if name.eq_ignore_ascii_case("From Position") => Variant::Standard,
+        Some((_, name)) => Variant::from_name(name).ok_or_else(|| PgnErrorKind::UnknownVariant(name.clone()))?,
+        None => Variant::Standard,
+    };
+    let mut game = match tags.iter().find(|(name, _)| name == "FEN") {
+        Some((_, fen)) => ChessGame::from_position(parse_variant_fen(fen, variant).map_err(PgnErrorKind::Fen)?),
+        None => ChessGame::from_variant(variant),
+    };
+
+    let mut parser = MovetextParser { tokens: tokens[index..].to_vec(), index: 0, ply: 0, result: None };
+    let (mut moves, leftover) = parser.parse_line(game.position.clone(), 0)?;
+    let comment = match moves.first_mut() {
+        Some(first) => first.starting_comment.take().or(leftover),
+        None => leftover,
+    };
+
+    for node in &moves {
+        game.make_move(&node.mov);
+    }
+
+    let mut pgn = PgnGame { tags, comment, moves, game };
+    let result = parser.result.unwrap_or_else(|| pgn.result().to_string());
+    pgn.game.outcome = result_outcome(&result, pgn.tag("Termination"), &pgn.game);
+    pgn.set_tag("Result", &result);
+    Ok(pgn)
+}
+
+// a single game from a string
+pub fn parse_pgn(text: &str) -> Result<PgnGame, PgnError> {
+    parse_game(text).map_err(|kind| PgnError { game: 1, line: 1, kind })
+}
+
+// Streams games out of any reader. A game that fails to parse is reported as an error and
+// reading carries on with the next one.
+pub struct PgnReader<R> {
+    reader:  R,
+    line:    usize,
+    games:   usize,
+    pending: Option<(usize, String)>, // first line of the next game, already read
+    done:    bool,
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, line: 0, games: 0, pending: None, done: false }
+    }
+
+    // raw text of the next game and the line it starts on
+    fn next_text(&mut self) -> io::Result<Option<(usize, String)>> {
+        let mut text = String::new();
+        let mut start = 0;
+        let mut in_movetext = false;
+        let mut after_tags = false; // a blank line closed the tag section, a game may have no movetext
+        let mut in_comment = false;
+
+        if let Some((line, pending)) = self.pending.take() {
+            start = line;
+            text.push_str(&pending);
+        }
+
+        loop {
+            let mut buf = String::new();
+            if self.reader.read_line(&mut buf)? == 0 {
+                break;
+            }
+            self.line += 1;
+
+            let trimmed = buf.trim_start();
+            if !in_comment && buf.starts_with('%') {
+                continue;
+            }
+            if !in_comment && trimmed.starts_with('[') && (in_movetext || after_tags) {
+                self.pending = Some((self.line, buf));
+                break;
+            }
+            if trimmed.is_empty() && !text.trim().is_empty() {
+                after_tags = true;
+            }
+            if !in_comment && !trimmed.is_empty() && !trimmed.starts_with('[') {
+                in_movetext = true;
+            }
+
+            for c in buf.chars() {
+                match c {
+                    '{' if !in_comment => in_comment = true,
+                    '}' if in_comment => in_comment = false,
+                    ';' if !in_comment => break,
+                    _ => {}
+                }
+            }
+
+            if text.trim().is_empty() && !trimmed.is_empty() {
+                start = self.line;
+            }
+            text.push_str(&buf);
+        }
+
+        Ok(if text.trim().is_empty() { None } else { Some((start, text)) })
+    }
+}
+
+impl PgnReader<Box<dyn BufRead>> {
+    // plain or zstd compressed, told apart by the zstd frame magic
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let compressed = reader.fill_buf()?.starts_with(&ZSTD_MAGIC);
+        let reader: Box<dyn BufRead> = if compressed {
+            Box::new(BufReader::new(zstd::stream::Decoder::with_buffer(reader)?))
+        } else {
+            Box::new(reader)
+        };
+        Ok(Self::new(reader))
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<PgnGame, PgnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let (line, text) = match self.next_text() {
+            Ok(Some(game)) => game,
+            Ok(None) => return None,
+            Err(e) => {
+                self.done = true;
+                let kind = PgnErrorKind::Io(e.to_string());
+                return Some(Err(PgnError { game: self.games + 1, line: self.line, kind }));
+            }
+        };
+
+        self.games += 1;
+        Some(parse_game(&text).map_err(|kind| PgnError { game: self.games, line, kind }))
+    }
+}
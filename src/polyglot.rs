@@ -0,0 +1,233 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead},
+    path::Path,
+    sync::OnceLock,
+};
+
+use crate::{ChessMove, ChessPosition, ChessSquare, Color, PgnError, PgnGame, PgnReader, PieceType, XorShift64, zobrist::PolyglotKeys};
+
+static BOOK: OnceLock<PolyglotBook> = OnceLock::new();
+
+// One 16 byte record of a .bin book, stored big endian and sorted by key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookEntry {
+    pub key:    u64,
+    pub mov:    u16,
+    pub weight: u16,
+    pub learn:  u32,
+}
+
+impl BookEntry {
+    pub const SIZE: usize = 16;
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        Self {
+            key:    u64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+            mov:    u16::from_be_bytes(bytes[8..10].try_into().unwrap()),
+            weight: u16::from_be_bytes(bytes[10..12].try_into().unwrap()),
+            learn:  u32::from_be_bytes(bytes[12..16].try_into().unwrap()),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..8].copy_from_slice(&self.key.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.mov.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.weight.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.learn.to_be_bytes());
+        bytes
+    }
+}
+
+const PROMOTIONS: [PieceType; 4] = [PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen];
+
+// Moves are packed as to file, to rank, from file, from rank and promotion piece, three bits
+// each from the low end. Castling is written as the king capturing its own rook.
+pub fn encode_move(position: &ChessPosition, mov: &ChessMove) -> u16 {
+    let to = position.chessboard.castling(mov).map_or(mov.to, |castling| castling.rook_from);
+    let promotion = mov.promotion.and_then(|piece_type| PROMOTIONS.iter().position(|&p| p == piece_type)).map_or(0, |i| i as u16 + 1);
+    to.file() as u16 | (to.rank() as u16) << 3 | (mov.from.file() as u16) << 6 | (mov.from.rank() as u16) << 9 | promotion << 12
+}
+
+// the legal move a book move stands for, None when it isn't legal in the position
+pub fn decode_move(position: &ChessPosition, raw: u16) -> Option<ChessMove> {
+    let square = |bits: u16| ChessSquare::from_coords((bits & 7) as u8, (bits >> 3 & 7) as u8).unwrap();
+    let to = square(raw);
+    let from = square(raw >> 6);
+    let promotion = match raw >> 12 & 7 {
+        0 => None,
+        i => Some(*PROMOTIONS.get(i as usize - 1)?),
+    };
+
+    position.generate_legal().into_iter().find(|mov| {
+        let target = position.chessboard.castling(mov).map_or(mov.to, |castling| castling.rook_from);
+        mov.from == from && target == to && mov.promotion == promotion
+    })
+}
+
+pub struct PolyglotBook {
+    entries: Vec<BookEntry>,
+}
+
+impl PolyglotBook {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        if bytes.len() % BookEntry::SIZE != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "book size is not a multiple of 16 bytes"));
+        }
+        Ok(Self::from_bytes(&bytes))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut entries: Vec<BookEntry> = bytes.chunks_exact(BookEntry::SIZE).map(|chunk| BookEntry::from_bytes(chunk.try_into().unwrap())).collect();
+        // books are sorted already, this keeps lookups working on ones that are not
+        entries.sort_by_key(|entry| entry.key);
+        Self { entries }
+    }
+
+    pub fn init_global(path: impl AsRef<Path>) -> io::Result<&'static PolyglotBook> {
+        let book = Self::open(path)?;
+        Ok(BOOK.get_or_init(|| book))
+    }
+
+    pub fn global() -> Option<&'static PolyglotBook> {
+        BOOK.get()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self, position: &ChessPosition) -> &[BookEntry] {
+        let key = PolyglotKeys::get().hash(position);
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        let end = start + self.entries[start..].partition_point(|entry| entry.key == key);
+        &self.entries[start..end]
+    }
+
+    // the legal book moves of a position with their weights
+    pub fn moves(&self, position: &ChessPosition) -> Vec<(ChessMove, u16)> {
+        self.entries(position).iter().filter_map(|entry| Some((decode_move(position, entry.mov)?, entry.weight))).collect()
+    }
+
+    // a book move picked with probability proportional to its weight
+    pub fn weighted_move(&self, position: &ChessPosition, rng: &mut XorShift64) -> Option<ChessMove> {
+        let moves = self.moves(position);
+        let total: u32 = moves.iter().map(|(_, weight)| *weight as u32).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut choice = (rng.next() % total as u64) as u32;
+        for (mov, weight) in moves {
+            if choice < weight as u32 {
+                return Some(mov);
+            }
+            choice -= weight as u32;
+        }
+        None
+    }
+}
+
+// Collects weighted moves and writes them as a book. Weights of a position are scaled down
+// together when the largest no longer fits in 16 bits.
+pub struct BookWriter {
+    max_plies: usize,
+    weights:   HashMap<(u64, u16), u64>,
+}
+
+impl BookWriter {
+    // only the first max_plies moves of a game go into the book
+    pub fn new(max_plies: usize) -> Self {
+        Self { max_plies, weights: HashMap::new() }
+    }
+
+    pub fn add(&mut self, position: &ChessPosition, mov: &ChessMove, weight: u64) {
+        let key = PolyglotKeys::get().hash(position);
+        *self.weights.entry((key, encode_move(position, mov))).or_default() += weight;
+    }
+
+    // a win counts twice as much as a draw for the side that played the move, lost moves are left out
+    pub fn add_game(&mut self, pgn: &PgnGame) {
+        let winner = match pgn.result() {
+            "1-0" => Some(Color::White),
+            "0-1" => Some(Color::Black),
+            "1/2-1/2" => None,
+            _ => return,
+        };
+
+        let mut position = pgn.start_position().clone();
+        for mov in pgn.game.move_list.iter().take(self.max_plies) {
+            let score = match winner {
+                Some(color) if color == position.side_to_move => 2,
+                Some(_) => 0,
+                None => 1,
+            };
+            if score > 0 {
+                self.add(&position, mov, score);
+            }
+            position.make_move(mov);
+        }
+    }
+
+    // every game of a PGN collection, returns how many were read
+    pub fn add_pgn<R: BufRead>(&mut self, reader: PgnReader<R>) -> Result<usize, PgnError> {
+        let mut games = 0;
+        for pgn in reader {
+            self.add_game(&pgn?);
+            games += 1;
+        }
+        Ok(games)
+    }
+
+    // the visit counts of the moves searched from a self-play root
+    pub fn add_visits(&mut self, position: &ChessPosition, visits: &[(ChessMove, u32)]) {
+        for (mov, count) in visits {
+            if *count > 0 {
+                self.add(position, mov, *count as u64);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut max_weight: HashMap<u64, u64> = HashMap::new();
+        for (&(key, _), &weight) in &self.weights {
+            let max = max_weight.entry(key).or_default();
+            *max = (*max).max(weight);
+        }
+
+        let mut entries: Vec<BookEntry> = self
+            .weights
+            .iter()
+            .map(|(&(key, mov), &weight)| {
+                let max = max_weight[&key];
+                let weight = if max > u16::MAX as u64 {
+                    (weight * u16::MAX as u64 / max).max(1)
+                } else {
+                    weight
+                };
+                BookEntry { key, mov, weight: weight as u16, learn: 0 }
+            })
+            .collect();
+        // best moves first within a position, like the books other tools write
+        entries.sort_by(|a, b| a.key.cmp(&b.key).then(b.weight.cmp(&a.weight)).then(a.mov.cmp(&b.mov)));
+        entries.iter().flat_map(|entry| entry.to_bytes()).collect()
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+}
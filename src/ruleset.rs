@@ -0,0 +1,145 @@
+use arrayvec::ArrayVec;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Bitboard, ChessMove, ChessPosition, Color, MAX_MOVES, POLICY_SIZE, PackedMove, PieceType, Selection,
+    chess_game::{Outcome, Termination},
+};
+
+// The rules a game is played under. Everything that differs between them, the move set, the
+// masks fed to the network and when a game ends, goes through here so MCTS and the training
+// loop never have to ask which one is in use.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum)]
+pub enum Ruleset {
+    // pseudolegal moves, a game ends when a king is captured
+    #[default]
+    KingCapture,
+    // legal moves only, a game ends in checkmate or stalemate
+    Standard,
+}
+
+impl Ruleset {
+    pub fn moves(&self, position: &ChessPosition) -> ArrayVec<ChessMove, MAX_MOVES> {
+        self.packed_moves(position).into_iter().map(ChessMove::from).collect()
+    }
+
+    pub fn packed_moves(&self, position: &ChessPosition) -> ArrayVec<PackedMove, MAX_MOVES> {
+        match self {
+            Ruleset::KingCapture => position.pseudolegal_moves.iter().copied().collect(),
+            Ruleset::Standard => position.generate_legal_packed(),
+        }
+    }
+
+    // Syzygy tables assume legal chess, they say nothing about games decided by king capture
+    pub fn uses_tablebases(&self) -> bool {
+        matches!(self, Ruleset::Standard)
+    }
+
+    // the from squares and pocket pieces of all moves, or the to squares of the moves of the selection
+    pub fn mask(&self, position: &ChessPosition, selected: Option<Selection>) -> [bool; POLICY_SIZE] {
+        let mut mask = [false; POLICY_SIZE];
+        let moves = self.packed_moves(position);
+        assert!(!moves.is_empty(), "no moves to mask in a finished position");
+        for &mov in &moves {
+            let selection = Selection::of(mov);
+            match selected {
+                Some(selected) if selected == selection => mask[mov.to().0 as usize] = true,
+                Some(_) => {}
+                None => mask[selection.index()] = true,
+            }
+        }
+        mask
+    }
+
+    // repetitions counts the current position, so a position seen for the first time has one
+    pub fn outcome(&self, position: &ChessPosition, repetitions: usize, draw_rules: &DrawRules) -> Outcome {
+        if let Some(outcome) = position.variant.outcome(position) {
+            return outcome;
+        }
+
+        let board = &position.chessboard;
+        for color in [Color::White, Color::Black] {
+            if position.variant.has_royal_king(color) && board.get_piece_bitboard(color, PieceType::King).is_empty() {
+                return Outcome::Finished(Some(color.opposite()), Termination::KingCaptured);
+            }
+        }
+
+        if self.moves(position).is_empty() {
+            let king_sq = board.get_piece_bitboard(position.side_to_move, PieceType::King).lsb_square();
+            let in_check = king_sq.is_some_and(|king_sq| board.is_square_attacked(king_sq, position.side_to_move.opposite()));
+            // a side with no pseudolegal moves at all can't be mated by capture, call it a draw
+            return match self {
+                Ruleset::Standard if in_check => Outcome::Finished(Some(position.side_to_move.opposite()), Termination::Checkmate),
+                _ => Outcome::Finished(None, Termination::Stalemate),
+            };
+        }
+
+        match draw_rules.draw(position, repetitions) {
+            Some(termination) => Outcome::Finished(None, termination),
+            None => Outcome::Unfinished,
+        }
+    }
+}
+
+// Which draw rules end a game. Threefold repetition and the 50-move rule are only draws once a
+// player claims them, when enabled they are claimed the moment they apply. Fivefold repetition,
+// the 75-move rule and insufficient material are automatic under FIDE rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DrawRules {
+    pub claim_threefold: bool,
+    pub claim_fifty_moves: bool,
+    pub fivefold: bool,
+    pub seventy_five_moves: bool,
+    pub insufficient_material: bool,
+}
+
+impl Default for DrawRules {
+    fn default() -> Self {
+        Self::FIDE
+    }
+}
+
+impl DrawRules {
+    // automatic draws and both claims
+    pub const FIDE: DrawRules =
+        DrawRules { claim_threefold: true, claim_fifty_moves: true, fivefold: true, seventy_five_moves: true, insufficient_material: true };
+    // only the draws FIDE applies without a claim
+    pub const AUTOMATIC: DrawRules =
+        DrawRules { claim_threefold: false, claim_fifty_moves: false, fivefold: true, seventy_five_moves: true, insufficient_material: true };
+
+    pub fn draw(&self, position: &ChessPosition, repetitions: usize) -> Option<Termination> {
+        if self.insufficient_material && position.variant.has_insufficient_material_draws() && insufficient_material(position) {
+            Some(Termination::InsufficientMaterial)
+        } else if self.fivefold && repetitions >= 5 {
+            Some(Termination::FivefoldRepetition)
+        } else if self.seventy_five_moves && position.halfmove_clock >= 150 {
+            Some(Termination::SeventyFiveMoves)
+        } else if self.claim_threefold && repetitions >= 3 {
+            Some(Termination::ThreefoldRepetition)
+        } else if self.claim_fifty_moves && position.halfmove_clock >= 100 {
+            Some(Termination::FiftyMoves)
+        } else {
+            None
+        }
+    }
+}
+
+// No sequence of moves can mate: only kings and minor pieces are left, and either a single
+// minor piece or bishops that all stand on squares of the same colour.
+fn insufficient_material(position: &ChessPosition) -> bool {
+    let board = &position.chessboard;
+    for color in [Color::White, Color::Black] {
+        for piece_type in [PieceType::Pawn, PieceType::Rook, PieceType::Queen] {
+            if !board.get_piece_bitboard(color, piece_type).is_empty() {
+                return false;
+            }
+        }
+    }
+
+    let knights = board.get_piece_bitboard(Color::White, PieceType::Knight) | board.get_piece_bitboard(Color::Black, PieceType::Knight);
+    let bishops = board.get_piece_bitboard(Color::White, PieceType::Bishop) | board.get_piece_bitboard(Color::Black, PieceType::Bishop);
+    if (knights | bishops).count() <= 1 {
+        return true;
+    }
+    knights.is_empty() && ((bishops & Bitboard::LIGHT_SQUARES).is_empty() || (bishops & Bitboard::DARK_SQUARES).is_empty())
+}
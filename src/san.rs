@@ -0,0 +1,213 @@
+use core::fmt;
+
+use crate::{ChessMove, ChessPosition, ChessSquare, PieceType};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SanError {
+    Empty,
+    InvalidSyntax(String),
+    IllegalMove(String),
+    MissingPromotion(String),
+    AmbiguousMove { san: String, candidates: Vec<ChessMove> },
+}
+
+impl fmt::Display for SanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SanError::Empty => write!(f, "empty SAN move"),
+            SanError::InvalidSyntax(san) => write!(f, "invalid SAN syntax '{}'", san),
+            SanError::IllegalMove(san) => write!(f, "'{}' is not a legal move in this position", san),
+            SanError::MissingPromotion(san) => write!(f, "'{}' reaches the last rank without a promotion piece", san),
+            SanError::AmbiguousMove { san, candidates } => {
+                let candidates: Vec<String> = candidates.iter().map(|mov| mov.to_uci()).collect();
+                write!(f, "'{}' is ambiguous, could be any of {}", san, candidates.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for SanError {}
+
+fn piece_letter(piece_type: PieceType) -> Option<char> {
+    match piece_type {
+        PieceType::Pawn => None,
+        piece_type => Some(piece_type.to_char(crate::Color::White)),
+    }
+}
+
+fn is_castling(position: &ChessPosition, mov: &ChessMove) -> bool {
+    position.chessboard.castling(mov).is_some()
+}
+
+impl ChessMove {
+    // expects a legal move for the position
+    pub fn to_san(&self, position: &ChessPosition) -> String {
+        let mut san = String::new();
+
+        if let Some(piece_type) = self.dropped() {
+            san.push(piece_type.to_char(crate::Color::White));
+            san.push('@');
+            san.push_str(&self.to.name());
+        } else if is_castling(position, self) {
+            san.push_str(if self.to.file() > self.from.file() { "O-O" } else { "O-O-O" });
+        } else {
+            let piece = position.chessboard.get_piece_at(self.from).expect("no piece on the from square");
+            let is_capture =
+                position.chessboard.all_pieces.is_set(self.to) || (piece.piece_type == PieceType::Pawn && position.en_passant == Some(self.to));
+
+            match piece_letter(piece.piece_type) {
+                None => {
+                    if is_capture {
+                        san.push((b'a' + self.from.file()) as char);
+                    }
+                }
+                Some(letter) => {
+                    san.push(letter);
+
+                    // other pieces of the same kind that can reach the same square
+                    let rivals: Vec<ChessSquare> = position
+                        .generate_legal()
+                        .iter()
+                        .filter(|mov| mov.to == self.to && mov.from != self.from)
+                        .filter(|mov| position.chessboard.get_piece_at(mov.from) == Some(piece))
+                        .map(|mov| mov.from)
+                        .collect();
+
+                    if !rivals.is_empty() {
+                        let file_unique = rivals.iter().all(|sq| sq.file() != self.from.file());
+                        let rank_unique = rivals.iter().all(|sq| sq.rank() != self.from.rank());
+                        if file_unique || !rank_unique {
+                            san.push((b'a' + self.from.file()) as char);
+                        }
+                        if !file_unique {
+                            san.push((b'1' + self.from.rank()) as char);
+                        }
+                    }
+                }
+            }
+
+            if is_capture {
+                san.push('x');
+            }
+            san.push_str(&self.to.name());
+
+            if let Some(promotion) = self.promotion {
+                san.push('=');
+                san.push(promotion.to_char(crate::Color::White));
+            }
+        }
+
+        let mut after = position.clone();
+        after.make_move(self);
+        let king = after.chessboard.get_piece_bitboard(after.side_to_move, PieceType::King).lsb_square();
+        if king.is_some_and(|sq| after.chessboard.is_square_attacked(sq, position.side_to_move)) {
+            san.push(if after.generate_legal().is_empty() { '#' } else { '+' });
+        }
+
+        san
+    }
+
+    // Tolerates 0-0 castling, missing or superfluous capture marks, long algebraic (Ng1-f3),
+    // promotions without '=', pawn drops without the P, and trailing check marks or annotations.
+    pub fn from_san(san: &str, position: &ChessPosition) -> Result<Self, SanError> {
+        let trimmed = san.trim().trim_end_matches(['+', '#', '!', '?']);
+        let trimmed = trimmed.strip_suffix("e.p.").unwrap_or(trimmed).trim_end();
+        if trimmed.is_empty() {
+            return Err(SanError::Empty);
+        }
+
+        let legal = position.generate_legal();
+        let invalid = || SanError::InvalidSyntax(san.to_string());
+
+        let castle = match trimmed {
+            "O-O" | "0-0" => Some(true),
+            "O-O-O" | "0-0-0" => Some(false),
+            _ => None,
+        };
+        if let Some(kingside) = castle {
+            return legal
+                .iter()
+                .find(|mov| is_castling(position, mov) && (mov.to.file() > mov.from.file()) == kingside)
+                .copied()
+                .ok_or_else(|| SanError::IllegalMove(san.to_string()));
+        }
+
+        if let Some((piece, square)) = trimmed.split_once('@') {
+            let piece_type = match piece {
+                "" | "P" => PieceType::Pawn,
+                "N" | "B" | "R" | "Q" => PieceType::from_char(piece.chars().next().unwrap()).unwrap(),
+                _ => return Err(invalid()),
+            };
+            let to = ChessSquare::from_name(square).ok_or_else(invalid)?;
+            return legal
+                .iter()
+                .find(|mov| mov.dropped() == Some(piece_type) && mov.to == to)
+                .copied()
+                .ok_or_else(|| SanError::IllegalMove(san.to_string()));
+        }
+
+        let mut chars: Vec<char> = trimmed.chars().filter(|c| !matches!(c, 'x' | ':' | '-')).collect();
+
+        let piece_type = match chars.first() {
+            Some(&c) if "NBRQK".contains(c) => {
+                chars.remove(0);
+                PieceType::from_char(c).unwrap()
+            }
+            Some('P') => {
+                chars.remove(0);
+                PieceType::Pawn
+            }
+            _ => PieceType::Pawn,
+        };
+
+        let promotion = match chars.last() {
+            Some(&c) if "NBRQKnbrqk".contains(c) => {
+                chars.pop();
+                if chars.last() == Some(&'=') {
+                    chars.pop();
+                }
+                Some(PieceType::from_char(c).unwrap())
+            }
+            _ => None,
+        };
+
+        if chars.len() < 2 || chars.len() > 4 {
+            return Err(invalid());
+        }
+        let square: String = chars[chars.len() - 2..].iter().collect();
+        let to = ChessSquare::from_name(&square).ok_or_else(invalid)?;
+
+        let mut from_file = None;
+        let mut from_rank = None;
+        for &c in &chars[..chars.len() - 2] {
+            match c {
+                'a'..='h' if from_file.is_none() => from_file = Some(c as u8 - b'a'),
+                '1'..='8' if from_rank.is_none() => from_rank = Some(c as u8 - b'1'),
+                _ => return Err(invalid()),
+            }
+        }
+
+        let candidates: Vec<ChessMove> = legal
+            .iter()
+            .filter(|mov| mov.to == to && !is_castling(position, mov))
+            .filter(|mov| position.chessboard.get_piece_at(mov.from).is_some_and(|piece| piece.piece_type == piece_type))
+            .filter(|mov| from_file.is_none_or(|file| mov.from.file() == file))
+            .filter(|mov| from_rank.is_none_or(|rank| mov.from.rank() == rank))
+            .copied()
+            .collect();
+
+        if candidates.is_empty() {
+            return Err(SanError::IllegalMove(san.to_string()));
+        }
+        if promotion.is_none() && candidates.iter().any(|mov| mov.promotion.is_some()) {
+            return Err(SanError::MissingPromotion(san.to_string()));
+        }
+
+        let mut candidates: Vec<ChessMove> = candidates.into_iter().filter(|mov| mov.promotion == promotion).collect();
+        match candidates.len() {
+            0 => Err(SanError::IllegalMove(san.to_string())),
+            1 => Ok(candidates.remove(0)),
+            _ => Err(SanError::AmbiguousMove { san: san.to_string(), candidates }),
+        }
+    }
+}
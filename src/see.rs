@@ -0,0 +1,84 @@
+use crate::{Bitboard, ChessMove, ChessPosition, ChessSquare, Color, PieceType};
+
+const PIECES_BY_VALUE: [PieceType; 6] = [PieceType::Pawn, PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen, PieceType::King];
+
+impl ChessPosition {
+    // Static exchange evaluation: the material the side to move wins, in centipawns, if both sides
+    // keep recapturing on the target square with their least valuable piece and stop as soon as
+    // that stops paying. Sliders lined up behind a capturer join in once it has moved off the ray.
+    pub fn see(&self, mov: &ChessMove) -> i32 {
+        let board = &self.chessboard;
+        let Some(mover) = board.get_piece_at(mov.from) else {
+            return 0;
+        };
+        if board.castling(mov).is_some() {
+            return 0;
+        }
+
+        let to = mov.to;
+        let last_rank = |color: Color| if color == Color::White { 7 } else { 0 };
+        let mut occupancy = board.all_pieces & !mov.from.bitboard();
+
+        let mut gain = [0i32; 32];
+        gain[0] = match board.get_piece_at(to) {
+            Some(captured) => captured.piece_type.value(),
+            None if mover.piece_type == PieceType::Pawn && self.en_passant == Some(to) => {
+                let cap_sq = ChessSquare::from_coords(to.file(), mov.from.rank()).unwrap();
+                occupancy &= !cap_sq.bitboard();
+                PieceType::Pawn.value()
+            }
+            None => 0,
+        };
+        let mut on_square = mover.piece_type;
+        if let Some(promotion) = mov.promotion {
+            gain[0] += promotion.value() - PieceType::Pawn.value();
+            on_square = promotion;
+        }
+
+        let mut side = mover.color.opposite();
+        let mut depth = 0;
+        loop {
+            let attackers = (board.attackers_to(to, Color::White, occupancy) | board.attackers_to(to, Color::Black, occupancy)) & occupancy;
+            let ours = attackers & occupancy_of(self, side);
+            let Some((piece_type, from)) = PIECES_BY_VALUE
+                .iter()
+                .find_map(|&piece_type| (ours & board.get_piece_bitboard(side, piece_type)).lsb_square().map(|sq| (piece_type, sq)))
+            else {
+                break;
+            };
+            // the king can only take last
+            if piece_type == PieceType::King && !(attackers & occupancy_of(self, side.opposite())).is_empty() {
+                break;
+            }
+
+            depth += 1;
+            gain[depth] = on_square.value() - gain[depth - 1];
+            on_square = piece_type;
+            if piece_type == PieceType::Pawn && to.rank() == last_rank(side) {
+                gain[depth] += PieceType::Queen.value() - PieceType::Pawn.value();
+                on_square = PieceType::Queen;
+            }
+
+            occupancy &= !from.bitboard();
+            side = side.opposite();
+        }
+
+        // either side can stand pat instead of recapturing
+        while depth > 0 {
+            gain[depth - 1] = -(-gain[depth - 1]).max(gain[depth]);
+            depth -= 1;
+        }
+        gain[0]
+    }
+
+    pub fn see_ge(&self, mov: &ChessMove, threshold: i32) -> bool {
+        self.see(mov) >= threshold
+    }
+}
+
+fn occupancy_of(position: &ChessPosition, color: Color) -> Bitboard {
+    match color {
+        Color::White => position.chessboard.white_occupancy,
+        Color::Black => position.chessboard.black_occupancy,
+    }
+}
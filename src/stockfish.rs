@@ -1,4 +1,4 @@
-use crate::ChessPosition;
+use crate::{ChessPosition, Divide};
 use std::sync::{Mutex, OnceLock};
 use std::{
     io::{BufRead, BufReader, Write},
@@ -76,6 +76,7 @@ impl Stockfish {
     pub fn get_eval(&mut self, position: &ChessPosition) -> f32 {
         let stdin = self.process.stdin.as_mut().unwrap();
 
+        writeln!(stdin, "setoption name UCI_Chess960 value {}", position.chess960).unwrap();
         writeln!(stdin, "position fen {}", position.to_fen()).unwrap();
         writeln!(stdin, "eval").unwrap();
 
@@ -97,6 +98,17 @@ impl Stockfish {
         }
         evaluation * 100.0
     }
+
+    // `go perft` splits its count by root move the same way divide does
+    pub fn perft(&mut self, position: &ChessPosition, depth: u32) -> Divide {
+        let stdin = self.process.stdin.as_mut().unwrap();
+
+        writeln!(stdin, "setoption name UCI_Chess960 value {}", position.chess960).unwrap();
+        writeln!(stdin, "position fen {}", position.to_fen()).unwrap();
+        writeln!(stdin, "go perft {}", depth).unwrap();
+
+        Divide::read(&mut self.reader).unwrap()
+    }
 }
 
 pub static STOCKFISH: OnceLock<Mutex<Stockfish>> = OnceLock::new();
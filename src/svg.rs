@@ -0,0 +1,291 @@
+use std::{fmt::Write, fs, io, path::Path};
+
+use crate::{ChessMove, ChessPosition, ChessSquare, Color, Mcts, MctsNode, NetworkLabels, PieceType};
+
+const SQUARE: f32 = 45.0;
+const MARGIN: f32 = 16.0;
+const SIZE: f32 = 8.0 * SQUARE + 2.0 * MARGIN;
+
+const LIGHT: &str = "#f0d9b5";
+const DARK: &str = "#b58863";
+const LAST_MOVE: &str = "#cdd26a";
+const HEAT: &str = "#d62728";
+const SELECTED: &str = "#1f77b4";
+const ARROW: &str = "#15781b";
+
+// An arrow between two squares, weight in 0..=1 sets how thick and opaque it is drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arrow {
+    pub from:   ChessSquare,
+    pub to:     ChessSquare,
+    pub weight: f32,
+}
+
+// Renders a position as a standalone SVG with optional overlays: a heatmap of square values,
+// arrows, the last move and the king in check. Heatmaps and arrows use board squares, the
+// policy helpers undo the side-to-move flip the network sees.
+#[derive(Debug, Clone)]
+pub struct BoardSvg {
+    position:  ChessPosition,
+    flipped:   bool,
+    heatmap:   Option<[f32; 64]>,
+    selected:  Option<ChessSquare>,
+    arrows:    Vec<Arrow>,
+    last_move: Option<ChessMove>,
+}
+
+impl BoardSvg {
+    pub fn new(position: &ChessPosition) -> Self {
+        Self { position: position.clone(), flipped: false, heatmap: None, selected: None, arrows: Vec::new(), last_move: None }
+    }
+
+    // the root position of a search, with arrows for its most visited moves
+    pub fn from_mcts(mcts: &Mcts, top: usize) -> Self {
+        Self::new(&mcts.get_position(mcts.root)).mcts(mcts, top)
+    }
+
+    // draw the board from black's side
+    pub fn flipped(mut self, flipped: bool) -> Self {
+        self.flipped = flipped;
+        self
+    }
+
+    pub fn heatmap(mut self, values: [f32; 64]) -> Self {
+        self.heatmap = Some(values);
+        self
+    }
+
+    // a from-square policy of the position
+    pub fn policy(self, labels: &NetworkLabels) -> Self {
+        let values = board_policy(labels, self.position.side_to_move);
+        self.heatmap(values)
+    }
+
+    // a to-square policy for the piece on from_sq, which is outlined
+    pub fn to_policy(mut self, from_sq: ChessSquare, labels: &NetworkLabels) -> Self {
+        self.selected = Some(from_sq);
+        self.policy(labels)
+    }
+
+    pub fn arrow(mut self, from: ChessSquare, to: ChessSquare, weight: f32) -> Self {
+        self.arrows.push(Arrow { from, to, weight: weight.clamp(0.0, 1.0) });
+        self
+    }
+
+    // Arrows for the top moves below the search root weighted by visits relative to the best.
+    // When the root already has its piece picked that square is outlined and only its moves shown.
+    pub fn mcts(mut self, mcts: &Mcts, top: usize) -> Self {
+        let root = &mcts.node_arena.buffer[mcts.root];
+        let visits = match root {
+            MctsNode::PieceSelect { .. } => mcts.root_move_visits().unwrap_or_default(),
+            MctsNode::PieceMove { data, .. } | MctsNode::PieceDrop { data, .. } => {
+                if let MctsNode::PieceMove { from_sq, .. } = root {
+                    self.selected = Some(*from_sq);
+                }
+                let (start, end) = data.child_edge_range.unwrap_or((0, 0));
+                mcts.edge_arena.buffer[start..end].iter().filter_map(|edge| Some((ChessMove::from(edge.mov?), edge.visits))).collect()
+            }
+        };
+
+        // promotions to different pieces share an arrow, drops have none
+        let mut totals: Vec<(ChessSquare, ChessSquare, u32)> = Vec::new();
+        for (mov, count) in visits.into_iter().filter(|(mov, _)| mov.dropped().is_none()) {
+            match totals.iter_mut().find(|(from, to, _)| *from == mov.from && *to == mov.to) {
+                Some(total) => total.2 += count,
+                None => totals.push((mov.from, mov.to, count)),
+            }
+        }
+        totals.retain(|total| total.2 > 0);
+        totals.sort_by_key(|total| std::cmp::Reverse(total.2));
+
+        let best = totals.first().map_or(1, |total| total.2) as f32;
+        for (from, to, count) in totals.into_iter().take(top) {
+            self = self.arrow(from, to, count as f32 / best);
+        }
+        self
+    }
+
+    pub fn last_move(mut self, mov: &ChessMove) -> Self {
+        self.last_move = Some(*mov);
+        self
+    }
+
+    pub fn render(&self) -> String {
+        let mut svg = String::new();
+        writeln!(svg, r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {SIZE} {SIZE}" width="{SIZE}" height="{SIZE}">"##).unwrap();
+        svg.push_str(concat!(
+            r##"<defs><radialGradient id="check"><stop offset="0%" stop-color="#ff0000"/>"##,
+            r##"<stop offset="50%" stop-color="#e70000"/><stop offset="100%" stop-color="#9e0000" stop-opacity="0"/></radialGradient></defs>"##,
+            "\n"
+        ));
+        writeln!(svg, r##"<rect width="{SIZE}" height="{SIZE}" fill="#312e2b"/>"##).unwrap();
+
+        for idx in 0..64 {
+            let sq = ChessSquare::new(idx).unwrap();
+            let fill = if (sq.file() + sq.rank()).is_multiple_of(2) { DARK } else { LIGHT };
+            self.square_rect(&mut svg, sq, fill, 1.0);
+        }
+
+        if let Some(mov) = self.last_move {
+            self.square_rect(&mut svg, mov.from, LAST_MOVE, 0.6);
+            self.square_rect(&mut svg, mov.to, LAST_MOVE, 0.6);
+        }
+
+        if !self.position.checkers().is_empty()
+            && let Some(king_sq) = self.position.chessboard.get_piece_bitboard(self.position.side_to_move, PieceType::King).lsb_square()
+        {
+            let (x, y) = self.corner(king_sq);
+            writeln!(svg, r##"<rect x="{x}" y="{y}" width="{SQUARE}" height="{SQUARE}" fill="url(#check)"/>"##).unwrap();
+        }
+
+        if let Some(values) = &self.heatmap {
+            let max = values.iter().copied().fold(0.0, f32::max);
+            for (idx, &value) in values.iter().enumerate() {
+                if max <= 0.0 || value <= 0.0 {
+                    continue;
+                }
+                let sq = ChessSquare::new(idx as u8).unwrap();
+                self.square_rect(&mut svg, sq, HEAT, 0.7 * value / max);
+                if value >= 0.005 {
+                    let (x, y) = self.corner(sq);
+                    writeln!(
+                        svg,
+                        r##"<text x="{}" y="{}" font-family="sans-serif" font-size="9" text-anchor="end" fill="#000">{:.0}%</text>"##,
+                        x + SQUARE - 2.0,
+                        y + SQUARE - 2.0,
+                        value * 100.0
+                    )
+                    .unwrap();
+                }
+            }
+        }
+
+        if let Some(sq) = self.selected {
+            let (x, y) = self.corner(sq);
+            writeln!(
+                svg,
+                r##"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="{SELECTED}" stroke-width="3"/>"##,
+                x + 1.5,
+                y + 1.5,
+                SQUARE - 3.0,
+                SQUARE - 3.0
+            )
+            .unwrap();
+        }
+
+        self.coordinates(&mut svg);
+
+        for idx in 0..64 {
+            let sq = ChessSquare::new(idx).unwrap();
+            if let Some(piece) = self.position.chessboard.get_piece_at(sq) {
+                let (x, y) = self.center(sq);
+                let (fill, stroke) = if piece.color == Color::White { ("#fff", "#000") } else { ("#000", "#000") };
+                writeln!(
+                    svg,
+                    r##"<text x="{x}" y="{y}" font-size="38" text-anchor="middle" dominant-baseline="central" fill="{fill}" stroke="{stroke}" stroke-width="1">{}</text>"##,
+                    glyph(piece.piece_type)
+                )
+                .unwrap();
+            }
+        }
+
+        for arrow in &self.arrows {
+            self.draw_arrow(&mut svg, arrow);
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.render())
+    }
+
+    // top left corner of a square in SVG coordinates
+    fn corner(&self, sq: ChessSquare) -> (f32, f32) {
+        let (col, row) = if self.flipped {
+            (7 - sq.file(), sq.rank())
+        } else {
+            (sq.file(), 7 - sq.rank())
+        };
+        (MARGIN + col as f32 * SQUARE, MARGIN + row as f32 * SQUARE)
+    }
+
+    fn center(&self, sq: ChessSquare) -> (f32, f32) {
+        let (x, y) = self.corner(sq);
+        (x + SQUARE / 2.0, y + SQUARE / 2.0)
+    }
+
+    fn square_rect(&self, svg: &mut String, sq: ChessSquare, fill: &str, opacity: f32) {
+        let (x, y) = self.corner(sq);
+        writeln!(svg, r##"<rect x="{x}" y="{y}" width="{SQUARE}" height="{SQUARE}" fill="{fill}" fill-opacity="{opacity:.3}"/>"##).unwrap();
+    }
+
+    fn coordinates(&self, svg: &mut String) {
+        for i in 0..8u8 {
+            let file = if self.flipped { 7 - i } else { i };
+            let rank = if self.flipped { i } else { 7 - i };
+            let along = MARGIN + (i as f32 + 0.5) * SQUARE;
+            for y in [MARGIN / 2.0, SIZE - MARGIN / 2.0] {
+                writeln!(svg, r##"<text x="{along}" y="{y}" {}>{}</text>"##, COORDINATE_STYLE, (b'a' + file) as char).unwrap();
+            }
+            for x in [MARGIN / 2.0, SIZE - MARGIN / 2.0] {
+                writeln!(svg, r##"<text x="{x}" y="{along}" {}>{}</text>"##, COORDINATE_STYLE, rank + 1).unwrap();
+            }
+        }
+    }
+
+    // a line from center to center ending in a triangle that stops short of the target center
+    fn draw_arrow(&self, svg: &mut String, arrow: &Arrow) {
+        let (x1, y1) = self.center(arrow.from);
+        let (x2, y2) = self.center(arrow.to);
+        let (dx, dy) = (x2 - x1, y2 - y1);
+        let length = (dx * dx + dy * dy).sqrt();
+        if length == 0.0 {
+            return;
+        }
+        let (ux, uy) = (dx / length, dy / length);
+
+        let width = 3.0 + 7.0 * arrow.weight;
+        let head = 2.5 * width;
+        let opacity = 0.35 + 0.5 * arrow.weight;
+        let (tip_x, tip_y) = (x2 - ux * SQUARE * 0.2, y2 - uy * SQUARE * 0.2);
+        let (base_x, base_y) = (tip_x - ux * head, tip_y - uy * head);
+        let (px, py) = (-uy * head * 0.6, ux * head * 0.6);
+
+        writeln!(
+            svg,
+            r##"<g fill="{ARROW}" stroke="{ARROW}" opacity="{opacity:.3}"><line x1="{x1}" y1="{y1}" x2="{base_x:.1}" y2="{base_y:.1}" stroke-width="{width:.1}"/><polygon points="{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}" stroke="none"/></g>"##,
+            tip_x,
+            tip_y,
+            base_x + px,
+            base_y + py,
+            base_x - px,
+            base_y - py
+        )
+        .unwrap();
+    }
+}
+
+const COORDINATE_STYLE: &str = r##"font-family="sans-serif" font-size="11" text-anchor="middle" dominant-baseline="central" fill="#ccc""##;
+
+// the filled glyphs for both colours, white ones get a light fill and a dark outline
+fn glyph(piece_type: PieceType) -> char {
+    match piece_type {
+        PieceType::King => '\u{265A}',
+        PieceType::Queen => '\u{265B}',
+        PieceType::Rook => '\u{265C}',
+        PieceType::Bishop => '\u{265D}',
+        PieceType::Knight => '\u{265E}',
+        PieceType::Pawn => '\u{265F}',
+    }
+}
+
+// policies are indexed from the side to move's view, black's with the ranks mirrored
+fn board_policy(labels: &NetworkLabels, side_to_move: Color) -> [f32; 64] {
+    std::array::from_fn(|idx| {
+        let sq = ChessSquare::new(idx as u8).unwrap();
+        let sq = if side_to_move == Color::Black { sq.square_opposite() } else { sq };
+        labels.policy[sq.0 as usize]
+    })
+}
@@ -0,0 +1,1040 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use log::warn;
+
+use crate::{
+    ChessBoard, ChessMove, ChessPiece, ChessPosition, Color, PieceType, Variant,
+    chess_game::{Outcome, Termination},
+};
+
+// Probing of Syzygy WDL (.rtbw) and DTZ (.rtbz) endgame tables. The decoder follows the layout
+// written by the generator at https://github.com/syzygy1/tb, index by index the same way the
+// Stockfish prober reads it. Tables are read into memory the first time a position needs them.
+
+const TB_PIECES: usize = 7;
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+// per-table flags stored in the first byte of every PairsData
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+// the header byte of a table file
+const HEADER_SPLIT: u8 = 1;
+const HEADER_HAS_PAWNS: u8 = 2;
+
+static TABLEBASE: OnceLock<Tablebase> = OnceLock::new();
+static INDICES: OnceLock<Indices> = OnceLock::new();
+
+// Win/draw/loss from the side to move. Cursed wins and blessed losses are wins and losses that
+// the 50-move rule turns into draws.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    fn from_score(score: i32) -> Self {
+        match score {
+            ..=-2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            2.. => Wdl::Win,
+        }
+    }
+
+    // the result of the game once the 50-move rule is applied
+    pub fn outcome(&self, side_to_move: Color) -> Outcome {
+        match self {
+            Wdl::Win => Outcome::Finished(Some(side_to_move), Termination::Adjudication),
+            Wdl::Loss => Outcome::Finished(Some(side_to_move.opposite()), Termination::Adjudication),
+            _ => Outcome::Finished(None, Termination::Adjudication),
+        }
+    }
+
+    // win/draw/loss probabilities from the side to move, as used for node values and targets
+    pub fn value(&self) -> [f32; 3] {
+        match self {
+            Wdl::Win => [1.0, 0.0, 0.0],
+            Wdl::Loss => [0.0, 0.0, 1.0],
+            _ => [0.0, 1.0, 0.0],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TableKind {
+    Wdl,
+    Dtz,
+}
+
+impl TableKind {
+    fn extension(&self) -> &'static str {
+        match self {
+            TableKind::Wdl => "rtbw",
+            TableKind::Dtz => "rtbz",
+        }
+    }
+
+    fn magic(&self) -> [u8; 4] {
+        match self {
+            TableKind::Wdl => WDL_MAGIC,
+            TableKind::Dtz => DTZ_MAGIC,
+        }
+    }
+
+    // a WDL table holds both sides to move, a DTZ table only one
+    fn sides(&self) -> usize {
+        match self {
+            TableKind::Wdl => 2,
+            TableKind::Dtz => 1,
+        }
+    }
+}
+
+enum Probe {
+    Value(i32),
+    // the DTZ table only stores the other side to move
+    ChangeStm,
+}
+
+// What the name of a table tells about its material, e.g. KRPvKR.
+#[derive(Debug, Clone)]
+struct Material {
+    name: String,
+    white: String,
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    symmetric: bool,
+    // the side with the leading pawns first
+    pawn_count: [usize; 2],
+}
+
+impl Material {
+    fn from_name(name: &str) -> Option<Self> {
+        let (white, black) = name.split_once('v')?;
+        let valid = |side: &str| side.starts_with('K') && side.len() < TB_PIECES && side.chars().all(|c| "KQRBNP".contains(c));
+        if !valid(white) || !valid(black) || white.len() + black.len() > TB_PIECES {
+            return None;
+        }
+
+        let count = |side: &str, c: char| side.chars().filter(|&p| p == c).count();
+        let unique = |side: &str| "QRBNP".chars().any(|c| count(side, c) == 1);
+        let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+
+        Some(Self {
+            name: name.to_string(),
+            white: white.to_string(),
+            piece_count: white.len() + black.len(),
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces: unique(white) || unique(black),
+            symmetric: white == black,
+            pawn_count: if white_leads {
+                [white_pawns, black_pawns]
+            } else {
+                [black_pawns, white_pawns]
+            },
+        })
+    }
+
+    // the material of one side as it appears in table names
+    fn side_of(board: &ChessBoard, color: Color) -> String {
+        let mut side = String::new();
+        for piece_type in [PieceType::King, PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn] {
+            let letter = piece_type.to_char(Color::White);
+            side.extend(std::iter::repeat_n(letter, board.get_piece_bitboard(color, piece_type).count() as usize));
+        }
+        side
+    }
+}
+
+// The decoding state of one sub-table: one per side to move and, with pawns, per file of the
+// leading pawn. Offsets point into the bytes of the table file.
+#[derive(Debug, Clone, Default)]
+struct PairsData {
+    flags: u8,
+    block_size: usize,
+    span: u64,
+    num_blocks: usize,
+    max_sym_len: u8,
+    min_sym_len: u8,
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    block_length_size: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    base64: Vec<u64>,
+    symlen: Vec<u8>,
+    pieces: [u8; TB_PIECES],
+    group_idx: [u64; TB_PIECES + 1],
+    group_len: [usize; TB_PIECES + 1],
+    // DTZ only, where the value maps of each WDL result start
+    map_idx: [usize; 4],
+}
+
+struct Table {
+    bytes: Vec<u8>,
+    // [side to move][file of the leading pawn]
+    items: [[PairsData; 4]; 2],
+    map:   usize,
+}
+
+fn byte(bytes: &[u8], offset: usize) -> u8 {
+    bytes.get(offset).copied().unwrap_or(0)
+}
+
+fn read_u16_le(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([byte(bytes, offset), byte(bytes, offset + 1)])
+}
+
+fn read_u32_le(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(std::array::from_fn(|i| byte(bytes, offset + i)))
+}
+
+fn read_u32_be(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(std::array::from_fn(|i| byte(bytes, offset + i)))
+}
+
+fn read_u64_be(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(std::array::from_fn(|i| byte(bytes, offset + i)))
+}
+
+impl Table {
+    fn parse(bytes: Vec<u8>, material: &Material, kind: TableKind) -> Option<Self> {
+        if bytes.len() < 5 || bytes[..4] != kind.magic() {
+            return None;
+        }
+        let header = bytes[4];
+        if (header & HEADER_SPLIT != 0) == material.symmetric || (header & HEADER_HAS_PAWNS != 0) != material.has_pawns {
+            return None;
+        }
+
+        let mut items: [[PairsData; 4]; 2] = Default::default();
+        let sides = if kind == TableKind::Wdl && !material.symmetric { 2 } else { 1 };
+        let files = if material.has_pawns { 4 } else { 1 };
+        let pp = material.has_pawns && material.pawn_count[1] > 0;
+        let mut data = 5;
+
+        for f in 0..files {
+            let order = [
+                [byte(&bytes, data) & 0xF, if pp { byte(&bytes, data + 1) & 0xF } else { 0xF }],
+                [byte(&bytes, data) >> 4, if pp { byte(&bytes, data + 1) >> 4 } else { 0xF }],
+            ];
+            data += 1 + pp as usize;
+
+            for k in 0..material.piece_count {
+                for (i, side) in items.iter_mut().enumerate().take(sides) {
+                    side[f].pieces[k] = if i == 0 { byte(&bytes, data) & 0xF } else { byte(&bytes, data) >> 4 };
+                }
+                data += 1;
+            }
+            for (i, side) in items.iter_mut().enumerate().take(sides) {
+                set_groups(material, &mut side[f], order[i], f);
+            }
+        }
+        data += data & 1;
+
+        for f in 0..files {
+            for side in items.iter_mut().take(sides) {
+                data = set_sizes(&bytes, &mut side[f], data)?;
+            }
+        }
+
+        let map = data;
+        if kind == TableKind::Dtz {
+            for item in items[0].iter_mut().take(files) {
+                if item.flags & FLAG_MAPPED == 0 {
+                    continue;
+                }
+                if item.flags & FLAG_WIDE != 0 {
+                    data += data & 1;
+                    for i in 0..4 {
+                        item.map_idx[i] = (data - map) / 2 + 1;
+                        data += 2 * read_u16_le(&bytes, data) as usize + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        item.map_idx[i] = data - map + 1;
+                        data += byte(&bytes, data) as usize + 1;
+                    }
+                }
+            }
+            data += data & 1;
+        }
+
+        for f in 0..files {
+            for side in items.iter_mut().take(sides) {
+                side[f].sparse_index = data;
+                data += side[f].sparse_index_size * 6;
+            }
+        }
+        for f in 0..files {
+            for side in items.iter_mut().take(sides) {
+                side[f].block_length = data;
+                data += side[f].block_length_size * 2;
+            }
+        }
+        for f in 0..files {
+            for side in items.iter_mut().take(sides) {
+                data = (data + 0x3F) & !0x3F;
+                side[f].data = data;
+                data += side[f].num_blocks * side[f].block_size;
+            }
+        }
+
+        Some(Self { bytes, items, map })
+    }
+
+    fn get(&self, kind: TableKind, stm: usize, file: usize) -> &PairsData {
+        &self.items[stm % kind.sides()][file]
+    }
+
+    fn decompress(&self, d: &PairsData, idx: u64) -> i32 {
+        let bytes = &self.bytes;
+        if d.flags & FLAG_SINGLE_VALUE != 0 {
+            return d.min_sym_len as i32;
+        }
+
+        // the sparse index points at a block and an offset close to the value we want
+        let entry = d.sparse_index + (idx / d.span) as usize * 6;
+        let mut block = read_u32_le(bytes, entry) as usize;
+        let mut offset = read_u16_le(bytes, entry + 4) as i64 + (idx % d.span) as i64 - (d.span / 2) as i64;
+        let block_length = |block: usize| read_u16_le(bytes, d.block_length + 2 * block) as i64;
+
+        while offset < 0 {
+            block = block.wrapping_sub(1);
+            offset += block_length(block) + 1;
+        }
+        while offset > block_length(block) {
+            offset -= block_length(block) + 1;
+            block += 1;
+        }
+
+        let mut ptr = d.data + block * d.block_size;
+        let mut buf64 = read_u64_be(bytes, ptr);
+        ptr += 8;
+        let mut buf64_size: i32 = 64;
+        let min_sym_len = d.min_sym_len as usize;
+        let symlen = |sym: usize| d.symlen.get(sym).copied().unwrap_or(0) as i64;
+
+        // walk the canonical Huffman codes of the block until we reach the symbol holding offset
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while len + 1 < d.base64.len() && buf64 < d.base64[len] {
+                len += 1;
+            }
+            sym = (buf64 - d.base64[len]).checked_shr((64 - len - min_sym_len) as u32).unwrap_or(0) as usize;
+            sym += read_u16_le(bytes, d.lowest_sym + 2 * len) as usize;
+
+            if offset < symlen(sym) + 1 {
+                break;
+            }
+            offset -= symlen(sym) + 1;
+            let len = len + min_sym_len;
+            buf64 = buf64.checked_shl(len as u32).unwrap_or(0);
+            buf64_size -= len as i32;
+            if buf64_size <= 32 {
+                buf64_size += 32;
+                buf64 |= (read_u32_be(bytes, ptr) as u64) << (64 - buf64_size);
+                ptr += 4;
+            }
+        }
+
+        // then descend the pair tree of that symbol to the single value
+        while symlen(sym) != 0 {
+            let left = btree_left(bytes, d.btree, sym);
+            if offset < symlen(left) + 1 {
+                sym = left;
+            } else {
+                offset -= symlen(left) + 1;
+                sym = btree_right(bytes, d.btree, sym);
+            }
+        }
+        btree_left(bytes, d.btree, sym) as i32
+    }
+
+    // DTZ values are stored compressed per WDL result, turn them back into plies
+    fn map_score(&self, file: usize, value: i32, wdl: i32) -> i32 {
+        const WDL_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+
+        let d = &self.items[0][file];
+        let mut value = value;
+        if d.flags & FLAG_MAPPED != 0 {
+            let idx = d.map_idx[WDL_MAP[(wdl + 2) as usize]] + value as usize;
+            value = if d.flags & FLAG_WIDE != 0 {
+                read_u16_le(&self.bytes, self.map + 2 * idx) as i32
+            } else {
+                byte(&self.bytes, self.map + idx) as i32
+            };
+        }
+
+        // DTZ tables store moves rather than plies unless the flags say otherwise
+        if (wdl == 2 && d.flags & FLAG_WIN_PLIES == 0) || (wdl == -2 && d.flags & FLAG_LOSS_PLIES == 0) || wdl == 1 || wdl == -1 {
+            value *= 2;
+        }
+        value + 1
+    }
+}
+
+fn btree_left(bytes: &[u8], btree: usize, sym: usize) -> usize {
+    let lr = btree + 3 * sym;
+    ((byte(bytes, lr + 1) as usize & 0xF) << 8) | byte(bytes, lr) as usize
+}
+
+fn btree_right(bytes: &[u8], btree: usize, sym: usize) -> usize {
+    let lr = btree + 3 * sym;
+    ((byte(bytes, lr + 2) as usize) << 4) | (byte(bytes, lr + 1) as usize >> 4)
+}
+
+// Pieces are encoded in groups of identical pieces, the first group being the kings (plus a
+// third unique piece when there is one) or the leading pawns. order gives the position of the
+// leading group and of the other side's pawns in the product that makes up an index.
+fn set_groups(material: &Material, d: &mut PairsData, order: [u8; 2], file: usize) {
+    let indices = Indices::get();
+    let mut n = 0;
+    let mut first_len: i32 = if material.has_pawns {
+        0
+    } else if material.has_unique_pieces {
+        3
+    } else {
+        2
+    };
+    d.group_len[0] = 1;
+
+    for i in 1..material.piece_count {
+        first_len -= 1;
+        if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+            d.group_len[n] += 1;
+        } else {
+            n += 1;
+            d.group_len[n] = 1;
+        }
+    }
+    n += 1;
+    d.group_len[n] = 0;
+
+    let pp = material.has_pawns && material.pawn_count[1] > 0;
+    let mut next = if pp { 2 } else { 1 };
+    let mut free_squares = 64 - d.group_len[0] - if pp { d.group_len[1] } else { 0 };
+    let mut idx: u64 = 1;
+    let mut k = 0;
+
+    while next < n || k == order[0] as usize || k == order[1] as usize {
+        if k == order[0] as usize {
+            d.group_idx[0] = idx;
+            idx *= if material.has_pawns {
+                indices.lead_pawns_size[d.group_len[0]][file]
+            } else if material.has_unique_pieces {
+                31332
+            } else {
+                462
+            };
+        } else if k == order[1] as usize {
+            d.group_idx[1] = idx;
+            idx *= indices.binomial[d.group_len[1]][48 - d.group_len[0]];
+        } else {
+            d.group_idx[next] = idx;
+            idx *= indices.binomial[d.group_len[next]][free_squares];
+            free_squares -= d.group_len[next];
+            next += 1;
+        }
+        k += 1;
+    }
+    d.group_idx[n] = idx;
+}
+
+fn set_sizes(bytes: &[u8], d: &mut PairsData, mut data: usize) -> Option<usize> {
+    d.flags = byte(bytes, data);
+    data += 1;
+    if d.flags & FLAG_SINGLE_VALUE != 0 {
+        d.min_sym_len = byte(bytes, data);
+        return Some(data + 1);
+    }
+
+    // the number of positions in the table is the product of all group sizes
+    let groups = d.group_len.iter().position(|&len| len == 0)?;
+    let table_size = d.group_idx[groups];
+
+    d.block_size = 1 << byte(bytes, data);
+    d.span = 1 << byte(bytes, data + 1);
+    d.sparse_index_size = table_size.div_ceil(d.span) as usize;
+    let padding = byte(bytes, data + 2) as usize;
+    d.num_blocks = read_u32_le(bytes, data + 3) as usize;
+    d.block_length_size = d.num_blocks + padding;
+    d.max_sym_len = byte(bytes, data + 7);
+    d.min_sym_len = byte(bytes, data + 8);
+    data += 9;
+    d.lowest_sym = data;
+
+    if d.min_sym_len == 0 || d.max_sym_len < d.min_sym_len || d.max_sym_len > 64 {
+        return None;
+    }
+    let lengths = (d.max_sym_len - d.min_sym_len + 1) as usize;
+    let lowest_sym = |i: usize| read_u16_le(bytes, d.lowest_sym + 2 * i) as u64;
+
+    // base64[i] is the lowest code of length min_sym_len + i, left aligned in 64 bits
+    d.base64 = vec![0; lengths];
+    for i in (0..lengths - 1).rev() {
+        d.base64[i] = d.base64[i + 1].wrapping_add(lowest_sym(i)).wrapping_sub(lowest_sym(i + 1)) / 2;
+    }
+    for (i, base) in d.base64.iter_mut().enumerate() {
+        *base = base.checked_shl((64 - i - d.min_sym_len as usize) as u32).unwrap_or(0);
+    }
+    data += lengths * 2;
+
+    let symbols = read_u16_le(bytes, data) as usize;
+    data += 2;
+    d.btree = data;
+    d.symlen = vec![0; symbols];
+    let mut visited = vec![false; symbols];
+    for sym in 0..symbols {
+        if !visited[sym] {
+            set_symlen(bytes, d, sym, &mut visited);
+        }
+    }
+    Some(data + symbols * 3 + (symbols & 1))
+}
+
+// the number of values a symbol expands to, minus one
+fn set_symlen(bytes: &[u8], d: &mut PairsData, sym: usize, visited: &mut [bool]) {
+    let right = btree_right(bytes, d.btree, sym);
+    if right == 0xFFF {
+        d.symlen[sym] = 0;
+    } else {
+        let left = btree_left(bytes, d.btree, sym);
+        if left >= visited.len() || right >= visited.len() {
+            return;
+        }
+        visited[sym] = true;
+        if !visited[left] {
+            set_symlen(bytes, d, left, visited);
+        }
+        if !visited[right] {
+            set_symlen(bytes, d, right, visited);
+        }
+        d.symlen[sym] = d.symlen[left].wrapping_add(d.symlen[right]).wrapping_add(1);
+    }
+    visited[sym] = true;
+}
+
+// The tables used to turn piece placements into table indices.
+struct Indices {
+    map_pawns: [usize; 64],
+    map_b1h1h7: [usize; 64],
+    map_a1d1d4: [usize; 64],
+    // [map_a1d1d4 of the first king][square of the second king]
+    map_kk: [[usize; 64]; 10],
+    binomial: [[u64; 64]; 7],
+    lead_pawn_idx: [[u64; 64]; 7],
+    lead_pawns_size: [[u64; 4]; 7],
+}
+
+fn off_a1h8(sq: usize) -> i32 {
+    (sq >> 3) as i32 - (sq & 7) as i32
+}
+
+fn edge_distance(file: usize) -> usize {
+    file.min(7 - file)
+}
+
+impl Indices {
+    fn get() -> &'static Indices {
+        INDICES.get_or_init(Indices::new)
+    }
+
+    // written as square loops to stay close to how the tables are usually described
+    #[allow(clippy::needless_range_loop)]
+    fn new() -> Self {
+        let mut map_b1h1h7 = [0; 64];
+        let mut map_a1d1d4 = [0; 64];
+        let mut map_kk = [[0; 64]; 10];
+
+        // squares below the a1-h8 diagonal
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_a1h8(sq) < 0 {
+                map_b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+
+        // the a1-d1-d4 triangle, off the diagonal first
+        let mut diagonal = Vec::new();
+        let mut code = 0;
+        for sq in [0, 1, 2, 3, 9, 10, 11, 18, 19, 27] {
+            if off_a1h8(sq) < 0 {
+                map_a1d1d4[sq] = code;
+                code += 1;
+            } else if off_a1h8(sq) == 0 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            map_a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        // legal placements of two kings with the first in the a1-d1-d4 triangle, both on the
+        // diagonal last. b1 is the only square of the triangle with code 0.
+        let mut both_on_diagonal = Vec::new();
+        let mut code = 0;
+        for idx in 0..10 {
+            for s1 in 0..28 {
+                if map_a1d1d4[s1] != idx || (idx == 0 && s1 != 1) {
+                    continue;
+                }
+                for s2 in 0..64 {
+                    if s1 == s2 || ChessBoard::KING_ATTACKS[s1].is_set(crate::ChessSquare(s2 as u8)) {
+                        continue;
+                    }
+                    if off_a1h8(s1) == 0 && off_a1h8(s2) > 0 {
+                        continue;
+                    }
+                    if off_a1h8(s1) == 0 && off_a1h8(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            map_kk[idx][s2] = code;
+            code += 1;
+        }
+
+        let mut binomial = [[0; 64]; 7];
+        binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..7.min(n + 1) {
+                binomial[k][n] = if k > 0 { binomial[k - 1][n - 1] } else { 0 } + binomial[k][n - 1];
+            }
+        }
+
+        // pawns never stand on the first or last rank, squares towards the centre files get
+        // the lower codes so the leading pawn is the one closest to the a or h file
+        let mut map_pawns = [0; 64];
+        let mut lead_pawn_idx = [[0; 64]; 7];
+        let mut lead_pawns_size = [[0; 4]; 7];
+        let mut available: usize = 47;
+        for lead_pawns in 1..=6 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        map_pawns[sq] = available;
+                        available -= 1;
+                        map_pawns[sq ^ 7] = available;
+                        available = available.saturating_sub(1);
+                    }
+                    lead_pawn_idx[lead_pawns][sq] = idx;
+                    idx += binomial[lead_pawns - 1][map_pawns[sq]];
+                }
+                lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+
+        Self { map_pawns, map_b1h1h7, map_a1d1d4, map_kk, binomial, lead_pawn_idx, lead_pawns_size }
+    }
+}
+
+// the code of a piece in table files, white pawn to king 1 to 6 and black 9 to 14
+fn piece_code(piece: ChessPiece) -> u8 {
+    piece.piece_type as u8 + 1 + if piece.color == Color::Black { 8 } else { 0 }
+}
+
+struct Entry {
+    material: Material,
+    wdl:      OnceLock<Option<Table>>,
+    dtz:      OnceLock<Option<Table>>,
+}
+
+// The tables found in a directory. Files are only read once a probe needs them.
+pub struct Tablebase {
+    directory:  PathBuf,
+    entries:    Vec<Entry>,
+    // both orientations of every table name, e.g. KRvKP and KPvKR
+    by_name:    HashMap<String, usize>,
+    max_pieces: usize,
+}
+
+impl Tablebase {
+    pub fn open(directory: impl AsRef<Path>) -> io::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        let mut entries = Vec::new();
+        let mut by_name = HashMap::new();
+        let mut max_pieces = 0;
+
+        for file in fs::read_dir(&directory)? {
+            let path = file?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(TableKind::Wdl.extension()) {
+                continue;
+            }
+            let Some(material) = path.file_stem().and_then(|stem| stem.to_str()).and_then(Material::from_name) else {
+                continue;
+            };
+            let (white, black) = material.name.split_once('v').unwrap();
+            by_name.insert(material.name.clone(), entries.len());
+            by_name.insert(format!("{}v{}", black, white), entries.len());
+            max_pieces = max_pieces.max(material.piece_count);
+            entries.push(Entry { material, wdl: OnceLock::new(), dtz: OnceLock::new() });
+        }
+
+        Ok(Self { directory, entries, by_name, max_pieces })
+    }
+
+    pub fn init_global(directory: impl AsRef<Path>) -> io::Result<&'static Tablebase> {
+        let tablebase = Self::open(directory)?;
+        Ok(TABLEBASE.get_or_init(|| tablebase))
+    }
+
+    pub fn global() -> Option<&'static Tablebase> {
+        TABLEBASE.get()
+    }
+
+    // the most pieces, kings included, of any table found
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    pub fn num_tables(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.by_name.contains_key(name)
+    }
+
+    // None when the position has castling rights, too many pieces or a table is missing
+    pub fn probe_wdl(&self, position: &ChessPosition) -> Option<Wdl> {
+        if !self.covers(position) {
+            return None;
+        }
+        self.search(position, false).map(|(wdl, _)| Wdl::from_score(wdl))
+    }
+
+    // Plies to the next capture or pawn move that keeps the result, positive when the side to
+    // move wins and negative when it loses. Cursed wins and blessed losses are reported beyond
+    // 100 plies. Results can be one ply off when the table stores moves rather than plies.
+    pub fn probe_dtz(&self, position: &ChessPosition) -> Option<i32> {
+        if !self.covers(position) {
+            return None;
+        }
+        self.dtz(position)
+    }
+
+    // the tables are for standard chess, variants end and move differently
+    fn covers(&self, position: &ChessPosition) -> bool {
+        position.variant == Variant::Standard
+            && position.castling_rights.bits == 0
+            && (position.chessboard.all_pieces.count() as usize) <= self.max_pieces.max(2)
+    }
+
+    fn table<'a>(&self, entry: &'a Entry, kind: TableKind) -> Option<&'a Table> {
+        let cell = match kind {
+            TableKind::Wdl => &entry.wdl,
+            TableKind::Dtz => &entry.dtz,
+        };
+        cell.get_or_init(|| {
+            let path = self.directory.join(format!("{}.{}", entry.material.name, kind.extension()));
+            let table = fs::read(&path).ok().and_then(|bytes| Table::parse(bytes, &entry.material, kind));
+            if table.is_none() && path.exists() {
+                warn!("could not read tablebase file {}", path.display());
+            }
+            table
+        })
+        .as_ref()
+    }
+
+    // Looks up the position without considering en passant or zeroing moves. wdl is only used
+    // by DTZ tables, which store separate value maps per result.
+    fn probe_table(&self, position: &ChessPosition, kind: TableKind, wdl: i32) -> Option<Probe> {
+        let board = &position.chessboard;
+        if board.all_pieces.count() == 2 {
+            return Some(Probe::Value(0));
+        }
+
+        let white = Material::side_of(board, Color::White);
+        let black = Material::side_of(board, Color::Black);
+        let entry = &self.entries[*self.by_name.get(&format!("{}v{}", white, black))?];
+        let material = &entry.material;
+        let table = self.table(entry, kind)?;
+        let indices = Indices::get();
+
+        // tables are stored with the stronger side as white, and only white to move when both
+        // sides have the same material
+        let black_stronger = material.white != white;
+        let symmetric_black_to_move = material.symmetric && position.side_to_move == Color::Black;
+        let flip = black_stronger || symmetric_black_to_move;
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = flip as usize ^ (position.side_to_move == Color::Black) as usize;
+
+        let mut squares = [0usize; TB_PIECES];
+        let mut pieces = [0u8; TB_PIECES];
+        let mut size = 0;
+        let mut lead_pawns_count = 0;
+        let mut lead_pawns = crate::Bitboard::EMPTY;
+        let mut file = 0;
+
+        if material.has_pawns {
+            let lead = table.get(kind, 0, 0).pieces[0] ^ flip_color;
+            let color = if lead & 8 != 0 { Color::Black } else { Color::White };
+            lead_pawns = board.get_piece_bitboard(color, PieceType::Pawn);
+            let mut pawns = lead_pawns;
+            while let Some(sq) = pawns.pop_lsb() {
+                squares[size] = sq.0 as usize ^ flip_squares;
+                size += 1;
+            }
+            lead_pawns_count = size;
+
+            // the pawn closest to the a or h file decides which sub-table to use
+            let mut leading = 0;
+            for i in 1..lead_pawns_count {
+                if indices.map_pawns[squares[i]] > indices.map_pawns[squares[leading]] {
+                    leading = i;
+                }
+            }
+            squares.swap(0, leading);
+            file = edge_distance(squares[0] & 7);
+        }
+
+        if kind == TableKind::Dtz {
+            let flags = table.get(kind, stm, file).flags;
+            if (flags & FLAG_STM) as usize != stm && !(material.symmetric && !material.has_pawns) {
+                return Some(Probe::ChangeStm);
+            }
+        }
+
+        let mut others = board.all_pieces & !lead_pawns;
+        while let Some(sq) = others.pop_lsb() {
+            squares[size] = sq.0 as usize ^ flip_squares;
+            pieces[size] = piece_code(board.get_piece_at(sq)?) ^ flip_color;
+            size += 1;
+        }
+
+        let d = table.get(kind, stm, file);
+
+        // put the pieces in the order the table expects them
+        for i in lead_pawns_count..size - 1 {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // mirror the board so the leading piece ends up on the a to d files
+        if squares[0] & 7 > 3 {
+            for sq in &mut squares[..size] {
+                *sq ^= 7;
+            }
+        }
+
+        let mut idx: u64;
+        if material.has_pawns {
+            idx = indices.lead_pawn_idx[lead_pawns_count][squares[0]];
+            squares[1..lead_pawns_count].sort_by_key(|&sq| indices.map_pawns[sq]);
+            for (i, &sq) in squares.iter().enumerate().take(lead_pawns_count).skip(1) {
+                idx += indices.binomial[i][indices.map_pawns[sq]];
+            }
+        } else {
+            // without pawns the board can also be mirrored vertically and along the diagonal
+            if squares[0] >> 3 > 3 {
+                for sq in &mut squares[..size] {
+                    *sq ^= 56;
+                }
+            }
+            for i in 0..d.group_len[0] {
+                if off_a1h8(squares[i]) == 0 {
+                    continue;
+                }
+                if off_a1h8(squares[i]) > 0 {
+                    for sq in &mut squares[i..size] {
+                        *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            idx = if material.has_unique_pieces {
+                let adjust1 = (squares[1] > squares[0]) as usize;
+                let adjust2 = (squares[2] > squares[0]) as usize + (squares[2] > squares[1]) as usize;
+                if off_a1h8(squares[0]) != 0 {
+                    (indices.map_a1d1d4[squares[0]] * 63 + (squares[1] - adjust1)) * 62 + squares[2] - adjust2
+                } else if off_a1h8(squares[1]) != 0 {
+                    (6 * 63 + (squares[0] >> 3) * 28 + indices.map_b1h1h7[squares[1]]) * 62 + squares[2] - adjust2
+                } else if off_a1h8(squares[2]) != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62 + (squares[0] >> 3) * 7 * 28 + ((squares[1] >> 3) - adjust1) * 28 + indices.map_b1h1h7[squares[2]]
+                } else {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + (squares[0] >> 3) * 7 * 6
+                        + ((squares[1] >> 3) - adjust1) * 6
+                        + ((squares[2] >> 3) - adjust2)
+                }
+            } else {
+                indices.map_kk[indices.map_a1d1d4[squares[0]]][squares[1]]
+            } as u64;
+        }
+
+        // the remaining groups are encoded as combinations of the squares still free
+        idx *= d.group_idx[0];
+        let mut group_start = d.group_len[0];
+        let mut remaining_pawns = material.has_pawns && material.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[group_start..group_start + len].sort_unstable();
+            let mut n = 0;
+            for i in 0..len {
+                let sq = squares[group_start + i];
+                let adjust = squares[..group_start].iter().filter(|&&other| sq > other).count();
+                n += indices.binomial[i + 1][sq - adjust - if remaining_pawns { 8 } else { 0 }];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            group_start += len;
+            next += 1;
+        }
+
+        let value = table.decompress(d, idx);
+        Some(Probe::Value(match kind {
+            TableKind::Wdl => value - 2,
+            TableKind::Dtz => table.map_score(file, value, wdl),
+        }))
+    }
+
+    // The tables know nothing of en passant and may store anything for positions where a
+    // capture or pawn move is best, so those moves are searched first. Returns the WDL score
+    // and whether the best move is a capture or pawn move.
+    fn search(&self, position: &ChessPosition, check_zeroing: bool) -> Option<(i32, bool)> {
+        let moves = position.generate_legal();
+        let mut best = -2;
+        let mut move_count = 0;
+
+        for mov in &moves {
+            if !is_capture(position, mov) && (!check_zeroing || !is_pawn_move(position, mov)) {
+                continue;
+            }
+            move_count += 1;
+
+            let mut next = position.clone();
+            next.make_move(mov);
+            let value = -self.search(&next, false)?.0;
+            if value > best {
+                best = value;
+                if value >= 2 {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        let no_more_moves = move_count > 0 && move_count == moves.len();
+        let value = if no_more_moves {
+            best
+        } else {
+            match self.probe_table(position, TableKind::Wdl, 0)? {
+                Probe::Value(value) => value,
+                Probe::ChangeStm => return None,
+            }
+        };
+
+        if best >= value {
+            return Some((best, best > 0 || no_more_moves));
+        }
+        Some((value, false))
+    }
+
+    fn dtz(&self, position: &ChessPosition) -> Option<i32> {
+        let (wdl, zeroing) = self.search(position, true)?;
+        if wdl == 0 {
+            return Some(0);
+        }
+        if zeroing {
+            return Some(dtz_before_zeroing(wdl));
+        }
+
+        match self.probe_table(position, TableKind::Dtz, wdl)? {
+            Probe::Value(dtz) => Some((dtz + if wdl.abs() == 1 { 100 } else { 0 }) * wdl.signum()),
+            // the table holds the other side to move, so take the best move and add a ply
+            Probe::ChangeStm => {
+                let mut min_dtz = i32::MAX;
+                for mov in &position.generate_legal() {
+                    let zeroing = is_capture(position, mov) || is_pawn_move(position, mov);
+                    let mut next = position.clone();
+                    next.make_move(mov);
+
+                    let mut dtz = if zeroing {
+                        -dtz_before_zeroing(self.search(&next, false)?.0)
+                    } else {
+                        -self.dtz(&next)?
+                    };
+                    if dtz == 1 && !next.checkers().is_empty() && next.generate_legal().is_empty() {
+                        min_dtz = 1;
+                    }
+                    if !zeroing {
+                        dtz += dtz.signum();
+                    }
+                    if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                        min_dtz = dtz;
+                    }
+                }
+                Some(if min_dtz == i32::MAX { -1 } else { min_dtz })
+            }
+        }
+    }
+}
+
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    match wdl {
+        2 => 1,
+        1 => 101,
+        -1 => -101,
+        -2 => -1,
+        _ => 0,
+    }
+}
+
+fn is_capture(position: &ChessPosition, mov: &ChessMove) -> bool {
+    position.chessboard.all_pieces.is_set(mov.to) || (is_pawn_move(position, mov) && position.en_passant == Some(mov.to))
+}
+
+fn is_pawn_move(position: &ChessPosition, mov: &ChessMove) -> bool {
+    position.chessboard.get_piece_at(mov.from).is_some_and(|piece| piece.piece_type == PieceType::Pawn)
+}
+
+impl ChessPosition {
+    // the exact result from the global tablebase, if one was loaded and covers the position
+    pub fn tablebase_wdl(&self) -> Option<Wdl> {
+        Tablebase::global()?.probe_wdl(self)
+    }
+
+    pub fn tablebase_dtz(&self) -> Option<i32> {
+        Tablebase::global()?.probe_dtz(self)
+    }
+}
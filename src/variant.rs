@@ -0,0 +1,116 @@
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Bitboard, ChessPosition, Color, PieceType,
+    chess_game::{Outcome, Termination},
+    parse_variant_fen,
+};
+
+// Games played on the standard board under other rules. The variant is part of the position,
+// move generation, the FEN and how a game ends look at it, the ruleset on top still decides
+// between legal and pseudolegal play.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum)]
+pub enum Variant {
+    #[default]
+    Standard,
+    // a king reaching d4, e4, d5 or e5 wins
+    KingOfTheHill,
+    // the third check wins, the checks given so far are part of the position
+    ThreeCheck,
+    // captures are compulsory and the king is an ordinary piece, the side that runs out of
+    // pieces or moves wins
+    Antichess,
+    // white has 36 pawns and no king, black wins by capturing all of them
+    Horde,
+    // captured pieces change sides and can be dropped back onto the board, promoted ones as pawns
+    Crazyhouse,
+}
+
+const CENTER: Bitboard = Bitboard(0x0000001818000000);
+
+impl Variant {
+    pub const ALL: [Variant; 6] =
+        [Variant::Standard, Variant::KingOfTheHill, Variant::ThreeCheck, Variant::Antichess, Variant::Horde, Variant::Crazyhouse];
+
+    // the names Lichess uses in its FEN and PGN exports
+    pub fn name(&self) -> &'static str {
+        match self {
+            Variant::Standard => "Standard",
+            Variant::KingOfTheHill => "King of the Hill",
+            Variant::ThreeCheck => "Three-check",
+            Variant::Antichess => "Antichess",
+            Variant::Horde => "Horde",
+            Variant::Crazyhouse => "Crazyhouse",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let key = |name: &str| name.chars().filter(char::is_ascii_alphanumeric).collect::<String>().to_ascii_lowercase();
+        Self::ALL.into_iter().find(|variant| key(variant.name()) == key(name))
+    }
+
+    pub fn start_fen(&self) -> &'static str {
+        match self {
+            Variant::Standard | Variant::KingOfTheHill => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            Variant::ThreeCheck => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 3+3 0 1",
+            Variant::Antichess => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1",
+            Variant::Horde => "rnbqkbnr/pppppppp/8/1PP2PP1/PPPPPPPP/PPPPPPPP/PPPPPPPP/PPPPPPPP w kq - 0 1",
+            Variant::Crazyhouse => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[] w KQkq - 0 1",
+        }
+    }
+
+    pub fn start_position(&self) -> ChessPosition {
+        parse_variant_fen(self.start_fen(), *self).expect("invalid variant start position")
+    }
+
+    // whether the side has a king it must not leave in check, without one every pseudolegal move is legal
+    pub fn has_royal_king(&self, color: Color) -> bool {
+        match self {
+            Variant::Antichess => false,
+            Variant::Horde => color == Color::Black,
+            _ => true,
+        }
+    }
+
+    // lone kings can still win the race to the center, Antichess and Horde aren't won by mate and
+    // in Crazyhouse every capture is material for a drop
+    pub fn has_insufficient_material_draws(&self) -> bool {
+        matches!(self, Variant::Standard | Variant::ThreeCheck)
+    }
+
+    // Results only this variant has, decided before the ruleset looks at moves, kings and draws.
+    pub fn outcome(&self, position: &ChessPosition) -> Option<Outcome> {
+        let board = &position.chessboard;
+        let us = position.side_to_move;
+        match self {
+            Variant::Standard | Variant::Crazyhouse => None,
+            Variant::KingOfTheHill => [us.opposite(), us]
+                .into_iter()
+                .find(|&color| !(board.get_piece_bitboard(color, PieceType::King) & CENTER).is_empty())
+                .map(|winner| Outcome::Finished(Some(winner), Termination::KingInCenter)),
+            Variant::ThreeCheck => [us.opposite(), us]
+                .into_iter()
+                .find(|&color| position.checks[color as usize] >= 3)
+                .map(|winner| Outcome::Finished(Some(winner), Termination::ThreeChecks)),
+            Variant::Antichess if position.pseudolegal_moves.is_empty() => {
+                let pieces = if us == Color::White { board.white_occupancy } else { board.black_occupancy };
+                let termination = if pieces.is_empty() {
+                    Termination::OutOfPieces
+                } else {
+                    Termination::Stalemate
+                };
+                Some(Outcome::Finished(Some(us), termination))
+            }
+            Variant::Antichess => None,
+            Variant::Horde => board.white_occupancy.is_empty().then_some(Outcome::Finished(Some(Color::Black), Termination::OutOfPieces)),
+        }
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
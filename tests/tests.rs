@@ -1,56 +1,1231 @@
-use chess_engine::{self, ChessGame, ChessMove, ChessSquare, Color};
-use log::{debug, info};
+use chess_engine::magic::ray_attacks;
+use chess_engine::polyglot::{decode_move, encode_move};
+use chess_engine::{
+    self, Bitboard, BoardSvg, BookWriter, CastlingRights, ChessBoard, ChessGame, ChessMove, ChessPiece, ChessPosition, ChessSquare, Color, Divide,
+    DrawRules, EpdErrorKind, EpdRecord, EpdResult, EpdSuite, FenError, FuzzConfig, FuzzFailureKind, HashHistory, MoveKind, NetworkInputs,
+    NetworkLabels, Outcome, PERFT_SUITE, POLICY_SIZE, PackError, PackedGame, PackedMove, PackedPosition, PgnErrorKind, PgnGame, PgnReader, PieceType,
+    PolyglotBook, PolyglotKeys, PositionBuilder, ReplayPosition, ReplaySample, Ruleset, SanError, Selection, SliderAttacks, SliderIndexing,
+    SuiteReport, Tablebase, Termination, TrainingSample, Variant, Wdl, XorShift64, chess960_fen, divide, flip_ranks, fuzz_movegen, parse_fen,
+    parse_pgn, parse_variant_fen, perft, perft_parallel, run_suite,
+};
 
 #[test]
 fn move_generator_start_position() {
-    env_logger::init();
+    let _ = env_logger::try_init();
     let mut chess_game = ChessGame::default();
-    chess_game.make_move(&ChessMove::new(
-        ChessSquare::from_name("a2").unwrap(),
-        ChessSquare::from_name("a3").unwrap(),
-        None,
-    ));
+    chess_game.make_move(&ChessMove::new(ChessSquare::from_name("a2").unwrap(), ChessSquare::from_name("a3").unwrap(), None));
     println!("{}", chess_game.position);
 
     assert_eq!(20, chess_game.position.pseudolegal_moves.iter().count())
 }
-//
-// #[test]
-// fn move_generator_kiwipete() {
-//     let chess_game = ChessGame::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
-//     let moves: Vec<ChessMove> =
-//         chess_game.generate_pseudolegal().into_iter().filter(|x| chess_game.is_legal(x)).collect();
-//     println!("{}", chess_game.chessboard.display_ascii());
-//     print_moves(&moves);
-//     assert_eq!(48, moves.iter().count());
-// }
-//
-// #[test]
-// fn move_generator_3() {
-//     let chess_game = ChessGame::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1");
-//     let moves: Vec<ChessMove> =
-//         chess_game.generate_pseudolegal().into_iter().filter(|x| chess_game.is_legal(x)).collect();
-//     // println!("{}", chess_game.chessboard.display_ascii());
-//     // print_moves(&moves);
-//     assert_eq!(14, moves.iter().count());
-// }
-//
-// #[test]
-// fn move_generator_4() {
-//     let chess_game = ChessGame::from_fen("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1");
-//     let moves: Vec<ChessMove> =
-//         chess_game.generate_pseudolegal().into_iter().filter(|x| chess_game.is_legal(x)).collect();
-//     // println!("{}", chess_game.chessboard.display_ascii());
-//     // print_moves(&moves);
-//     assert_eq!(6, moves.iter().count());
-// }
-//
-// #[test]
-// fn test_lone_rook_center() {
-//     let chess_game = ChessGame::from_fen("8/8/8/8/4R3/8/8/8 w - - 0 1");
-//     let moves: Vec<ChessMove> =
-//         chess_game.generate_pseudolegal().into_iter().collect();
-//     println!("{}", chess_game.chessboard.display_ascii());
-//     print_moves(&moves);
-//     assert_eq!(14, moves.iter().count());
-// }
+
+#[test]
+fn move_generator_kiwipete() {
+    let chess_game = ChessGame::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+    println!("{}", chess_game.position.chessboard.display_ascii());
+    assert_eq!(48, perft(&chess_game.position, 1));
+}
+
+#[test]
+fn move_generator_3() {
+    let chess_game = ChessGame::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1").unwrap();
+    assert_eq!(14, perft(&chess_game.position, 1));
+}
+
+#[test]
+fn move_generator_4() {
+    let chess_game = ChessGame::from_fen("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1").unwrap();
+    assert_eq!(6, perft(&chess_game.position, 1));
+}
+
+#[test]
+fn test_lone_rook_center() {
+    let chess_game = ChessGame::from_fen("k7/8/8/8/4R3/8/8/K7 w - - 0 1").unwrap();
+    println!("{}", chess_game.position.chessboard.display_ascii());
+    assert_eq!(14, chess_game.position.pseudolegal_moves.iter().filter(|mov| mov.from() == ChessSquare::E4).count());
+}
+
+#[test]
+fn fen_rejects_malformed_positions() {
+    let err = |fen: &str| ChessGame::from_fen(fen).unwrap_err();
+
+    assert_eq!(err("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP w KQkq - 0 1"), FenError::WrongRankCount { index: 0, ranks: 7 });
+    assert_eq!(err("rnbqkbnr/ppppXppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"), FenError::InvalidPiece { index: 13, found: 'X' });
+    assert_eq!(err("rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"), FenError::InvalidPiece { index: 18, found: '9' });
+    assert_eq!(err("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNRR w KQkq - 0 1"), FenError::RankTooLong { index: 43, rank: 0 });
+    assert_eq!(err("rnbqkbnr/ppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"), FenError::RankTooShort { index: 16, rank: 6 });
+    assert_eq!(err("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQ1BNR w kq - 0 1"), FenError::KingCount { color: Color::White, count: 0 });
+    assert_eq!(err("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPP1/RNBQKBNP w kq - 0 1"), FenError::PawnOnBackRank { square: ChessSquare::H1 });
+    assert_eq!(err("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1"), FenError::InvalidSideToMove { index: 44, found: "x".to_string() });
+    assert_eq!(err("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/1NBQKBNR w KQkq - 0 1"), FenError::CastlingWithoutPieces { index: 47, found: 'Q' });
+    assert_eq!(
+        err("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e6 0 1"),
+        FenError::ImplausibleEnPassant { index: 51, square: ChessSquare::E6 }
+    );
+    assert_eq!(err("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - x 1"), FenError::InvalidClock { index: 53, found: "x".to_string() });
+    assert_eq!(err("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w"), FenError::MissingField("castling rights"));
+
+    // X-FEN rook files and a trailing newline are accepted, the hash and history are set up
+    let game = ChessGame::from_fen("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b HAha e3 0 3\n").unwrap();
+    assert_eq!(game.position.castling_rights, CastlingRights::new());
+    assert_eq!(game.position.en_passant, Some(ChessSquare::E3));
+    assert_eq!(game.position.zobrist_hash, game.position.calculate_hash());
+    assert_eq!(game.history.len(), 1);
+}
+
+#[test]
+fn perft_divide_matches_total() {
+    let chess_game = ChessGame::default();
+    let div = divide(&chess_game.position, 3, false);
+    println!("{}", div);
+    assert_eq!(20, div.moves.len());
+    assert_eq!(8902, div.total());
+    assert_eq!(perft(&chess_game.position, 3), perft_parallel(&chess_game.position, 3));
+}
+
+#[test]
+fn perft_reference_suite() {
+    let results = run_suite(100_000, true);
+    results.iter().for_each(|result| println!("{}", result));
+    assert!(results.iter().all(|result| result.passed()));
+}
+
+#[test]
+fn slider_tables_match_ray_walk() {
+    let magic = SliderAttacks::new(SliderIndexing::Magic);
+    let pext = SliderAttacks::new(SliderIndexing::Pext);
+    let mut rng = XorShift64::new(42);
+
+    for _ in 0..2000 {
+        let occupancy = Bitboard(rng.next() & rng.next());
+        for sq in (0..64).map(ChessSquare) {
+            let bishop = ray_attacks(ChessBoard::BISHOP_ATTACKS[sq.0 as usize], occupancy);
+            let rook = ray_attacks(ChessBoard::ROOK_ATTACKS[sq.0 as usize], occupancy);
+            assert_eq!(bishop, magic.bishop(sq, occupancy));
+            assert_eq!(rook, magic.rook(sq, occupancy));
+            assert_eq!(bishop, pext.bishop(sq, occupancy));
+            assert_eq!(rook, pext.rook(sq, occupancy));
+        }
+    }
+}
+
+#[test]
+fn incremental_hashes_follow_transpositions() {
+    let play = |moves: &[&str]| {
+        let mut game = ChessGame::default();
+        for uci in moves {
+            game.make_move(&ChessMove::from_uci(uci).unwrap());
+        }
+        game.position
+    };
+
+    let a = play(&["g1f3", "g8f6", "b1c3", "b8c6"]);
+    let b = play(&["b1c3", "b8c6", "g1f3", "g8f6"]);
+    assert_eq!(a.zobrist_hash, b.zobrist_hash);
+    assert_eq!(a.zobrist_hash, a.calculate_hash());
+
+    // knight moves leave the pawn structure and the material alone
+    let start = ChessGame::default().position;
+    assert_eq!(start.pawn_hash, a.pawn_hash);
+    assert_eq!(start.material_hash, a.material_hash);
+
+    let captured = play(&["e2e4", "d7d5", "e4d5", "d8d5"]);
+    assert_ne!(start.material_hash, captured.material_hash);
+    assert_eq!(captured.material_hash, captured.calculate_material_hash());
+    assert_eq!(captured.pawn_hash, captured.calculate_pawn_hash());
+}
+
+#[test]
+fn make_unmake_round_trip_random_games() {
+    let mut rng = XorShift64::new(7);
+
+    for i in 0..30 {
+        // a third of the games start from Chess960 positions
+        let mut game = if i % 3 == 2 {
+            ChessGame::chess960(rng.next() as usize % 960)
+        } else {
+            ChessGame::default()
+        };
+        let mut fens = vec![game.position.to_fen()];
+
+        for _ in 0..120 {
+            let legal: Vec<ChessMove> =
+                game.position.pseudolegal_moves.iter().filter(|&&mov| game.position.is_legal_packed(mov)).map(|&mov| mov.into()).collect();
+            if legal.is_empty() {
+                break;
+            }
+
+            // every legal move must unmake back to the same position
+            for mov in &legal {
+                let before = game.position.clone();
+                let undo = game.position.make_move(mov);
+                game.position.unmake_move(mov, &undo);
+                assert_eq!(before.to_fen(), game.position.to_fen(), "unmake of {} broke the board", mov.to_uci());
+                assert_eq!(before.zobrist_hash, game.position.zobrist_hash);
+                assert_eq!(before.pseudolegal_moves, game.position.pseudolegal_moves);
+            }
+
+            let mov = legal[rng.next() as usize % legal.len()];
+            game.make_move(&mov);
+            fens.push(game.position.to_fen());
+        }
+
+        while !game.move_list.is_empty() {
+            fens.pop();
+            game.unmake_move();
+            assert_eq!(fens.last().unwrap(), &game.position.to_fen());
+        }
+        assert!(game.undo_stack.is_empty());
+    }
+}
+
+// the moves have to agree down to their kinds, make_packed trusts them
+fn legal_sets_agree(position: &mut ChessPosition, depth: u32) {
+    let mut direct: Vec<PackedMove> = position.generate_legal_packed().into_iter().collect();
+    let mut filtered: Vec<PackedMove> = position.pseudolegal_moves.iter().copied().filter(|&mov| position.is_legal_packed(mov)).collect();
+    direct.sort_by_key(|mov| mov.raw());
+    filtered.sort_by_key(|mov| mov.raw());
+    assert_eq!(direct, filtered, "legal move sets differ in {}", position.to_fen());
+
+    if depth > 1 {
+        for &mov in &filtered {
+            let undo = position.make_packed(mov);
+            legal_sets_agree(position, depth - 1);
+            position.unmake_packed(&undo);
+        }
+    }
+}
+
+#[test]
+fn legal_generator_matches_filtered_pseudolegal() {
+    let extra = [
+        // en passant that would expose the king along the rank
+        "8/8/8/K1pP3r/8/8/8/7k w - c6 0 1",
+        // en passant that removes the checking pawn
+        "8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1",
+        // double check
+        "4k3/8/8/8/8/5n2/8/r3K2R w K - 0 1",
+        // castling through an attacked square
+        "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1",
+        "r3k2r/8/8/8/8/8/5r2/R3K2R w KQkq - 0 1",
+    ];
+    for fen in PERFT_SUITE.iter().map(|case| case.fen).chain(extra) {
+        let mut position = ChessGame::from_fen(fen).unwrap().position;
+        legal_sets_agree(&mut position, 3);
+    }
+}
+
+#[test]
+fn san_round_trips_every_legal_move() {
+    for case in PERFT_SUITE.iter() {
+        let position = ChessGame::from_fen(case.fen).unwrap().position;
+        for mov in position.generate_legal() {
+            let san = mov.to_san(&position);
+            assert_eq!(ChessMove::from_san(&san, &position), Ok(mov), "{} in {}", san, case.fen);
+        }
+    }
+}
+
+#[test]
+fn san_formatting_and_parsing() {
+    let san = |fen: &str, uci: &str| {
+        let position = ChessGame::from_fen(fen).unwrap().position;
+        ChessMove::from_uci(uci).unwrap().to_san(&position)
+    };
+    let kiwipete = PERFT_SUITE[1].fen;
+    assert_eq!(san(kiwipete, "e1g1"), "O-O");
+    assert_eq!(san(kiwipete, "e1c1"), "O-O-O");
+    assert_eq!(san(kiwipete, "d5e6"), "dxe6");
+    assert_eq!(san(kiwipete, "e5f7"), "Nxf7");
+    assert_eq!(san("k7/8/8/8/8/2N3N1/8/K7 w - - 0 1", "c3e4"), "Nce4");
+    assert_eq!(san("k7/8/8/2N5/8/2N5/8/K7 w - - 0 1", "c5e4"), "N5e4");
+    assert_eq!(san("1k6/8/K7/8/4Q2Q/8/8/7Q w - - 0 1", "h4e1"), "Qh4e1");
+    assert_eq!(san("8/P6k/8/8/8/8/8/K7 w - - 0 1", "a7a8Q"), "a8=Q");
+    assert_eq!(san("rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2", "d8h4"), "Qh4#");
+    assert_eq!(san("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", "a1a8"), "Ra8+");
+
+    let mut game = ChessGame::default();
+    for san in ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Bxc6", "dxc6", "0-0", "Bg4"] {
+        let mov = game.san_to_move(san).unwrap();
+        game.make_move(&mov);
+    }
+    assert_eq!(game.position.to_fen(), "r2qkbnr/1pp2ppp/p1p5/4p3/4P1b1/5N2/PPPP1PPP/RNBQ1RK1 w kq - 2 6");
+
+    let promotion = ChessGame::from_fen("8/P6k/8/8/8/8/8/K7 w - - 0 1").unwrap();
+    assert_eq!(promotion.san_to_move("a8q+").unwrap().to_uci(), "a7a8q");
+    assert_eq!(promotion.san_to_move("a7-a8=N").unwrap().to_uci(), "a7a8n");
+    assert_eq!(promotion.san_to_move("a8"), Err(SanError::MissingPromotion("a8".to_string())));
+
+    let knights = ChessGame::from_fen("k7/8/8/8/8/2N3N1/8/K7 w - - 0 1").unwrap();
+    assert!(matches!(knights.san_to_move("Ne4"), Err(SanError::AmbiguousMove { candidates, .. }) if candidates.len() == 2));
+    assert_eq!(knights.san_to_move("Nd4"), Err(SanError::IllegalMove("Nd4".to_string())));
+    assert_eq!(knights.san_to_move("Nz9"), Err(SanError::InvalidSyntax("Nz9".to_string())));
+}
+
+const PGN_GAMES: &str = r#"[Event "Casual"]
+[Site "?"]
+[Date "2024.01.01"]
+[Round "1"]
+[White "Anderssen"]
+[Black "Kieseritzky"]
+[Result "1-0"]
+[Opening "King's Gambit"]
+
+{Opening comment} 1. e4 e5 2. f4 exf4 $1 {accepted} (2... d5 3. exd5 (3. Nf3) 3... exf4)
+3. Bc4!? Qh4+ 4. Kf1 ; rest of line comment
+b5 1-0
+
+% escaped line that is ignored
+[Event "Broken"]
+[White "A"]
+
+1. e4 e5 2. Ke3 *
+
+[Event "From a FEN"]
+[SetUp "1"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 w - - 0 40"]
+
+40. e4 Kd7 41. e5 1/2-1/2
+"#;
+
+#[test]
+fn pgn_reader_parses_variations_comments_and_errors() {
+    let games: Vec<_> = PgnReader::new(PGN_GAMES.as_bytes()).collect();
+    assert_eq!(games.len(), 3);
+
+    let first = games[0].as_ref().unwrap();
+    assert_eq!(first.tag("White"), Some("Anderssen"));
+    assert_eq!(first.tag("Opening"), Some("King's Gambit"));
+    assert_eq!(first.comment.as_deref(), Some("Opening comment"));
+    assert_eq!(first.moves.len(), 8);
+    assert_eq!(first.moves[3].nags, vec![1]);
+    assert_eq!(first.moves[3].comment.as_deref(), Some("accepted"));
+    assert_eq!(first.moves[3].variations.len(), 1);
+    assert_eq!(first.moves[3].variations[0][1].variations.len(), 1);
+    assert_eq!(first.moves[4].nags, vec![5]);
+    assert_eq!(first.moves[6].comment.as_deref(), Some("rest of line comment"));
+    assert_eq!(first.game.outcome, chess_engine::chess_game::Outcome::Finished(Some(Color::White), Termination::Adjudication));
+    assert_eq!(first.game.position.to_fen(), "rnb1kbnr/p1pp1ppp/8/1p6/2B1Pp1q/8/PPPP2PP/RNBQ1KNR w kq b6 0 5");
+
+    let broken = games[1].as_ref().unwrap_err();
+    assert_eq!(broken.game, 2);
+    assert_eq!(broken.line, 15);
+    assert!(matches!(broken.kind, PgnErrorKind::San { ply: 3, .. }));
+
+    let from_fen = games[2].as_ref().unwrap();
+    assert_eq!(from_fen.game.move_list.len(), 3);
+    assert_eq!(from_fen.game.outcome, chess_engine::chess_game::Outcome::Finished(None, Termination::Adjudication));
+
+    // a tag section without movetext is a game of its own, not the start of the next one
+    let truncated = "[Event \"Truncated\"]\n[White \"Adams\"]\n\n[Event \"Next\"]\n[White \"Short\"]\n\n1. e4 e5 *\n";
+    let games: Vec<PgnGame> = PgnReader::new(truncated.as_bytes()).map(Result::unwrap).collect();
+    assert_eq!(games.len(), 2);
+    assert_eq!((games[0].tag("White"), games[0].moves.len()), (Some("Adams"), 0));
+    assert_eq!((games[1].tag("White"), games[1].moves.len()), (Some("Short"), 2));
+}
+
+#[test]
+fn pgn_writer_round_trips() {
+    let games: Vec<PgnGame> = PgnReader::new(PGN_GAMES.as_bytes()).filter_map(Result::ok).collect();
+    for game in &games {
+        let text = game.to_string();
+        println!("{}", text);
+        let reparsed = parse_pgn(&text).unwrap();
+        assert_eq!(reparsed.moves, game.moves);
+        assert_eq!(reparsed.comment, game.comment);
+        assert_eq!(reparsed.to_string(), text);
+    }
+    assert!(games[0].to_string().replace('\n', " ").contains("2. f4 exf4 $1 {accepted} (2... d5 3. exd5 (3. Nf3) 3... exf4) 3. Bc4 $5"));
+    assert!(games[1].to_string().contains("[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 40\"]"));
+
+    let mut game = ChessGame::default();
+    for uci in ["e2e4", "e7e5", "d1h5", "b8c6", "f1c4", "g8f6", "h5f7"] {
+        game.make_move(&ChessMove::from_uci(uci).unwrap());
+    }
+    game.outcome = game.check_game_state(Ruleset::Standard, &DrawRules::FIDE);
+    assert_eq!(game.outcome, chess_engine::chess_game::Outcome::Finished(Some(Color::White), Termination::Checkmate));
+    let pgn = game.to_pgn();
+    assert!(pgn.starts_with("[Event \"?\"]\n[Site \"?\"]\n[Date \"????.??.??\"]"));
+    assert!(pgn.ends_with("1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# 1-0\n"));
+    assert!(pgn.contains("[Termination \"checkmate\"]"));
+    assert_eq!(parse_pgn(&pgn).unwrap().game.outcome, game.outcome);
+}
+
+#[test]
+fn pgn_reader_streams_zstd_files() {
+    let mut file = tempfile::Builder::new().suffix(".pgn.zst").tempfile().unwrap();
+    let compressed = zstd::encode_all(PGN_GAMES.as_bytes(), 3).unwrap();
+    std::io::Write::write_all(&mut file, &compressed).unwrap();
+
+    let results: Vec<bool> = PgnReader::open(file.path()).unwrap().map(|game| game.is_ok()).collect();
+    assert_eq!(results, vec![true, false, true]);
+}
+
+#[test]
+fn chess960_start_positions() {
+    assert_eq!(chess960_fen(518), PERFT_SUITE[0].fen);
+    assert_eq!(chess960_fen(0), "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1");
+
+    let mut back_ranks = std::collections::HashSet::new();
+    for index in 0..960 {
+        let game = ChessGame::chess960(index);
+        assert!(back_ranks.insert(game.position.to_fen()));
+    }
+}
+
+#[test]
+fn chess960_perft_and_fen() {
+    // https://www.chessprogramming.org/Chess960_Perft_Results
+    let cases: [(&str, [u64; 3]); 4] = [
+        ("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", [21, 528, 12189]),
+        ("2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9", [21, 807, 18002]),
+        ("b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9", [20, 479, 10471]),
+        ("qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9", [22, 593, 13440]),
+    ];
+    for (fen, nodes) in cases {
+        let mut position = ChessGame::from_fen(fen).unwrap().position;
+        assert!(position.chess960);
+        for (depth, expected) in nodes.iter().enumerate() {
+            assert_eq!(perft(&position, depth as u32 + 1), *expected, "{} depth {}", fen, depth + 1);
+        }
+        legal_sets_agree(&mut position, 2);
+    }
+
+    // king and rook land on each other's squares, castling is king takes rook
+    let mut game = ChessGame::from_fen("1r4kr/8/8/8/8/8/8/RK3R2 w FAhb - 0 1").unwrap();
+    assert_eq!(game.position.to_fen(), "1r4kr/8/8/8/8/8/8/RK3R2 w KQkq - 0 1");
+    assert_eq!(game.position.castling_rights.to_shredder_fen(), "FAhb");
+    let castle = game.uci_to_move("b1a1").unwrap();
+    assert_eq!(castle.to_san(&game.position), "O-O-O");
+    game.make_move(&castle);
+    assert_eq!(game.position.to_fen(), "1r4kr/8/8/8/8/8/8/2KR1R2 b kq - 1 1");
+    let castle = ChessMove::from_san("O-O", &game.position).unwrap();
+    assert_eq!(castle.to_uci(), "g8h8");
+    game.make_move(&castle);
+    assert_eq!(game.position.to_fen(), "1r3rk1/8/8/8/8/8/8/2KR1R2 w - - 2 2");
+    assert_eq!(game.position.zobrist_hash, game.position.calculate_hash());
+    game.unmake_move();
+    game.unmake_move();
+    assert_eq!(game.position.to_fen(), "1r4kr/8/8/8/8/8/8/RK3R2 w KQkq - 0 1");
+}
+
+#[test]
+fn rulesets_differ_on_moves_masks_and_outcomes() {
+    // the f1 square is covered by the bishop, castling through it is only a king capture move
+    let game = ChessGame::from_fen("4k3/8/8/8/8/8/6b1/4K2R w K - 0 1").unwrap();
+    let castle = ChessMove::from_uci("e1g1").unwrap();
+    assert!(Ruleset::KingCapture.moves(&game.position).contains(&castle));
+    assert!(!Ruleset::Standard.moves(&game.position).contains(&castle));
+    assert!(!game.position.is_legal(&castle));
+    assert!(Ruleset::KingCapture.mask(&game.position, Some(Selection::Square(ChessSquare::E1)))[ChessSquare::G1.0 as usize]);
+    assert!(!Ruleset::Standard.mask(&game.position, Some(Selection::Square(ChessSquare::E1)))[ChessSquare::G1.0 as usize]);
+
+    // stalemate ends a standard game, under king capture black has to step into the queen
+    let stalemate = ChessGame::from_fen("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1").unwrap();
+    assert_eq!(
+        Ruleset::Standard.outcome(&stalemate.position, 1, &DrawRules::FIDE),
+        chess_engine::chess_game::Outcome::Finished(None, Termination::Stalemate)
+    );
+    assert_eq!(Ruleset::KingCapture.outcome(&stalemate.position, 1, &DrawRules::FIDE), chess_engine::chess_game::Outcome::Unfinished);
+
+    let mut captured = stalemate.clone();
+    captured.make_move(&ChessMove::from_uci("a8b8").unwrap());
+    captured.make_move(&ChessMove::from_uci("c7b8").unwrap());
+    assert_eq!(
+        Ruleset::KingCapture.outcome(&captured.position, 1, &DrawRules::FIDE),
+        chess_engine::chess_game::Outcome::Finished(Some(Color::White), Termination::KingCaptured)
+    );
+}
+
+#[test]
+fn draw_rules_report_their_termination() {
+    use chess_engine::chess_game::Outcome;
+    let outcome = |fen: &str, draw_rules: &DrawRules| ChessGame::from_fen(fen).unwrap().check_game_state(Ruleset::Standard, draw_rules);
+
+    // same coloured bishops can never mate, however many there are, knights or opposite bishops can
+    assert_eq!(outcome("4k3/8/3b4/8/8/2B5/1B6/4K3 w - - 0 1", &DrawRules::FIDE), Outcome::Finished(None, Termination::InsufficientMaterial));
+    assert_eq!(outcome("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1", &DrawRules::FIDE), Outcome::Finished(None, Termination::InsufficientMaterial));
+    assert_eq!(outcome("4k3/8/8/8/8/8/8/1NB1K3 w - - 0 1", &DrawRules::FIDE), Outcome::Unfinished);
+    assert_eq!(outcome("4k1b1/8/8/8/8/8/8/2B1K3 w - - 0 1", &DrawRules::FIDE), Outcome::Unfinished);
+    assert_eq!(outcome("4kn2/8/8/8/8/8/8/1N2K3 w - - 0 1", &DrawRules::FIDE), Outcome::Unfinished);
+
+    // the 50-move rule needs a claim, the 75-move rule doesn't
+    assert_eq!(outcome("4k3/8/8/8/8/8/8/R3K3 w - - 100 80", &DrawRules::FIDE), Outcome::Finished(None, Termination::FiftyMoves));
+    assert_eq!(outcome("4k3/8/8/8/8/8/8/R3K3 w - - 100 80", &DrawRules::AUTOMATIC), Outcome::Unfinished);
+    assert_eq!(outcome("4k3/8/8/8/8/8/8/R3K3 w - - 150 80", &DrawRules::AUTOMATIC), Outcome::Finished(None, Termination::SeventyFiveMoves));
+
+    // shuffling knights repeats the start position every four plies
+    let mut game = ChessGame::default();
+    let repeats = |game: &mut ChessGame, times: usize| {
+        for _ in 0..times {
+            for uci in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+                game.make_move(&ChessMove::from_uci(uci).unwrap());
+            }
+        }
+    };
+    repeats(&mut game, 2);
+    assert_eq!(game.check_game_state(Ruleset::Standard, &DrawRules::FIDE), Outcome::Finished(None, Termination::ThreefoldRepetition));
+    assert_eq!(game.check_game_state(Ruleset::Standard, &DrawRules::AUTOMATIC), Outcome::Unfinished);
+    repeats(&mut game, 2);
+    assert_eq!(game.check_game_state(Ruleset::Standard, &DrawRules::AUTOMATIC), Outcome::Finished(None, Termination::FivefoldRepetition));
+
+    // a result set on the game, like an adjudication, wins over the board
+    game.outcome = Outcome::Finished(Some(Color::Black), Termination::Adjudication);
+    assert_eq!(game.check_game_state(Ruleset::Standard, &DrawRules::FIDE), game.outcome);
+    assert_eq!(Termination::from_name("75-move rule"), Some(Termination::SeventyFiveMoves));
+}
+
+#[test]
+fn attack_maps_checkers_pins_and_xrays() {
+    let squares = |names: &[&str]| names.iter().fold(Bitboard::EMPTY, |bb, name| bb | ChessSquare::from_name(name).unwrap().bitboard());
+
+    // the rook on a1 checks, the knight and rook shield the king from the bishop and queen
+    let position = ChessGame::from_fen("4q1k1/8/8/8/1b2R3/8/3N4/r3K3 w - - 0 1").unwrap().position;
+    let board = &position.chessboard;
+    assert_eq!(position.checkers(), squares(&["a1"]));
+    assert_eq!(board.checkers(Color::Black), Bitboard::EMPTY);
+    assert_eq!(board.attackers(ChessSquare::from_name("e4").unwrap(), Color::Black), squares(&["e8"]));
+    assert_eq!(board.attackers(ChessSquare::from_name("d1").unwrap(), Color::Black), squares(&["a1"]));
+    assert_eq!(board.attackers(ChessSquare::from_name("d1").unwrap(), Color::White), squares(&["e1"]));
+
+    let pins = board.pins(Color::White);
+    assert_eq!(pins.len(), 2);
+    let knight_pin = pins.iter().find(|pin| pin.pinned == ChessSquare::from_name("d2").unwrap()).unwrap();
+    assert_eq!(knight_pin.pinner, ChessSquare::from_name("b4").unwrap());
+    assert_eq!(knight_pin.ray, squares(&["d2", "c3", "b4"]));
+    let rook_pin = pins.iter().find(|pin| pin.pinned == ChessSquare::from_name("e4").unwrap()).unwrap();
+    assert_eq!(rook_pin.ray, squares(&["e2", "e3", "e4", "e5", "e6", "e7", "e8"]));
+    assert_eq!(board.pinned(Color::White), squares(&["d2", "e4"]));
+    assert_eq!(board.pinned(Color::Black), Bitboard::EMPTY);
+
+    // x-rays look through the first piece in the way, the checking rook already sees the king
+    assert_eq!(board.xray_attackers(ChessSquare::E1, Color::Black, board.all_pieces), squares(&["b4", "e8"]));
+
+    // a queen and rook battery
+    let battery = ChessGame::from_fen("3k4/8/8/8/8/8/3Q4/3RK3 w - - 0 1").unwrap().position.chessboard;
+    let d5 = ChessSquare::from_name("d5").unwrap();
+    assert_eq!(battery.attackers(d5, Color::White), squares(&["d2"]));
+    assert_eq!(battery.xray_attackers(d5, Color::White, battery.all_pieces), squares(&["d1"]));
+    assert_eq!(battery.attackers_to(d5, Color::White, battery.all_pieces & !squares(&["d2"])), squares(&["d1", "d2"]));
+
+    let map = ChessGame::from_fen("7k/8/8/8/8/8/P7/K6N w - - 0 1").unwrap().position.chessboard;
+    assert_eq!(map.attack_map(Color::White), squares(&["b3", "a2", "b1", "b2", "f2", "g3"]));
+    assert_eq!(map.attack_map(Color::Black), squares(&["g8", "g7", "h7"]));
+    assert_eq!(map.attacks_from(ChessSquare::from_name("a2").unwrap(), map.all_pieces), squares(&["b3"]));
+}
+
+#[test]
+fn static_exchange_evaluation() {
+    let see = |fen: &str, uci: &str| {
+        let position = ChessGame::from_fen(fen).unwrap().position;
+        position.see(&ChessMove::from_uci(uci).unwrap())
+    };
+
+    // a free pawn, and a defended pawn that costs the knight
+    assert_eq!(see("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5"), 100);
+    assert_eq!(see("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1", "d3e5"), 100 - 320);
+    // the second rook x-rays through the first and wins the exchange back
+    assert_eq!(see("3r2k1/8/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2d5"), 100);
+    assert_eq!(see("3r2k1/3r4/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2d5"), 100 - 500);
+    // quiet moves only count what they hang
+    assert_eq!(see("4k3/8/8/3p4/8/8/4R3/4K3 w - - 0 1", "e2e4"), -500);
+    assert_eq!(see("4k3/8/8/8/8/8/3R4/4K3 w - - 0 1", "d2d4"), 0);
+    assert_eq!(see("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), 100);
+    // promotions count the new piece, a recapture takes it back
+    assert_eq!(see("r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7a8Q"), 500 + 800);
+    assert_eq!(see("r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8Q"), -100);
+    // a king may only recapture when nothing else guards the square
+    assert_eq!(see("4k3/8/8/3p4/4K3/8/8/8 w - - 0 1", "e4d5"), 100);
+    assert_eq!(see("4k3/8/2p5/3p4/4K3/8/8/8 w - - 0 1", "e4d5"), 100 - 20000);
+    assert_eq!(see("8/8/8/2k5/3p4/8/8/3RK3 w - - 0 1", "d1d4"), 100 - 500);
+
+    let position = ChessGame::from_fen("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1").unwrap().position;
+    let mov = ChessMove::from_uci("d3e5").unwrap();
+    assert!(position.see_ge(&mov, -220));
+    assert!(!position.see_ge(&mov, 0));
+}
+
+#[test]
+fn syzygy_tables_are_found_by_material() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["KQvK.rtbw", "KRvKP.rtbw", "KRvKP.rtbz", "KXvK.rtbw", "notes.txt"] {
+        std::fs::write(dir.path().join(name), b"not a table").unwrap();
+    }
+    let tablebase = Tablebase::open(dir.path()).unwrap();
+    assert_eq!(tablebase.num_tables(), 2);
+    assert_eq!(tablebase.max_pieces(), 4);
+    assert!(tablebase.contains("KQvK") && tablebase.contains("KvKQ") && tablebase.contains("KPvKR"));
+    assert!(!tablebase.contains("KRvK"));
+
+    let probe = |fen: &str| tablebase.probe_wdl(&parse_fen(fen).unwrap());
+    // bare kings need no table, unreadable or missing tables and positions with castling
+    // rights or too many pieces are not covered
+    assert_eq!(probe("8/8/3k4/8/8/3K4/8/8 w - - 0 1"), Some(Wdl::Draw));
+    assert_eq!(probe("7k/8/6K1/8/8/8/8/Q7 w - - 0 1"), None);
+    assert_eq!(probe("7k/8/6K1/8/8/8/8/R7 w - - 0 1"), None);
+    assert_eq!(probe("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1"), None);
+    assert_eq!(probe("4k3/8/8/8/8/8/PP6/R3K3 w - - 0 1"), None);
+    assert_eq!(tablebase.probe_dtz(&parse_fen("8/8/3k4/8/8/3K4/8/8 b - - 0 1").unwrap()), Some(0));
+}
+
+#[test]
+fn syzygy_probes_three_piece_tables() {
+    let tablebase = Tablebase::open(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/syzygy")).unwrap();
+    assert_eq!(tablebase.num_tables(), 5);
+    let probe = |fen: &str| {
+        let position = parse_fen(fen).unwrap();
+        (tablebase.probe_wdl(&position), tablebase.probe_dtz(&position))
+    };
+
+    assert_eq!(probe("7k/8/6K1/8/8/8/8/1Q6 w - - 0 1"), (Some(Wdl::Win), Some(1)));
+    assert_eq!(probe("8/4P3/8/8/8/8/k7/4K3 w - - 0 1"), (Some(Wdl::Win), Some(1)));
+    assert_eq!(probe("8/8/8/3k4/8/8/8/3KN3 w - - 0 1"), (Some(Wdl::Draw), Some(0)));
+    assert_eq!(probe("k7/8/1K6/8/8/8/8/7Q b - - 0 1").0, Some(Wdl::Loss));
+
+    // a winning move leads to a position the opponent loses a ply sooner
+    let position = parse_fen("8/8/8/4k3/8/8/8/K6R w - - 0 1").unwrap();
+    let dtz = tablebase.probe_dtz(&position).unwrap();
+    assert!(dtz > 1);
+    assert!(position.generate_legal().iter().any(|mov| {
+        let mut next = position.clone();
+        next.make_move(mov);
+        tablebase.probe_dtz(&next) == Some(-(dtz - 1))
+    }));
+}
+
+#[test]
+fn polyglot_keys_match_the_reference_hashes() {
+    // the test positions published with the book format
+    let cases: [(&[&str], u64); 9] = [
+        (&[], 0x463b96181691fc9c),
+        (&["e2e4"], 0x823c9b50fd114196),
+        (&["e2e4", "d7d5"], 0x0756b94461c50fb0),
+        (&["e2e4", "d7d5", "e4e5"], 0x662fafb965db29d4),
+        (&["e2e4", "d7d5", "e4e5", "f7f5"], 0x22a48b5a8e47ff78),
+        (&["e2e4", "d7d5", "e4e5", "f7f5", "e1e2"], 0x652a607ca3f242c1),
+        (&["e2e4", "d7d5", "e4e5", "f7f5", "e1e2", "e8f7"], 0x00fdd303c946bdd9),
+        (&["a2a4", "b7b5", "h2h4", "b5b4", "c2c4"], 0x3c8123ea7b067637),
+        (&["a2a4", "b7b5", "h2h4", "b5b4", "c2c4", "b4c3", "a1a3"], 0x5c3f9b829b279560),
+    ];
+    for (moves, key) in cases {
+        let mut game = ChessGame::default();
+        for uci in moves {
+            game.make_move(&ChessMove::from_uci(uci).unwrap());
+        }
+        assert_eq!(PolyglotKeys::get().hash(&game.position), key, "after {:?}", moves);
+    }
+}
+
+#[test]
+fn polyglot_books_round_trip() {
+    let position = parse_fen("r3k2r/1P6/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+    for mov in position.generate_legal() {
+        assert_eq!(decode_move(&position, encode_move(&position, &mov)), Some(mov));
+    }
+    // castling is stored as the king taking its own rook, promotions by piece
+    let castle = ChessMove::from_uci("e1g1").unwrap();
+    assert_eq!(encode_move(&position, &castle), 7 | 4 << 6);
+    assert_eq!(encode_move(&position, &ChessMove::from_uci("b7a8Q").unwrap()), 7 << 3 | 1 << 6 | 6 << 9 | 4 << 12);
+
+    let start = ChessGame::default().position;
+    let mut writer = BookWriter::new(2);
+    let e4 = ChessMove::from_uci("e2e4").unwrap();
+    let d4 = ChessMove::from_uci("d2d4").unwrap();
+    writer.add_visits(&start, &[(e4, 300_000), (d4, 100_000), (ChessMove::from_uci("a2a3").unwrap(), 0)]);
+    writer.add_game(&parse_pgn("1. e4 e5 2. Nf3 Nc6 1-0").unwrap());
+
+    let book = PolyglotBook::from_bytes(&writer.to_bytes());
+    assert_eq!(book.len(), 2);
+    // weights are scaled to fit 16 bits and the best move comes first
+    assert_eq!(book.moves(&start), vec![(e4, 65535), (d4, 21844)]);
+
+    let mut after_e4 = start.clone();
+    after_e4.make_move(&e4);
+    // a lost move is left out and moves past the ply limit never enter the book
+    assert!(book.moves(&after_e4).is_empty());
+    let mut rng = XorShift64::new(7);
+    let picks: Vec<ChessMove> = (0..200).filter_map(|_| book.weighted_move(&start, &mut rng)).collect();
+    assert_eq!(picks.len(), 200);
+    assert!(picks.contains(&e4) && picks.contains(&d4));
+    assert!(picks.iter().filter(|&&mov| mov == e4).count() > 100);
+}
+
+#[test]
+fn svg_boards_draw_overlays_on_board_squares() {
+    let position = parse_fen("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3").unwrap();
+    let mut labels = NetworkLabels::default();
+    // the network sees the board from the side to move, for white that is the board itself
+    labels.policy[ChessSquare::from_name("e1").unwrap().0 as usize] = 1.0;
+    let qh4 = ChessMove::from_uci("d8h4").unwrap();
+    let svg = BoardSvg::new(&position).policy(&labels).last_move(&qh4).arrow(qh4.from, qh4.to, 1.0).render();
+
+    assert!(svg.starts_with("<svg") && svg.trim_end().ends_with("</svg>"));
+    assert_eq!(svg.matches("stroke-width=\"1\">").count(), 32);
+    // e1 is the bottom row, fifth column, and the king there is in check
+    assert!(svg.contains(r##"<rect x="196" y="331" width="45" height="45" fill="url(#check)"/>"##));
+    assert!(svg.contains(r##"<rect x="196" y="331" width="45" height="45" fill="#d62728" fill-opacity="0.700"/>"##));
+    assert!(svg.contains(r##"<rect x="151" y="16" width="45" height="45" fill="#cdd26a""##));
+    assert_eq!(svg.matches("<polygon").count(), 1);
+
+    // black's policy has its ranks mirrored, and a flipped board puts e1 at the top
+    let mut black = position.clone();
+    black.side_to_move = Color::Black;
+    let svg = BoardSvg::new(&black).policy(&labels).flipped(true).render();
+    assert!(svg.contains(r##"<rect x="151" y="331" width="45" height="45" fill="#d62728""##));
+    assert!(!svg.contains("url(#check)\"/>"));
+}
+
+#[test]
+fn epd_records_parse_opcodes_and_score_moves() {
+    let text = r#"
+# a comment line
+2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id "WAC.001";
+r1b1k2r/ppppnppp/2n2q2/2b5/3NP3/2P1B3/PP3PPP/RN1QKB1R w KQkq - am Nxc6; bm Qd2 Be2; c0 "avoid; the trade"; hmvc 3; fmvn 7;
+4k3/8/8/8/8/8/8/4K3 w - - id "no moves to check";
+"#;
+    let suite = EpdSuite::parse("wac", text).unwrap();
+    assert_eq!(suite.records.len(), 3);
+
+    let wac = &suite.records[0];
+    assert_eq!(wac.id(), Some("WAC.001"));
+    let qg6 = ChessMove::from_uci("g3g6").unwrap();
+    assert_eq!(wac.best_moves(), vec![qg6]);
+    assert_eq!(wac.solved_by(Some(&qg6)), Some(true));
+    assert_eq!(wac.solved_by(Some(&ChessMove::from_uci("e5f7").unwrap())), Some(false));
+    assert_eq!(wac.solved_by(None), Some(false));
+
+    let second = &suite.records[1];
+    assert_eq!(second.comment(0), Some("avoid; the trade"));
+    assert_eq!((second.position.halfmove_clock, second.position.fullmove_counter), (3, 7));
+    assert_eq!(second.operands("bm").unwrap(), ["Qd2", "Be2"]);
+    assert_eq!(second.solved_by(Some(&ChessMove::from_uci("d4c6").unwrap())), Some(false));
+    assert_eq!(second.solved_by(Some(&ChessMove::from_uci("f1e2").unwrap())), Some(true));
+    assert_eq!(suite.records[2].solved_by(None), None);
+
+    let again = EpdRecord::parse(&second.to_epd()).unwrap();
+    assert_eq!(again.operations, second.operations);
+    assert_eq!(again.position.to_fen(), second.position.to_fen());
+
+    // errors carry the line of the broken record
+    let err = EpdSuite::parse("bad", "\n4k3/8/8/8/8/8/8/4K3 w - - bm Qh5;").unwrap_err();
+    assert_eq!(err.line, 2);
+    assert!(matches!(err.kind, EpdErrorKind::InvalidMove { ref opcode, .. } if opcode == "bm"));
+    assert_eq!(EpdRecord::parse("4k3/8/8/8/8/8/8/4K3 w - - id \"open").unwrap_err(), EpdErrorKind::UnterminatedString);
+
+    let result = |id: &str, solved| EpdResult {
+        id: id.to_string(),
+        fen: wac.position.to_fen(),
+        played: Some("Qg6".to_string()),
+        best: vec!["Qg6".to_string()],
+        avoid: Vec::new(),
+        solved,
+        nodes: 100,
+        millis: 12,
+    };
+    let report = SuiteReport { suite: "wac".to_string(), results: vec![result("a, b", Some(true)), result("c", Some(false)), result("d", None)] };
+    assert_eq!(report.to_string(), "wac: 1/2 solved (50.0%)");
+    let csv = SuiteReport::to_csv(std::slice::from_ref(&report));
+    assert_eq!(csv.lines().count(), 4);
+    assert!(csv.lines().nth(1).unwrap().starts_with("wac,\"a, b\",2rr3k/"));
+    assert!(csv.lines().nth(1).unwrap().ends_with(",Qg6,Qg6,,true,100,12"));
+    let json: serde_json::Value = serde_json::from_str(&SuiteReport::to_json(&[report])).unwrap();
+    assert_eq!(json[0]["results"][2]["solved"], serde_json::Value::Null);
+    assert_eq!(json[0]["results"][0]["played"], "Qg6");
+}
+
+#[test]
+fn mirrored_samples_match_mirrored_positions() {
+    // the same position with files a and h swapped, ranks reversed character by character
+    fn mirror_fen(fen: &str) -> String {
+        let fields: Vec<&str> = fen.split(' ').collect();
+        let board: Vec<String> = fields[0].split('/').map(|rank| rank.chars().rev().collect()).collect();
+        let ep = match fields[3].as_bytes() {
+            [file, rank] => format!("{}{}", (b'h' - (file - b'a')) as char, *rank as char),
+            _ => "-".to_string(),
+        };
+        format!("{} {} {} {} {} {}", board.join("/"), fields[1], fields[2], ep, fields[4], fields[5])
+    }
+
+    fn sample(fen: &str, selected: Option<&str>) -> TrainingSample {
+        let position = parse_fen(fen).unwrap();
+        let selected = selected.map(|name| Selection::Square(ChessSquare::from_name(name).unwrap()));
+        let mut mask = Ruleset::Standard.mask(&position, selected);
+        let mut policy = [0.0; POLICY_SIZE];
+        for (sq, legal) in mask.iter().enumerate() {
+            if *legal {
+                policy[sq] = (sq + 1) as f32;
+            }
+        }
+        // policies and masks are seen from the side to move like the inputs
+        if position.side_to_move == Color::Black {
+            mask = flip_ranks(mask);
+            policy = flip_ranks(policy);
+        }
+        TrainingSample { inputs: NetworkInputs::from_position(&position, selected), targets: NetworkLabels { policy, value: [0.5, 0.3, 0.2] }, mask }
+    }
+
+    let cases = [
+        ("4k3/1p4p1/8/2P5/8/8/5PPP/1N2K2R w - - 3 40", None),
+        ("r3k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1", Some("d4")),
+        ("8/2P5/4k3/8/8/3K4/8/8 w - - 0 1", Some("c7")),
+    ];
+    for (fen, selected) in cases {
+        let mirrored = sample(fen, selected).mirrored().expect("no castling rights to break the symmetry");
+        let mirrored_selected = selected.map(|name| ChessSquare::from_name(name).unwrap().0 ^ 7).map(|sq| ChessSquare::new(sq).unwrap().to_name());
+        let expected = sample(&mirror_fen(fen), mirrored_selected.as_deref());
+
+        assert_eq!(mirrored.inputs.boards, expected.inputs.boards, "{}", fen);
+        assert_eq!(mirrored.inputs.meta, expected.inputs.meta);
+        assert_eq!(mirrored.inputs.to_string(), expected.inputs.to_string());
+        assert_eq!(mirrored.mask, expected.mask);
+        assert_eq!(mirrored.targets.value, expected.targets.value);
+        // the policy follows its squares, not the move generator order
+        let original = sample(fen, selected);
+        for sq in 0..64 {
+            assert_eq!(mirrored.targets.policy[sq ^ 7], original.targets.policy[sq]);
+        }
+        assert_eq!(mirrored.mirrored().unwrap().inputs.boards, original.inputs.boards);
+    }
+
+    assert!(sample("4k3/8/8/8/8/8/8/4K2R w K - 0 1", None).mirrored().is_none());
+}
+
+#[test]
+fn replay_samples_rebuild_their_inputs() {
+    let targets = NetworkLabels { policy: [1.0 / POLICY_SIZE as f32; POLICY_SIZE], value: [0.5, 0.3, 0.2] };
+    let mut horde = Variant::Horde.start_position();
+    horde.make_move(&ChessMove::from_uci("e4e5").unwrap());
+    let cases = [
+        (parse_fen("r3k3/8/8/8/3pP3/8/8/4K3 b q e3 7 1").unwrap(), Some(Selection::Square(ChessSquare::D4))),
+        (parse_variant_fen("4k3/8/8/8/8/8/8/q~2RK3[n] b - - 0 1", Variant::Crazyhouse).unwrap(), Some(Selection::Pocket(PieceType::Knight))),
+        (horde, None),
+    ];
+    for (position, selection) in &cases {
+        let mask = Ruleset::Standard.mask(position, *selection);
+        let sample = ReplaySample::new(position, *selection, targets, mask);
+        // more than 32 pieces don't pack, those samples keep their inputs
+        assert_eq!(matches!(sample.position, ReplayPosition::Inputs(_)), position.variant == Variant::Horde);
+
+        let rebuilt = sample.to_sample();
+        let inputs = NetworkInputs::from_position(position, *selection);
+        assert_eq!(rebuilt.inputs.boards, inputs.boards, "{}", position.to_fen());
+        assert_eq!(rebuilt.inputs.meta, inputs.meta);
+        assert_eq!(rebuilt.targets.policy, targets.policy);
+        assert_eq!(rebuilt.targets.value, targets.value);
+        assert_eq!(rebuilt.mask, mask);
+    }
+    assert!(std::mem::size_of::<ReplaySample>() * 8 < std::mem::size_of::<TrainingSample>());
+}
+
+#[test]
+fn packed_positions_round_trip() {
+    let mut fens: Vec<String> = PERFT_SUITE.iter().map(|case| case.fen.to_string()).collect();
+    fens.extend([0, 518, 959].map(chess960_fen));
+    fens.push("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3".to_string());
+    fens.push("1r2k1r1/8/8/8/8/8/8/R2K3R w HAg - 137 2000".to_string());
+
+    for fen in &fens {
+        let position = parse_fen(fen).unwrap();
+        let packed = PackedPosition::new(&position).unwrap();
+        let unpacked = packed.unpack().unwrap();
+        assert_eq!(unpacked.to_fen(), position.to_fen(), "{}", fen);
+        assert_eq!(unpacked.castling_rights, position.castling_rights);
+        assert_eq!(unpacked.chess960, position.chess960);
+        assert_eq!(unpacked.zobrist_hash, position.zobrist_hash);
+        assert_eq!(packed.network_inputs(None).unwrap().boards, NetworkInputs::new(&position).boards);
+    }
+    // the classical setup played as Chess960 keeps its castling encoding
+    assert!(PackedPosition::new(&ChessGame::chess960(518).position).unwrap().unpack().unwrap().chess960);
+    assert_eq!(std::mem::size_of::<PackedPosition>(), 32);
+
+    let mut broken = PackedPosition::new(&ChessGame::default().position).unwrap();
+    broken.0[8] = 0xFF;
+    assert_eq!(broken.unpack().unwrap_err(), PackError::InvalidPiece(15));
+}
+
+#[test]
+fn packed_games_round_trip() {
+    let mut rng = XorShift64::new(19);
+    let mut logged = Vec::new();
+    for index in [518, 77] {
+        let mut game = if index == 518 { ChessGame::default() } else { ChessGame::chess960(index) };
+        while game.move_list.len() < 300 {
+            let moves = game.position.generate_legal();
+            if moves.is_empty() {
+                break;
+            }
+            game.make_move(&moves[(rng.next() % moves.len() as u64) as usize]);
+        }
+        game.outcome = Outcome::Finished(Some(Color::Black), Termination::Adjudication);
+
+        let packed = PackedGame::new(&game).unwrap();
+        let bytes = packed.to_bytes();
+        assert_eq!(bytes.len(), 36 + 2 * game.move_list.len());
+        let (decoded, len) = PackedGame::from_bytes(&bytes).unwrap();
+        assert_eq!((&decoded, len), (&packed, bytes.len()));
+
+        let replayed = decoded.unpack().unwrap();
+        assert_eq!(replayed.move_list, game.move_list);
+        assert_eq!(replayed.position.to_fen(), game.position.to_fen());
+        assert_eq!(replayed.outcome, game.outcome);
+        logged.push(packed);
+    }
+
+    let file = tempfile::NamedTempFile::new().unwrap();
+    logged.iter().for_each(|game| game.append(file.path()).unwrap());
+    assert_eq!(PackedGame::read_all(file.path()).unwrap(), logged);
+
+    let bytes = logged[0].to_bytes();
+    assert!(matches!(PackedGame::from_bytes(&bytes[..bytes.len() - 1]), Err(PackError::Truncated { .. })));
+    let mut illegal = logged[0].clone();
+    illegal.moves[0] = illegal.moves[1];
+    assert!(matches!(illegal.unpack(), Err(PackError::IllegalMove { ply: 0, .. })));
+    // a raw value that isn't a PackedMove at all
+    illegal.moves[0] = 7 << 12;
+    assert_eq!(illegal.unpack().unwrap_err(), PackError::IllegalMove { ply: 0, raw: 7 << 12 });
+}
+
+#[test]
+fn packed_moves_round_trip_through_uci() {
+    assert_eq!(std::mem::size_of::<PackedMove>(), 2);
+
+    let fens = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 3",
+        "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        "1r2k1r1/8/8/8/8/8/8/1R2K1R1 w GBgb - 0 1",
+    ];
+    for fen in fens {
+        let position = parse_fen(fen).unwrap();
+        for &mov in &position.pseudolegal_moves {
+            assert_eq!(PackedMove::from_uci(&mov.to_uci(), &position), Ok(mov), "{} in {}", mov.to_uci(), fen);
+            assert_eq!(PackedMove::from_move(&mov.into(), &position), mov, "{} in {}", mov.to_uci(), fen);
+            assert_eq!(PackedMove::from_raw(mov.raw()), Some(mov));
+        }
+    }
+
+    let position = parse_fen(fens[1]).unwrap();
+    let ep = PackedMove::from_uci("d4e3", &position).unwrap();
+    assert_eq!(ep.kind(), MoveKind::EnPassant);
+    assert!(ep.is_capture());
+    let promotion = PackedMove::from_uci("g2h1q", &parse_fen(fens[2]).unwrap()).unwrap();
+    assert_eq!(promotion.kind(), MoveKind::PromotionCapture(PieceType::Queen));
+    assert_eq!(promotion.to_uci(), "g2h1q");
+    let castle = PackedMove::from_uci("e1g1", &parse_fen(fens[3]).unwrap()).unwrap();
+    assert_eq!(castle.kind(), MoveKind::KingsideCastle);
+    assert_eq!(PackedMove::from_raw(7 << 12), None);
+
+    let mut position = parse_fen(fens[0]).unwrap();
+    let before = position.clone();
+    for mov in before.pseudolegal_moves.iter().copied().filter(|&mov| before.is_legal_packed(mov)) {
+        let undo = position.make_packed(mov);
+        position.unmake_packed(&undo);
+        assert_eq!(position.to_fen(), before.to_fen());
+        assert_eq!(position.zobrist_hash, before.zobrist_hash);
+    }
+}
+
+#[test]
+fn hash_history_matches_a_full_scan() {
+    assert_eq!(HashHistory::new(&ChessGame::default().position).repetitions(), 1);
+    assert_eq!(HashHistory::default().repetitions(), 0);
+
+    let mut rng = XorShift64::new(21);
+    for _ in 0..4 {
+        let mut game = ChessGame::default();
+        let mut hashes = vec![game.position.zobrist_hash];
+        for _ in 0..200 {
+            // mostly king and knight moves so positions come back
+            let moves = game.position.generate_legal();
+            let quiet: Vec<ChessMove> = moves.iter().copied().filter(|mov| game.position.chessboard.get_piece_at(mov.to).is_none()).collect();
+            let pool = if quiet.is_empty() { moves.to_vec() } else { quiet };
+            if pool.is_empty() {
+                break;
+            }
+            let mov = pool[(rng.next() % pool.len() as u64) as usize];
+
+            let mut next = game.position.clone();
+            next.make_move(&mov);
+            let expected = 1 + hashes.iter().filter(|&&hash| hash == next.zobrist_hash).count();
+            assert_eq!(game.history.repetitions_of(&next), expected);
+
+            game.make_move(&mov);
+            hashes.push(game.position.zobrist_hash);
+            assert_eq!(game.history.repetitions(), expected);
+            assert_eq!(game.history.len(), hashes.len());
+        }
+
+        while !game.move_list.is_empty() {
+            game.unmake_move();
+            hashes.pop();
+            assert_eq!(game.history.hashes().collect::<Vec<_>>(), hashes);
+        }
+    }
+}
+
+// a reference that never sees black's moves to the h-file
+fn blind_reference(position: &ChessPosition, depth: u32) -> Divide {
+    let moves = position.generate_legal().into_iter().filter(|mov| position.side_to_move == Color::White || mov.to.file() != 7);
+    Divide {
+        moves: moves
+            .map(|mov| {
+                let mut child = position.clone();
+                child.make_move(&mov);
+                (mov, if depth == 1 { 1 } else { blind_reference(&child, depth - 1).total() })
+            })
+            .collect(),
+    }
+}
+
+#[test]
+fn stockfish_perft_output_is_read_as_a_divide() {
+    // the banner and info strings come before the moves and aren't read as moves even when they
+    // look like one, anything after the total is left unread
+    let output = "Stockfish 17 by the Stockfish developers (see AUTHORS file)
+info string Using 1 thread
+info string: 1
+info: 1
+aé1e: 1
+b7b8q: 3
+b7b8r: 3
+b7b8b: 5
+b7b8n: 4
+a1a2: 5
+a1a3: 5
+a1a4: 5
+a1a5: 5
+a1a6: 5
+a1a7: 5
+a1a8: 3
+a1b1: 5
+a1c1: 5
+a1d1: 3
+e1d1: 5
+e1d2: 5
+e1e2: 5
+e1f1: 5
+e1f2: 5
+e1c1: 3
+
+Nodes searched: 89
+
+readyok
+";
+    let mut reader = output.as_bytes();
+    let theirs = Divide::read(&mut reader).unwrap();
+    assert_eq!(reader, b"\nreadyok\n");
+    assert_eq!(theirs.total(), 89);
+    assert!(ChessMove::from_uci("info").is_err());
+    assert!(ChessMove::from_uci("aé1e").is_err());
+
+    let ours = divide(&parse_fen("4k3/1P6/8/8/8/8/8/R3K3 w Q - 0 1").unwrap(), 2, false);
+    let sorted = |divide: &Divide| {
+        let mut moves: Vec<(String, u64)> = divide.moves.iter().map(|(mov, nodes)| (mov.to_uci(), *nodes)).collect();
+        moves.sort();
+        moves
+    };
+    assert_eq!(sorted(&theirs), sorted(&ours));
+}
+
+#[test]
+fn fuzzing_finds_and_shrinks_move_generation_differences() {
+    let config = FuzzConfig { games: 2, max_plies: 40, sample_every: 5, depth: 2, ..FuzzConfig::default() };
+    let stats = fuzz_movegen(&config, |position, depth| divide(position, depth, false)).unwrap();
+    assert_eq!(stats.games, 2);
+    assert!(stats.positions >= 2);
+    let chess960 = FuzzConfig { chess960: true, seed: 7, ..config };
+    assert!(fuzz_movegen(&chess960, |position, depth| divide(position, depth, false)).is_ok());
+
+    // the start position only differs at depth 2, shrinking walks down to black's reply and starts there
+    let failure = fuzz_movegen(&config, blind_reference).unwrap_err();
+    assert_eq!(failure.case.depth, 1);
+    assert!(failure.case.moves.is_empty());
+    let position = failure.case.position().unwrap();
+    assert_eq!(position.side_to_move, Color::Black);
+    let FuzzFailureKind::Perft(diffs) = &failure.kind else {
+        panic!("expected a perft difference, got {}", failure)
+    };
+    assert!(diffs.iter().all(|diff| diff.ours == Some(1) && diff.reference.is_none() && diff.mov.to.file() == 7));
+    assert!(failure.to_string().contains(&format!("position fen {}\ngo perft 1", position.to_fen())));
+
+    // pseudolegal games end exactly when a king is taken
+    let king_capture = FuzzConfig { games: 6, max_plies: 200, ruleset: Ruleset::KingCapture, seed: 3, ..config };
+    let stats = fuzz_movegen(&king_capture, |position, depth| divide(position, depth, false)).unwrap();
+    assert_eq!(stats.games, 6);
+}
+
+#[test]
+fn position_builder_sets_up_and_checks_positions() {
+    let piece = |color: Color, piece_type: PieceType| ChessPiece::new(color, piece_type);
+    let kings = PositionBuilder::new()
+        .piece(piece(Color::White, PieceType::King), ChessSquare::E1)
+        .piece(piece(Color::Black, PieceType::King), ChessSquare::E8);
+
+    // the same position as its FEN, hash and moves included
+    let position = kings
+        .clone()
+        .piece(piece(Color::White, PieceType::Rook), ChessSquare::H1)
+        .piece(piece(Color::Black, PieceType::Pawn), ChessSquare::D4)
+        .piece(piece(Color::White, PieceType::Pawn), ChessSquare::E4)
+        .side_to_move(Color::Black)
+        .castling("K")
+        .unwrap()
+        .en_passant(Some(ChessSquare::E3))
+        .fullmove_counter(30)
+        .build()
+        .unwrap();
+    let parsed = parse_fen("4k3/8/8/8/3pP3/8/8/4K2R b K e3 0 30").unwrap();
+    assert_eq!(position.to_fen(), parsed.to_fen());
+    assert_eq!(position.zobrist_hash, parsed.zobrist_hash);
+    assert_eq!(position.pseudolegal_moves, parsed.pseudolegal_moves);
+
+    // editing replaces and removes pieces, and castling with the king off e1 makes it Chess960
+    let edited = PositionBuilder::from_position(&parse_fen("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1").unwrap())
+        .piece(piece(Color::White, PieceType::Knight), ChessSquare::A1)
+        .remove(ChessSquare::E1)
+        .piece(piece(Color::White, PieceType::King), ChessSquare::F1)
+        .castling("K")
+        .unwrap();
+    assert_eq!(edited.get(ChessSquare::A1), Some(piece(Color::White, PieceType::Knight)));
+    assert_eq!(edited.get(ChessSquare::E1), None);
+    assert!(edited.build().unwrap().chess960);
+    assert!(PositionBuilder::from_board(edited.build().unwrap().chessboard).castling("K").unwrap().build().unwrap().chess960);
+    assert!(!PositionBuilder::from_position(&edited.build().unwrap()).chess960(false).build().unwrap().chess960);
+
+    assert_eq!(PositionBuilder::new().build().unwrap_err(), FenError::KingCount { color: Color::White, count: 0 });
+    assert_eq!(
+        kings.clone().piece(piece(Color::Black, PieceType::Pawn), ChessSquare::A1).build().unwrap_err(),
+        FenError::PawnOnBackRank { square: ChessSquare::A1 }
+    );
+    assert_eq!(
+        kings.clone().piece(piece(Color::White, PieceType::Rook), ChessSquare::E5).build().unwrap_err(),
+        FenError::OpponentInCheck { color: Color::Black }
+    );
+    assert_eq!(
+        kings.clone().castling_rights(CastlingRights::new()).build().unwrap_err(),
+        FenError::CastlingRightWithoutPieces { square: ChessSquare::H1 }
+    );
+    assert_eq!(kings.clone().castling("K").unwrap_err(), FenError::CastlingWithoutPieces { index: 0, found: 'K' });
+    assert_eq!(kings.en_passant(Some(ChessSquare::D6)).build().unwrap_err(), FenError::EnPassantWithoutPawn { square: ChessSquare::D6 });
+}
+
+#[test]
+fn variants_follow_their_own_rules() {
+    let play = |position: &mut ChessPosition, uci: &str| {
+        position.make_move(&ChessMove::from_uci(uci).unwrap());
+    };
+    let outcome = |position: &ChessPosition| Ruleset::Standard.outcome(position, 1, &DrawRules::FIDE);
+
+    let mut hill = parse_variant_fen("4k3/8/8/8/8/3K4/8/8 w - - 0 1", Variant::KingOfTheHill).unwrap();
+    play(&mut hill, "d3d4");
+    assert_eq!(outcome(&hill), Outcome::Finished(Some(Color::White), Termination::KingInCenter));
+
+    // the remaining checks follow the en passant square, the third check wins
+    let mut three_check = parse_variant_fen("4k3/8/8/8/8/8/8/4K2R w - - 1+3 0 1", Variant::ThreeCheck).unwrap();
+    assert_eq!(three_check.checks, [2, 0]);
+    assert_eq!(outcome(&three_check), Outcome::Unfinished);
+    play(&mut three_check, "h1h8");
+    assert_eq!(three_check.to_fen(), "4k2R/8/8/8/8/8/8/4K3 b - - 0+3 1 1");
+    assert_eq!(three_check.zobrist_hash, three_check.calculate_hash());
+    assert_eq!(outcome(&three_check), Outcome::Finished(Some(Color::White), Termination::ThreeChecks));
+    assert_eq!(PackedPosition::new(&three_check).unwrap().unpack().unwrap().to_fen(), three_check.to_fen());
+    let meta = NetworkInputs::new(&three_check).meta;
+    assert_eq!(meta[5..7], [0.0, 1.0]);
+
+    // captures are forced, pawns promote to kings and running out of pieces wins
+    let mut antichess = parse_variant_fen("8/8/8/8/8/8/1p6/B7 w - - 0 1", Variant::Antichess).unwrap();
+    assert_eq!(antichess.generate_legal().iter().map(ChessMove::to_uci).collect::<Vec<_>>(), ["a1b2"]);
+    play(&mut antichess, "a1b2");
+    assert_eq!(outcome(&antichess), Outcome::Finished(Some(Color::Black), Termination::OutOfPieces));
+    let promotion = parse_variant_fen("8/4P3/8/8/8/8/8/7k w - - 0 1", Variant::Antichess).unwrap();
+    assert!(promotion.generate_legal().iter().any(|mov| mov.to_uci() == "e7e8k"));
+
+    // the horde's pawns on the first rank may move two squares, black wins by taking all of them
+    let horde = Variant::Horde.start_position();
+    assert_eq!(horde.to_fen(), Variant::Horde.start_fen());
+    assert_eq!(PackedPosition::new(&horde).unwrap().unpack().unwrap().to_fen(), horde.to_fen());
+    let mut advanced = horde.clone();
+    play(&mut advanced, "e4e5");
+    assert_eq!(PackedPosition::new(&advanced), Err(PackError::TooManyPieces(52)));
+    let first_rank = parse_variant_fen("4k3/8/8/8/8/8/8/P7 w - - 0 1", Variant::Horde).unwrap();
+    assert!(first_rank.generate_legal().iter().any(|mov| mov.to_uci() == "a1a3"));
+    let no_horde = parse_variant_fen("4k3/8/8/8/8/8/8/8 w - - 0 1", Variant::Horde).unwrap();
+    assert_eq!(outcome(&no_horde), Outcome::Finished(Some(Color::Black), Termination::OutOfPieces));
+
+    // games carry the variant through PGN
+    assert_eq!(Variant::from_name("three-check"), Some(Variant::ThreeCheck));
+    let mut game = ChessGame::from_variant(Variant::ThreeCheck);
+    for uci in ["e2e4", "f7f6", "d1h5"] {
+        game.make_move(&ChessMove::from_uci(uci).unwrap());
+    }
+    let text = PgnGame::from_game(&game).to_string();
+    assert!(text.contains("[Variant \"Three-check\"]"));
+    assert_eq!(parse_pgn(&text).unwrap().game.position.to_fen(), game.position.to_fen());
+}
+
+#[test]
+fn crazyhouse_captures_go_to_the_pocket_and_drop_back() {
+    let sq = |name: &str| ChessSquare::from_name(name).unwrap();
+
+    let start = Variant::Crazyhouse.start_position();
+    assert_eq!(start.to_fen(), Variant::Crazyhouse.start_fen());
+
+    // a promoted queen goes back to the pocket as a pawn
+    let mut position = parse_variant_fen("4k3/8/8/8/8/8/8/q~2RK3[n] w - - 0 1", Variant::Crazyhouse).unwrap();
+    assert_eq!(position.to_fen(), "4k3/8/8/8/8/8/8/q~2RK3[n] w - - 0 1");
+    let before = position.clone();
+    let undo = position.make_move(&ChessMove::from_uci("d1a1").unwrap());
+    assert_eq!(position.to_fen(), "4k3/8/8/8/8/8/8/R3K3[Pn] b - - 0 1");
+    assert_eq!(position.zobrist_hash, position.calculate_hash());
+    position.unmake_move(&ChessMove::from_uci("d1a1").unwrap(), &undo);
+    assert_eq!(position.to_fen(), before.to_fen());
+    assert_eq!(position.zobrist_hash, before.zobrist_hash);
+
+    // drops are written with an @ and can block a check
+    let position = parse_variant_fen("4k3/8/8/8/8/8/8/r3K3[N] w - - 0 1", Variant::Crazyhouse).unwrap();
+    let drop = ChessMove::from_uci("N@c1").unwrap();
+    assert_eq!(drop, ChessMove::drop(PieceType::Knight, sq("c1")));
+    assert_eq!(drop.to_uci(), "N@c1");
+    assert_eq!(drop.to_san(&position), "N@c1");
+    assert_eq!(ChessMove::from_san("N@c1", &position).unwrap(), drop);
+    let mut blocks: Vec<ChessSquare> = position.generate_legal().iter().filter(|mov| mov.dropped().is_some()).map(|mov| mov.to).collect();
+    blocks.sort_by_key(|sq| sq.0);
+    assert_eq!(blocks, [sq("b1"), sq("c1"), sq("d1")]);
+    assert_eq!(PackedMove::from_raw(PackedMove::drop(PieceType::Knight, sq("c1")).raw()).map(ChessMove::from), Some(drop));
+
+    // a full pocket over an empty board drops well past 256 moves, pawns stay off the back ranks
+    let full_pocket = parse_variant_fen("k7/8/8/8/8/8/8/7K[PNBRQ] w - - 0 1", Variant::Crazyhouse).unwrap();
+    let legal = full_pocket.generate_legal();
+    assert_eq!(legal.iter().filter(|mov| mov.dropped().is_some()).count(), 4 * 62 + 48);
+    assert_eq!(legal.len(), 296 + 3);
+    assert_eq!(perft(&full_pocket, 1), 299);
+    assert_eq!(full_pocket.pseudolegal_moves.len(), 299);
+    // the drops past 256 spill onto the heap instead of growing every position's move list
+    assert!(std::mem::size_of::<ChessPosition>() <= 768);
+
+    // the piece is picked from a pocket square of the policy, then the square to drop it on
+    let mask = Ruleset::Standard.mask(&position, None);
+    assert!(mask[Selection::Pocket(PieceType::Knight).index()]);
+    assert!(!mask[Selection::Pocket(PieceType::Pawn).index()]);
+    let drops = Ruleset::Standard.mask(&position, Some(Selection::Pocket(PieceType::Knight)));
+    assert_eq!(drops.iter().filter(|&&legal| legal).count(), 3);
+
+    let packed = PackedPosition::new(&position).unwrap().unpack().unwrap();
+    assert_eq!(packed.to_fen(), position.to_fen());
+    assert_eq!(packed.zobrist_hash, position.zobrist_hash);
+
+    // promoted pieces keep their ~ through packing, past the 16th piece they don't fit
+    let promoted = parse_variant_fen("r3k2r/8/8/3Q~4/8/5n~2/8/R3K2R[Bp] w KQkq - 0 12", Variant::Crazyhouse).unwrap();
+    let packed = PackedPosition::new(&promoted).unwrap().unpack().unwrap();
+    assert_eq!(packed.to_fen(), promoted.to_fen());
+    assert_eq!(packed.promoted, promoted.promoted);
+    assert_eq!(packed.zobrist_hash, promoted.zobrist_hash);
+    let crowded = parse_variant_fen("rnbqkbnr~/8/8/8/8/8/QQQ5/RNBQKBNR w - - 0 1", Variant::Crazyhouse).unwrap();
+    assert_eq!(PackedPosition::new(&crowded), Err(PackError::PromotedPiece(ChessSquare::H8.0)));
+
+    let mut game = ChessGame::from_variant(Variant::Crazyhouse);
+    for uci in ["e2e4", "d7d5", "e4d5", "d8d5", "b1c3", "d5a5", "P@e4"] {
+        game.make_move(&ChessMove::from_uci(uci).unwrap());
+    }
+    assert_eq!(game.position.to_fen(), "rnb1kbnr/ppp1pppp/8/q7/4P3/2N5/PPPP1PPP/R1BQKBNR[p] b KQkq - 0 4");
+}